@@ -5,6 +5,13 @@ fn main() {
         required -i, --input input : PathBuf
         /// run rustfmt on the code
         optional --fmt
+        /// emit todo!() with the python code as a comment for unsupported statements
+        /// and report all problems
+        optional --best-effort
+        /// translate exceptions into `Result`s with a generated `Error` enum instead of panics
+        optional --result-errors
+        /// async runtime which asyncio maps to: tokio (default) or async-std
+        optional --runtime runtime : String
         /// output .rs filepath (defaults to input path with .rs extension)
         optional -o, --output output : PathBuf
         // TODO 1
@@ -15,15 +22,55 @@ fn main() {
         // add option to print result to stdout/ make it the default?
     };
 
-    let prg = p2r::p2r(
-        &std::fs::read_to_string(&flags.input).unwrap(),
-        &mut p2r::Ctx::default(),
-    )
-    .unwrap();
+    let runtime = match flags
+        .runtime
+        .as_deref()
+        .map(str::parse::<p2r::Runtime>)
+        .transpose()
+    {
+        Ok(runtime) => runtime.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+
+    let code = std::fs::read_to_string(&flags.input).unwrap();
+    let mut ctx = p2r::Ctx {
+        result_errors: flags.result_errors,
+        runtime,
+        ..Default::default()
+    };
+    let prg = if flags.best_effort {
+        match p2r::transpile_module(&code, &mut ctx) {
+            Ok(transpiled) => {
+                for d in &transpiled.diagnostics {
+                    eprintln!("{}", d.report(&code));
+                }
+                transpiled.output
+            }
+            Err(e) => {
+                eprintln!("python parse error: {e}");
+                std::process::exit(1);
+            }
+        }
+    } else {
+        match p2r::p2r_module(&code, &mut ctx) {
+            Ok(prg) => prg,
+            Err(p2r::ParseError::TranspileError(e)) => {
+                eprintln!("{}", e.report(&code));
+                std::process::exit(1);
+            }
+            Err(p2r::ParseError::ParseError(e)) => {
+                eprintln!("python parse error: {e}");
+                std::process::exit(1);
+            }
+        }
+    };
 
     std::fs::write(
         flags.output.unwrap_or(flags.input.with_extension("rs")),
-        p2r::fmt(&format!("fn main(){{{prg}}}")),
+        p2r::fmt(&prg),
     )
     .unwrap();
 
@@ -0,0 +1,147 @@
+//! `async def`, `await` and `async for`, the functions of `asyncio` map to the configured
+//! runtime
+
+use crate::infer::is_generator;
+use crate::{
+    awaited, block_expr, call, cast, r_body_binding, r_e, r_fn, r_target, stmt, todo_link, Ctx,
+    TResult, TranspileError,
+};
+use rustpython_parser::ast::{Expr, ExprCall, StmtAsyncFor, StmtAsyncFunctionDef, StmtFunctionDef};
+use syn::parse_quote;
+
+/// async runtime which `asyncio.run`, `asyncio.gather` and `asyncio.sleep` map to
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Runtime {
+    #[default]
+    Tokio,
+    AsyncStd,
+}
+
+impl std::str::FromStr for Runtime {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tokio" => Ok(Runtime::Tokio),
+            "async-std" => Ok(Runtime::AsyncStd),
+            _ => Err(format!("unknown runtime {s}, expected tokio or async-std")),
+        }
+    }
+}
+
+/// `async def` as a `def`, the translated function is made `async`
+pub fn sync_def(def: &StmtAsyncFunctionDef) -> StmtFunctionDef {
+    StmtFunctionDef {
+        range: def.range,
+        name: def.name.clone(),
+        args: def.args.clone(),
+        body: def.body.clone(),
+        decorator_list: def.decorator_list.clone(),
+        returns: def.returns.clone(),
+        type_comment: def.type_comment.clone(),
+        type_params: def.type_params.clone(),
+    }
+}
+
+/// `async def` -> `async fn`
+pub fn r_async_fn(def: &StmtAsyncFunctionDef, ctx: &mut Ctx) -> TResult<syn::ItemFn> {
+    if is_generator(&def.body) {
+        return Err(todo_link!("async generators can't be translated"));
+    }
+    let mut f = r_fn(&sync_def(def), ctx)?;
+    f.sig.asyncness = Some(Default::default());
+    Ok(f)
+}
+
+/// `await value`, `await asyncio.gather(a, b)` joins the futures
+pub fn r_await(value: &Expr, ctx: &mut Ctx) -> TResult<syn::Expr> {
+    if let Expr::Call(ExprCall { func, args, .. }) = value
+        && asyncio_function(func) == Some("gather")
+    {
+        if let [Expr::Starred(s)] = args.as_slice() {
+            let futures = r_e(&s.value, ctx)?;
+            return Ok(awaited(call(
+                parse_quote!(futures::future::join_all),
+                [futures],
+            )));
+        }
+        let futures = args
+            .iter()
+            .map(|a| r_e(a, ctx))
+            .collect::<TResult<Vec<_>>>()?;
+        return Ok(match ctx.runtime {
+            Runtime::Tokio => parse_quote!(tokio::join!(#(#futures),*)),
+            Runtime::AsyncStd => parse_quote!(futures::join!(#(#futures),*)),
+        });
+    }
+    Ok(awaited(r_e(value, ctx)?))
+}
+
+/// `asyncio.f(args)`
+pub fn r_asyncio_call(name: &str, args: Vec<syn::Expr>, ctx: &mut Ctx) -> TResult<syn::Expr> {
+    let [arg] = args.as_slice() else {
+        return Err(todo_link!());
+    };
+    Ok(match (name, ctx.runtime) {
+        ("run", Runtime::Tokio) => {
+            parse_quote!(tokio::runtime::Runtime::new().unwrap().block_on(#arg))
+        }
+        ("run", Runtime::AsyncStd) => parse_quote!(async_std::task::block_on(#arg)),
+        ("sleep", runtime) => {
+            let duration = call(
+                parse_quote!(std::time::Duration::from_secs_f64),
+                [cast(arg.clone(), parse_quote!(f64))],
+            );
+            match runtime {
+                Runtime::Tokio => call(parse_quote!(tokio::time::sleep), [duration]),
+                Runtime::AsyncStd => call(parse_quote!(async_std::task::sleep), [duration]),
+            }
+        }
+        ("create_task", Runtime::Tokio) => call(parse_quote!(tokio::spawn), [arg.clone()]),
+        ("create_task", Runtime::AsyncStd) => {
+            call(parse_quote!(async_std::task::spawn), [arg.clone()])
+        }
+        // `gather` is only translated with `await`
+        _ => return Err(todo_link!()),
+    })
+}
+
+/// `async for x in stream:` -> `while let Some(x) = stream.next().await`
+pub fn r_async_for(node: &StmtAsyncFor, ctx: &mut Ctx) -> TResult<Vec<syn::Stmt>> {
+    let StmtAsyncFor {
+        target,
+        iter,
+        body,
+        orelse,
+        ..
+    } = node;
+    if !orelse.is_empty() {
+        return Err(todo_link!());
+    }
+    let stream = r_e(iter, ctx)?;
+    let pat = r_target(target, ctx)?;
+    ctx.breaks.push(None);
+    let outer_jumps = std::mem::replace(&mut ctx.try_jumps, false);
+    let body = r_body_binding(body, Some(target), ctx);
+    ctx.try_jumps = outer_jumps;
+    ctx.breaks.pop();
+    let body = body?;
+    let next: syn::Path = match ctx.runtime {
+        Runtime::Tokio => parse_quote!(tokio_stream::StreamExt::next),
+        Runtime::AsyncStd => parse_quote!(async_std::stream::StreamExt::next),
+    };
+    Ok(vec![stmt(block_expr(vec![
+        parse_quote!(let mut stream = std::pin::pin!(#stream);),
+        stmt(parse_quote!(while let Some(#pat) = #next(&mut stream).await { #(#body)* })),
+    ]))])
+}
+
+/// `asyncio.f`
+fn asyncio_function(func: &Expr) -> Option<&str> {
+    match func {
+        Expr::Attribute(a) if a.value.as_name_expr()?.id.as_str() == "asyncio" => {
+            Some(a.attr.as_str())
+        }
+        _ => None,
+    }
+}
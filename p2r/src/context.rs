@@ -0,0 +1,311 @@
+//! `with` statements become blocks in which the context managers are bound to guard values,
+//! the `__exit__` logic runs when they are dropped at the end of the block. classes with
+//! `__exit__` get a `FooGuard` struct which borrows the instance and runs `exit` in its `Drop`
+//!
+//! `Drop` can't be async, `async with` awaits `__aexit__` after the block instead
+
+use crate::infer::{visit_stmts, Ty};
+use crate::{
+    awaited, block_expr, call, dunder, ident, method_call, pat_ident, path, r_body, r_e, reference,
+    stmt, todo_link, Ctx, TResult, TranspileError,
+};
+use rustpython_parser::ast::{
+    Constant, Expr, ExprCall, Ranged, Stmt, StmtAsyncWith, StmtWith, WithItem,
+};
+use syn::parse_quote;
+
+/// `with a as x, b:` -> `{ let x = ...; let _guard = ...; body }`
+pub fn r_with(node: &StmtWith, ctx: &mut Ctx) -> TResult<Vec<syn::Stmt>> {
+    r_block(&node.items, &node.body, ctx)
+}
+
+/// `async with a as x:` -> `{ let mut x = ...; x.enter().await; body; x.exit().await; }`
+pub fn r_async_with(node: &StmtAsyncWith, ctx: &mut Ctx) -> TResult<Vec<syn::Stmt>> {
+    r_block(&node.items, &node.body, ctx)
+}
+
+fn r_block(items: &[WithItem], body: &[Stmt], ctx: &mut Ctx) -> TResult<Vec<syn::Stmt>> {
+    ctx.scopes.push();
+    let files = ctx.files.len();
+    let stmts = r_items(items, body, ctx);
+    ctx.files.truncate(files);
+    ctx.scopes.pop();
+    Ok(vec![stmt(block_expr(stmts?))])
+}
+
+fn r_items(items: &[WithItem], body: &[Stmt], ctx: &mut Ctx) -> TResult<Vec<syn::Stmt>> {
+    let mut stmts = vec![];
+    // `exit` of async context managers
+    let mut exits = vec![];
+    for item in items {
+        let target = match item.optional_vars.as_deref() {
+            None => None,
+            Some(Expr::Name(n)) => Some(n.id.as_str()),
+            // `with open(a) as (x, y):` unpacks the value of `__enter__`
+            Some(_) => return Err(todo_link!()),
+        };
+        stmts.extend(r_item(&item.context_expr, target, &mut exits, ctx)?);
+        if let Some(vars) = &item.optional_vars {
+            ctx.scopes.declare(vars);
+        }
+    }
+    stmts.extend(r_body(body, ctx)?);
+    if !exits.is_empty() && leaves_early(body) {
+        ctx.warn(
+            "AsyncWith",
+            items[0].context_expr.range(),
+            "`__aexit__` is not awaited when the block is left early",
+        );
+    }
+    stmts.extend(exits.into_iter().rev());
+    Ok(stmts)
+}
+
+/// `return`, `raise`, `break` or `continue` in `body`
+fn leaves_early(body: &[Stmt]) -> bool {
+    let mut leaves = false;
+    visit_stmts(body, &mut |s| {
+        leaves |= matches!(
+            s,
+            Stmt::Return(_) | Stmt::Raise(_) | Stmt::Break(_) | Stmt::Continue(_)
+        );
+    });
+    leaves
+}
+
+/// binds the context manager `manager` to `target` or to a guard which lives until the end of
+/// the block
+fn r_item(
+    manager: &Expr,
+    target: Option<&str>,
+    exits: &mut Vec<syn::Stmt>,
+    ctx: &mut Ctx,
+) -> TResult<Vec<syn::Stmt>> {
+    if let Expr::Call(c) = manager
+        && let Some(file) = r_file(c, ctx)?
+    {
+        let name = target.unwrap_or("_file");
+        ctx.files.push(name.to_string());
+        let pat = pat_ident(name, ctx.scopes.is_mutated(name));
+        return Ok(vec![parse_quote!(let #pat = #file;)]);
+    }
+
+    if let Expr::Call(ExprCall { func, args, .. }) = manager
+        && args.is_empty()
+        && module_function(func, "tempfile") == Some("TemporaryDirectory")
+    {
+        // the directory is removed when the `TempDir` is dropped, python binds its path
+        let Some(name) = target else {
+            return Ok(vec![parse_quote!(let _dir = tempfile::tempdir().unwrap();)]);
+        };
+        let dir = ident(&format!("{name}_dir"));
+        let name = ident(name);
+        return Ok(vec![
+            parse_quote!(let #dir = tempfile::tempdir().unwrap();),
+            parse_quote!(let #name = #dir.path();),
+        ]);
+    }
+
+    let ty = ctx.types.ty(manager, &ctx.types.locals);
+    let value = r_e(manager, ctx)?;
+    match ty {
+        Ty::Lock => {
+            // `with lock as acquired:` binds `True`
+            if target.is_some() {
+                return Err(todo_link!());
+            }
+            Ok(vec![parse_quote!(let _guard = #value.lock().unwrap();)])
+        }
+        Ty::Class(class) if ctx.types.context_managers.contains_key(&class) => {
+            let manager = ctx.types.context_managers[&class];
+            let mutable = manager.enter_mutates
+                || manager.is_async
+                || target.is_some_and(|t| ctx.scopes.is_mutated(t));
+            let enter = |guard: &str| {
+                let enter = method_call(path(guard), "enter", []);
+                if manager.is_async {
+                    awaited(enter)
+                } else {
+                    enter
+                }
+            };
+            let mut stmts = vec![];
+            let guard = if manager.exit && manager.returns_self {
+                // `enter` borrows the manager and returns the guard, which runs `exit` when it
+                // is dropped at the end of the block
+                let name = target.unwrap_or("manager");
+                let manager_pat = pat_ident(name, true);
+                let guard_pat = pat_ident(
+                    target.unwrap_or("_guard"),
+                    target.is_some_and(|t| ctx.scopes.is_mutated(t)),
+                );
+                let enter = enter(name);
+                stmts.push(parse_quote!(let #manager_pat = #value;));
+                stmts.push(parse_quote!(let #guard_pat = #enter;));
+                name
+            } else if manager.exit {
+                // the guard has to outlive the value of `__enter__`
+                let guard = dunder::guard_name(&class);
+                let name = pat_ident(target.unwrap_or("_entered"), false);
+                stmts.push(parse_quote!(let mut manager = #value;));
+                let guard_pat = pat_ident("guard", manager.enter_mutates);
+                stmts.push(parse_quote!(let #guard_pat = #guard(&mut manager);));
+                stmts.push(parse_quote!(let #name = guard.enter();));
+                "guard"
+            } else if manager.returns_self {
+                let used = manager.enter || manager.is_async;
+                let name = target.unwrap_or(if used { "guard" } else { "_guard" });
+                let pat = pat_ident(name, mutable);
+                stmts.push(parse_quote!(let #pat = #value;));
+                if manager.enter {
+                    stmts.push(stmt(enter(name)));
+                }
+                name
+            } else {
+                // the guard has to outlive the value of `__enter__`
+                let guard = pat_ident("guard", mutable);
+                let name = pat_ident(target.unwrap_or("_entered"), false);
+                let enter = enter("guard");
+                stmts.push(parse_quote!(let #guard = #value;));
+                stmts.push(parse_quote!(let #name = #enter;));
+                "guard"
+            };
+            if manager.is_async {
+                exits.push(stmt(awaited(method_call(path(guard), "exit", []))));
+            }
+            Ok(stmts)
+        }
+        _ => {
+            ctx.warn(
+                "With",
+                manager.range(),
+                "unknown context manager, it is bound to a variable which is dropped at the \
+                 end of the block",
+            );
+            let pat = pat_ident(target.unwrap_or("_guard"), false);
+            Ok(vec![parse_quote!(let #pat = #value;)])
+        }
+    }
+}
+
+/// `open(path, mode)`, `tempfile.TemporaryFile()` and `tempfile.NamedTemporaryFile()`
+fn r_file(node: &ExprCall, ctx: &mut Ctx) -> TResult<Option<syn::Expr>> {
+    let ExprCall {
+        func,
+        args,
+        keywords,
+        ..
+    } = node;
+    match module_function(func, "tempfile") {
+        Some("TemporaryFile") => return Ok(Some(parse_quote!(tempfile::tempfile().unwrap()))),
+        Some("NamedTemporaryFile") => {
+            return Ok(Some(parse_quote!(tempfile::NamedTempFile::new().unwrap())));
+        }
+        _ => {}
+    }
+    match func.as_name_expr() {
+        Some(n) if n.id.as_str() == "open" => {}
+        _ => return Ok(None),
+    }
+
+    let file = args.first().ok_or(todo_link!())?;
+    let mode = args
+        .get(1)
+        .or_else(|| {
+            keywords
+                .iter()
+                .find(|k| k.arg.as_deref() == Some("mode"))
+                .map(|k| &k.value)
+        })
+        .map(|m| match m.as_constant_expr().map(|c| &c.value) {
+            Some(Constant::Str(m)) => Ok(m.as_str()),
+            _ => Err(todo_link!("the mode of `open` has to be a string literal")),
+        })
+        .transpose()?
+        .unwrap_or("r");
+    let file = match r_e(file, ctx)? {
+        lit @ syn::Expr::Lit(_) => lit,
+        // keep the path usable after the block
+        e => reference(e),
+    };
+
+    Ok(Some(match mode.trim_end_matches(['b', 't']) {
+        "r" => parse_quote!(std::fs::File::open(#file).unwrap()),
+        "w" => parse_quote!(std::fs::File::create(#file).unwrap()),
+        "a" => parse_quote! {
+            std::fs::OpenOptions::new().append(true).create(true).open(#file).unwrap()
+        },
+        "r+" => parse_quote! {
+            std::fs::OpenOptions::new().read(true).write(true).open(#file).unwrap()
+        },
+        "x" => parse_quote! {
+            std::fs::OpenOptions::new().write(true).create_new(true).open(#file).unwrap()
+        },
+        _ => return Err(todo_link!()),
+    }))
+}
+
+/// `module.f` or `f` (imported with `from module import f`)
+fn module_function<'a>(func: &'a Expr, module: &str) -> Option<&'a str> {
+    match func {
+        Expr::Attribute(a) if a.value.as_name_expr()?.id.as_str() == module => {
+            Some(a.attr.as_str())
+        }
+        Expr::Name(n) => Some(n.id.as_str()),
+        _ => None,
+    }
+}
+
+/// methods of files which are bound in a `with` block
+pub fn r_file_method(
+    file: &str,
+    method: &str,
+    args: Vec<syn::Expr>,
+    ctx: &Ctx,
+) -> TResult<Option<syn::Expr>> {
+    if !ctx.files.iter().any(|f| f == file) {
+        return Ok(None);
+    }
+    let file = path(file);
+    Ok(Some(match (method, args.as_slice()) {
+        ("read", []) => parse_quote!(std::io::read_to_string(&#file).unwrap()),
+        ("readlines", []) => {
+            let lines = r_lines(file);
+            parse_quote!(#lines.collect::<Vec<_>>())
+        }
+        ("write", [s]) => {
+            let write = call(
+                parse_quote!(std::io::Write::write_all),
+                [
+                    parse_quote!(&mut #file),
+                    method_call(s.clone(), "as_bytes", []),
+                ],
+            );
+            method_call(write, "unwrap", [])
+        }
+        ("flush", []) => {
+            parse_quote!(std::io::Write::flush(&mut #file).unwrap())
+        }
+        _ => return Err(todo_link!()),
+    }))
+}
+
+/// `for line in f:` iterates over the lines of a file bound in a `with` block
+pub fn r_file_lines(iter: &Expr, ctx: &mut Ctx) -> Option<syn::Expr> {
+    let name = iter.as_name_expr()?.id.as_str();
+    if !ctx.files.iter().any(|f| f == name) {
+        return None;
+    }
+    ctx.warn(
+        "For",
+        iter.range(),
+        "the lines of a file don't end with a newline in rust",
+    );
+    Some(r_lines(path(name)))
+}
+
+fn r_lines(file: syn::Expr) -> syn::Expr {
+    parse_quote! {
+        std::io::BufRead::lines(std::io::BufReader::new(&#file)).map(Result::unwrap)
+    }
+}
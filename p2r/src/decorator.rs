@@ -0,0 +1,96 @@
+//! decorators of classes and methods
+
+use crate::{infer::Ty, inherit::base_name, Ctx};
+use rustpython_parser::ast::{Constant, Expr, ExprAttribute, ExprCall, Ranged};
+
+/// `@name`, `@module.name` or `@name(...)`
+pub fn find<'a>(decorators: &'a [Expr], name: &str) -> Option<&'a Expr> {
+    decorators.iter().find(|d| {
+        let d = match d {
+            Expr::Call(ExprCall { func, .. }) => func.as_ref(),
+            d => d,
+        };
+        base_name(d) == Some(name)
+    })
+}
+
+pub fn has(decorators: &[Expr], name: &str) -> bool {
+    find(decorators, name).is_some()
+}
+
+/// `@prop.setter`, returns the name of the property
+pub fn setter(decorators: &[Expr]) -> Option<&str> {
+    decorators.iter().find_map(|d| match d {
+        Expr::Attribute(ExprAttribute { value, attr, .. }) if attr.as_str() == "setter" => {
+            value.as_name_expr().map(|n| n.id.as_str())
+        }
+        _ => None,
+    })
+}
+
+/// boolean keyword argument of a decorator, e.g. `frozen=True` of `@dataclass(frozen=True)`
+pub fn flag(decorator: &Expr, name: &str) -> Option<bool> {
+    let Expr::Call(ExprCall { keywords, .. }) = decorator else {
+        return None;
+    };
+    keywords
+        .iter()
+        .find(|k| k.arg.as_deref() == Some(name))
+        .and_then(|k| match k.value.as_constant_expr()?.value {
+            Constant::Bool(b) => Some(b),
+            _ => None,
+        })
+}
+
+/// derives for the options of `@dataclass`, `fields` are the types of the fields
+///
+/// `f64` is neither `Eq` nor `Hash`, hashable dataclasses with float fields only get
+/// `PartialEq`
+pub fn dataclass_derives(decorators: &[Expr], fields: &[Ty], ctx: &mut Ctx) -> Vec<&'static str> {
+    let Some(d) = find(decorators, "dataclass") else {
+        return vec![];
+    };
+    let eq = flag(d, "eq").unwrap_or(true);
+    let frozen = flag(d, "frozen").unwrap_or(false);
+    let order = flag(d, "order").unwrap_or(false);
+    let unsafe_hash = flag(d, "unsafe_hash").unwrap_or(false);
+
+    let mut derives = vec![];
+    if eq {
+        derives.push("PartialEq");
+    }
+    // frozen dataclasses are hashable
+    if eq && (frozen || unsafe_hash) {
+        if fields.iter().any(|t| has_float(t, ctx)) {
+            ctx.warn(
+                crate::expr_kind(d),
+                d.range(),
+                "floats can't be hashed, `Eq` and `Hash` aren't derived",
+            );
+        } else {
+            derives.extend(["Eq", "Hash"]);
+        }
+    }
+    if order {
+        derives.push("PartialOrd");
+    }
+    derives
+}
+
+/// `ty` contains a float, also in a collection or a field of a class
+fn has_float(ty: &Ty, ctx: &Ctx) -> bool {
+    match ty {
+        Ty::Float => true,
+        Ty::List(t) | Ty::Set(t) | Ty::Iter(t) => has_float(t, ctx),
+        Ty::Dict(k, v) => has_float(k, ctx) || has_float(v, ctx),
+        Ty::Tuple(ts) => ts.iter().any(|t| has_float(t, ctx)),
+        Ty::Class(c) => ctx
+            .types
+            .classes
+            .get(c)
+            .into_iter()
+            .flatten()
+            .any(|(_, t)| has_float(t, ctx)),
+        _ => false,
+    }
+}
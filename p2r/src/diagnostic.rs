@@ -0,0 +1,137 @@
+//! locations in the python code and messages about them
+
+use rustpython_parser::text_size::{TextRange, TextSize};
+use std::fmt::Write;
+
+/// 1 based line and column (counted in chars)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LineCol {
+    pub line: u32,
+    pub column: u32,
+}
+
+impl LineCol {
+    fn new(code: &str, offset: TextSize) -> Self {
+        let before = code.get(..usize::from(offset)).unwrap_or(code);
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Self {
+            line: before.matches('\n').count() as u32 + 1,
+            column: before[line_start..].chars().count() as u32 + 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PySpan {
+    /// name of the python ast node, e.g. `Lambda` or `Try`
+    pub kind: &'static str,
+    pub range: TextRange,
+    pub start: LineCol,
+    pub end: LineCol,
+}
+
+impl PySpan {
+    /// `start` and `end` are only known after `locate`
+    pub(crate) fn new(kind: &'static str, range: TextRange) -> Self {
+        Self {
+            kind,
+            range,
+            start: LineCol::default(),
+            end: LineCol::default(),
+        }
+    }
+
+    /// turns the byte offsets of the range into lines and columns of `code`
+    pub(crate) fn locate(&mut self, code: &str) {
+        self.start = LineCol::new(code, self.range.start());
+        self.end = LineCol::new(code, self.range.end());
+    }
+
+    /// the first line of the span with the span underlined
+    pub fn snippet(&self, code: &str) -> String {
+        let mut res = String::new();
+        let Some(src) = code.lines().nth(self.start.line as usize - 1) else {
+            return res;
+        };
+
+        let line = self.start.line.to_string();
+        let pad = " ".repeat(line.len());
+        let len = if self.end.line == self.start.line {
+            (self.end.column - self.start.column).max(1) as usize
+        } else {
+            (src.chars().count() + 1)
+                .saturating_sub(self.start.column as usize)
+                .max(1)
+        };
+        let _ = writeln!(res, "{pad} |");
+        let _ = writeln!(res, "{line} | {src}");
+        let _ = writeln!(
+            res,
+            "{pad} | {}{}",
+            " ".repeat(self.start.column as usize - 1),
+            "^".repeat(len)
+        );
+        res
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// the python code was replaced by `todo!()`
+    Error,
+    /// the python code was transpiled, but the rust code might behave differently
+    Warning,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => f.write_str("error"),
+            Severity::Warning => f.write_str("warning"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub span: Option<PySpan>,
+    pub message: String,
+    /// place in p2r which needs to be improved, only set for errors
+    pub link: Option<String>,
+}
+
+impl From<crate::TranspileError> for Diagnostic {
+    fn from(e: crate::TranspileError) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: match (&e.span, e.message) {
+                (Some(span), Some(message)) => format!("python {}: {message}", span.kind),
+                (Some(span), None) => format!("python {} is not supported yet", span.kind),
+                (None, Some(message)) => message.to_string(),
+                (None, None) => "not supported yet".to_string(),
+            },
+            link: Some(e.to_link()),
+            span: e.span,
+        }
+    }
+}
+
+impl Diagnostic {
+    /// human readable message which underlines the python code
+    pub fn report(&self, code: &str) -> String {
+        let mut res = format!("{}: {}", self.severity, self.message);
+        if let Some(span) = &self.span {
+            let _ = write!(res, " at {}:{}", span.start.line, span.start.column);
+            res.push('\n');
+            res.push_str(&span.snippet(code));
+        } else {
+            res.push('\n');
+        }
+        if let Some(link) = &self.link {
+            res.push_str(link);
+            res.push('\n');
+        }
+        res
+    }
+}
@@ -0,0 +1,478 @@
+//! python dunder methods which are translated into rust trait implementations
+
+use crate::{
+    ident, impl_fn, infer::Ty, method_call, r_fn, scope::Receiver, type_starts_with, Ctx,
+    Promotion, TResult,
+};
+use rustpython_parser::ast::{Expr, Stmt, StmtFunctionDef};
+use syn::parse_quote;
+
+/// binary operators: python method, rust trait and method in `std::ops`
+const OPERATORS: &[(&str, &str, &str)] = &[
+    ("__add__", "Add", "add"),
+    ("__sub__", "Sub", "sub"),
+    ("__mul__", "Mul", "mul"),
+    ("__truediv__", "Div", "div"),
+    ("__mod__", "Rem", "rem"),
+    ("__and__", "BitAnd", "bitand"),
+    ("__or__", "BitOr", "bitor"),
+    ("__xor__", "BitXor", "bitxor"),
+    ("__lshift__", "Shl", "shl"),
+    ("__rshift__", "Shr", "shr"),
+];
+
+/// comparisons: python method and method of `PartialOrd`
+const COMPARISONS: &[(&str, &str)] = &[
+    ("__lt__", "lt"),
+    ("__gt__", "gt"),
+    ("__le__", "le"),
+    ("__ge__", "ge"),
+];
+
+/// methods which are translated by `impls` instead of going into the inherent impl
+pub fn is_dunder(name: &str) -> bool {
+    OPERATORS.iter().any(|(m, ..)| *m == name)
+        || COMPARISONS.iter().any(|(m, _)| *m == name)
+        || matches!(
+            name,
+            "__neg__"
+                | "__eq__"
+                | "__str__"
+                | "__repr__"
+                | "__hash__"
+                | "__len__"
+                | "__getitem__"
+                | "__iter__"
+                | "__next__"
+                | "__enter__"
+                | "__exit__"
+                | "__aenter__"
+                | "__aexit__"
+        )
+}
+
+/// derives of a class with the field types `fields`, traits which are implemented from
+/// dunder methods are left out
+pub fn derives(
+    decorators: &[Expr],
+    defs: &[&StmtFunctionDef],
+    fields: &[Ty],
+    ctx: &mut Ctx,
+) -> Vec<syn::Ident> {
+    let has = |name: &str| defs.iter().any(|d| d.name.as_str() == name);
+    let mut derives = vec![];
+    if !has("__repr__") {
+        derives.push("Debug");
+    }
+    derives.push("Clone");
+    derives.extend(crate::decorator::dataclass_derives(decorators, fields, ctx));
+    if COMPARISONS.iter().any(|(m, _)| has(m)) {
+        // required by `PartialOrd`
+        if !derives.contains(&"PartialEq") {
+            derives.push("PartialEq");
+        }
+        derives.retain(|d| *d != "PartialOrd");
+    }
+    if has("__eq__") {
+        derives.retain(|d| *d != "PartialEq");
+    }
+    if has("__hash__") {
+        derives.retain(|d| *d != "Hash");
+    }
+    derives.into_iter().map(ident).collect()
+}
+
+/// trait implementations for the dunder methods of `class`
+///
+/// `__len__` and `__iter__` become the inherent methods `len` and `iter`, they are returned
+/// separately
+pub fn impls(
+    class: &str,
+    defs: &[&StmtFunctionDef],
+    ctx: &mut Ctx,
+) -> TResult<(Vec<syn::Item>, Vec<syn::ImplItem>)> {
+    let class_ident = ident(class);
+    let find = |name: &str| defs.iter().copied().find(|d| d.name.as_str() == name);
+    let by_ref = Receiver::default();
+    let mut items: Vec<syn::Item> = vec![];
+    let mut methods = vec![];
+
+    for (py, tr, method) in OPERATORS {
+        let Some(def) = find(py) else { continue };
+        let by_value = Receiver {
+            consumes: true,
+            mutates: false,
+        };
+        let f = method_fn(def, by_value, Promotion::None, ctx)?;
+        let rhs = arg_type(def, &f, 1).unwrap_or_else(|| parse_quote!(Self));
+        let output = ret_type(&f).unwrap_or_else(|| parse_quote!(Self));
+        let (tr, method, other, block) = (ident(tr), ident(method), arg_name(def, 1), f.block);
+        let tr: syn::Path = if quote::quote!(#rhs).to_string() == "Self" {
+            parse_quote!(std::ops::#tr)
+        } else {
+            parse_quote!(std::ops::#tr<#rhs>)
+        };
+        items.push(parse_quote! {
+            impl #tr for #class_ident {
+                type Output = #output;
+                fn #method(self, #other: #rhs) -> Self::Output #block
+            }
+        });
+    }
+
+    if let Some(def) = find("__neg__") {
+        let by_value = Receiver {
+            consumes: true,
+            mutates: false,
+        };
+        let f = method_fn(def, by_value, Promotion::None, ctx)?;
+        let output = ret_type(&f).unwrap_or_else(|| parse_quote!(Self));
+        let block = f.block;
+        items.push(parse_quote! {
+            impl std::ops::Neg for #class_ident {
+                type Output = #output;
+                fn neg(self) -> Self::Output #block
+            }
+        });
+    }
+
+    if let Some(def) = find("__eq__") {
+        let f = method_fn(def, by_ref, Promotion::None, ctx)?;
+        let other = arg_name(def, 1);
+        let block = f.block;
+        items.push(parse_quote! {
+            impl PartialEq for #class_ident {
+                fn eq(&self, #other: &Self) -> bool #block
+            }
+        });
+    }
+
+    let comparisons = COMPARISONS
+        .iter()
+        .filter_map(|(py, method)| Some((find(py)?, *method)))
+        .collect::<Vec<_>>();
+    if let Some((_, first)) = comparisons.first() {
+        // `partial_cmp` is derived from one of the comparisons, the others use it by default
+        let partial_cmp: syn::Expr = match *first {
+            "lt" => parse_quote!(if self < other {
+                Some(Less)
+            } else if other < self {
+                Some(Greater)
+            } else {
+                Some(Equal)
+            }),
+            "gt" => parse_quote!(if self > other {
+                Some(Greater)
+            } else if other > self {
+                Some(Less)
+            } else {
+                Some(Equal)
+            }),
+            "le" => parse_quote!(match (self <= other, other <= self) {
+                (true, true) => Some(Equal),
+                (true, false) => Some(Less),
+                (false, true) => Some(Greater),
+                (false, false) => None,
+            }),
+            _ => parse_quote!(match (self >= other, other >= self) {
+                (true, true) => Some(Equal),
+                (true, false) => Some(Greater),
+                (false, true) => Some(Less),
+                (false, false) => None,
+            }),
+        };
+        let mut fns = vec![];
+        for (def, method) in comparisons {
+            let f = method_fn(def, by_ref, Promotion::None, ctx)?;
+            let (method, other, block) = (ident(method), arg_name(def, 1), f.block);
+            fns.push(quote::quote!(fn #method(&self, #other: &Self) -> bool #block));
+        }
+        items.push(parse_quote! {
+            impl PartialOrd for #class_ident {
+                fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+                    use std::cmp::Ordering::*;
+                    #partial_cmp
+                }
+                #(#fns)*
+            }
+        });
+    }
+
+    for (py, tr) in [("__str__", "Display"), ("__repr__", "Debug")] {
+        let Some(def) = find(py) else { continue };
+        let f = method_fn(def, by_ref, Promotion::Write, ctx)?;
+        let (tr, block) = (ident(tr), f.block);
+        items.push(parse_quote! {
+            impl std::fmt::#tr for #class_ident {
+                fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result #block
+            }
+        });
+    }
+
+    if let Some(def) = find("__hash__") {
+        let f = method_fn(def, by_ref, Promotion::Hash, ctx)?;
+        let block = f.block;
+        items.push(parse_quote! {
+            impl std::hash::Hash for #class_ident {
+                fn hash<H: std::hash::Hasher>(&self, state: &mut H) #block
+            }
+        });
+        if find("__eq__").is_some() {
+            items.push(parse_quote!(impl Eq for #class_ident {}));
+        }
+    }
+
+    if let Some(def) = find("__getitem__") {
+        let f = method_fn(def, by_ref, Promotion::Ref, ctx)?;
+        // python indices are `int`, but rust containers are indexed by `usize`
+        let index = arg_type(def, &f, 1)
+            .filter(|t| quote::quote!(#t).to_string() != "isize")
+            .unwrap_or_else(|| parse_quote!(usize));
+        let output = ret_type(&f).unwrap_or_else(|| parse_quote!(()));
+        let (i, block) = (arg_name(def, 1), f.block);
+        items.push(parse_quote! {
+            impl std::ops::Index<#index> for #class_ident {
+                type Output = #output;
+                fn index(&self, #i: #index) -> &Self::Output #block
+            }
+        });
+    }
+
+    if let Some(def) = find("__next__") {
+        let by_mut = Receiver {
+            consumes: false,
+            mutates: true,
+        };
+        let f = method_fn(def, by_mut, Promotion::Into, ctx)?;
+        let item = match ret_type(&f) {
+            // `Optional[T]`
+            Some(syn::Type::Path(p)) if type_starts_with(&syn::Type::Path(p.clone()), "Option") => {
+                match &p.path.segments[0].arguments {
+                    syn::PathArguments::AngleBracketed(a) => match a.args.first() {
+                        Some(syn::GenericArgument::Type(t)) => t.clone(),
+                        _ => parse_quote!(()),
+                    },
+                    _ => parse_quote!(()),
+                }
+            }
+            Some(t) => t,
+            None => parse_quote!(()),
+        };
+        let block = f.block;
+        items.push(parse_quote! {
+            impl Iterator for #class_ident {
+                type Item = #item;
+                fn next(&mut self) -> Option<Self::Item> #block
+            }
+        });
+    }
+
+    if let Some(def) = find("__iter__") {
+        // `return self` is covered by the `Iterator` implementation
+        let returns_self = matches!(
+            def.body.as_slice(),
+            [Stmt::Return(r)] if r.value.as_deref().and_then(Expr::as_name_expr).is_some_and(|n| n.id.as_str() == "self")
+        );
+        if !(returns_self && find("__next__").is_some()) {
+            let mut f = method_fn(def, by_ref, Promotion::None, ctx)?;
+            f.sig.ident = ident("iter");
+            methods.push(syn::ImplItem::Fn(impl_fn(f)));
+        }
+    }
+
+    // `__exit__` runs when the guard which `enter` returns is dropped, not for every instance
+    let guard = find("__exit__").map(|_| guard_name(class));
+    if let Some(guard) = &guard {
+        items.extend([
+            parse_quote!(struct #guard<'a>(&'a mut #class_ident);),
+            parse_quote! {
+                impl std::ops::Deref for #guard<'_> {
+                    type Target = #class_ident;
+                    fn deref(&self) -> &#class_ident {
+                        self.0
+                    }
+                }
+            },
+            parse_quote! {
+                impl std::ops::DerefMut for #guard<'_> {
+                    fn deref_mut(&mut self) -> &mut #class_ident {
+                        self.0
+                    }
+                }
+            },
+            parse_quote! {
+                impl Drop for #guard<'_> {
+                    fn drop(&mut self) {
+                        self.0.exit();
+                    }
+                }
+            },
+        ]);
+        if find("__enter__").is_none() {
+            methods.push(parse_quote! {
+                fn enter(&mut self) -> #guard<'_> {
+                    #guard(self)
+                }
+            });
+        }
+    }
+
+    for (py, is_async) in [("__enter__", false), ("__aenter__", true)] {
+        let Some(def) = find(py) else { continue };
+        // `return self` is the guard itself, which is bound by the caller
+        let returns_self = ctx
+            .types
+            .context_managers
+            .get(class)
+            .is_some_and(|m| m.returns_self);
+        let receiver = crate::scope::receivers(&[def], |_| false)[py];
+        let (receiver, promotion) = if returns_self {
+            // the guard borrows `self` mutably
+            let receiver = Receiver {
+                consumes: false,
+                mutates: receiver.mutates || guard.is_some(),
+            };
+            (receiver, Promotion::Ignore)
+        } else {
+            (receiver, Promotion::None)
+        };
+        let mut f = method_fn(def, receiver, promotion, ctx)?;
+        f.sig.ident = ident("enter");
+        if is_async {
+            f.sig.asyncness = Some(Default::default());
+        }
+        if returns_self {
+            f.sig.output = syn::ReturnType::Default;
+            pop_return(&mut f.block);
+            if let Some(guard) = &guard
+                && !is_async
+            {
+                f.sig.output = parse_quote!(-> #guard<'_>);
+                f.block
+                    .stmts
+                    .push(syn::Stmt::Expr(parse_quote!(#guard(self)), None));
+            }
+        }
+        methods.push(syn::ImplItem::Fn(impl_fn(f)));
+    }
+
+    for (py, is_async) in [("__exit__", false), ("__aexit__", true)] {
+        let Some(def) = find(py) else { continue };
+        // the exception which left the `with` block is not available
+        let params = def
+            .args
+            .args
+            .iter()
+            .skip(1)
+            .map(|a| a.def.arg.as_str())
+            .collect::<Vec<_>>();
+        let mut uses_exception = false;
+        crate::infer::visit_exprs(&def.body, &mut |e| {
+            uses_exception |= e
+                .as_name_expr()
+                .is_some_and(|n| params.contains(&n.id.as_str()));
+        });
+        if uses_exception {
+            ctx.warn(
+                "FunctionDef",
+                def.range,
+                "the exception which left the `with` block is not available in `exit`",
+            );
+        }
+        let mut def = def.clone();
+        def.args.args.truncate(1);
+        let by_mut = Receiver {
+            consumes: false,
+            mutates: true,
+        };
+        let mut f = method_fn(&def, by_mut, Promotion::Ignore, ctx)?;
+        pop_return(&mut f.block);
+        let block = f.block;
+        if is_async {
+            // `Drop` can't be async, `async with` awaits `exit` at the end of the block
+            methods.push(parse_quote!(async fn exit(&mut self) #block));
+        } else {
+            methods.push(parse_quote!(fn exit(&mut self) #block));
+        }
+    }
+
+    if let Some(def) = find("__len__") {
+        let mut f = method_fn(def, by_ref, Promotion::None, ctx)?;
+        f.sig.ident = ident("len");
+        f.sig.output = parse_quote!(-> usize);
+        methods.push(syn::ImplItem::Fn(impl_fn(f)));
+    }
+
+    Ok((items, methods))
+}
+
+/// `FooGuard` of the context manager `Foo`, which runs `exit` when it is dropped
+pub fn guard_name(class: &str) -> syn::Ident {
+    ident(&format!("{class}Guard"))
+}
+
+/// converts the method `def` with the given receiver and conversion of returned values
+fn method_fn(
+    def: &StmtFunctionDef,
+    receiver: Receiver,
+    promotion: Promotion,
+    ctx: &mut Ctx,
+) -> TResult<syn::ItemFn> {
+    ctx.receiver = receiver;
+    let outer = std::mem::replace(&mut ctx.ret_needs_promotion, promotion);
+    let f = r_fn(def, ctx);
+    ctx.ret_needs_promotion = outer;
+    f
+}
+
+/// removes the `return;` at the end of `block`
+fn pop_return(block: &mut syn::Block) {
+    if let Some(syn::Stmt::Expr(syn::Expr::Return(r), _)) = block.stmts.last()
+        && r.expr.is_none()
+    {
+        block.stmts.pop();
+    }
+}
+
+/// `f64` isn't `Hash`, floats which are hashed (also in tuples) are hashed by their bits
+pub fn float_bits(value: &Expr, v: syn::Expr, ctx: &Ctx) -> syn::Expr {
+    match (value, v) {
+        (Expr::Tuple(t), syn::Expr::Tuple(mut tuple)) if t.elts.len() == tuple.elems.len() => {
+            tuple.elems = t
+                .elts
+                .iter()
+                .zip(tuple.elems)
+                .map(|(e, v)| float_bits(e, v, ctx))
+                .collect();
+            syn::Expr::Tuple(tuple)
+        }
+        (value, v) if ctx.types.ty(value, &ctx.types.locals) == Ty::Float => {
+            method_call(v, "to_bits", [])
+        }
+        (_, v) => v,
+    }
+}
+
+/// name of the `i`th parameter
+fn arg_name(def: &StmtFunctionDef, i: usize) -> syn::Ident {
+    ident(def.args.args.get(i).map_or("other", |a| a.def.arg.as_str()))
+}
+
+/// type of the `i`th parameter of the converted method, `None` if it is not annotated
+fn arg_type(def: &StmtFunctionDef, f: &syn::ItemFn, i: usize) -> Option<syn::Type> {
+    def.args.args.get(i)?.def.annotation.as_ref()?;
+    match f.sig.inputs.iter().nth(i)? {
+        syn::FnArg::Typed(t) => Some(*t.ty.clone()),
+        syn::FnArg::Receiver(_) => None,
+    }
+}
+
+/// return type of the converted method, `None` for `()`
+fn ret_type(f: &syn::ItemFn) -> Option<syn::Type> {
+    match &f.sig.output {
+        syn::ReturnType::Type(_, t) if !matches!(t.as_ref(), syn::Type::Tuple(t) if t.elems.is_empty()) => {
+            Some(*t.clone())
+        }
+        _ => None,
+    }
+}
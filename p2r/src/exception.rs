@@ -0,0 +1,669 @@
+//! python exceptions become variants of a generated `Error` enum and functions which raise
+//! return `Result<T, Error>`
+
+use crate::infer::{visit_expr, visit_exprs, visit_stmts, Ty, Types};
+use crate::inherit::base_name;
+use crate::{
+    doc_attrs, docstring, dunder, ident, method_call, r_e, todo_link, Ctx, TResult, TranspileError,
+};
+use rustpython_parser::ast::{
+    Constant, ExceptHandlerExceptHandler, Expr, ExprAttribute, ExprCall, Stmt, StmtClassDef,
+    StmtFor, StmtIf, StmtRaise, StmtTry, StmtWhile, StmtWith,
+};
+use std::collections::{HashMap, HashSet};
+use syn::parse_quote;
+
+/// builtin exceptions and their base class, `except` of the base class catches them
+const BUILTIN_BASES: &[(&str, &str)] = &[
+    ("IndexError", "LookupError"),
+    ("KeyError", "LookupError"),
+    ("ZeroDivisionError", "ArithmeticError"),
+    ("OverflowError", "ArithmeticError"),
+    ("FileNotFoundError", "OSError"),
+    ("NotImplementedError", "RuntimeError"),
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct Exceptions {
+    /// variants of `Error`: exception classes of the module, then raised and caught builtin
+    /// exceptions
+    variants: Vec<String>,
+    /// docstrings of the exception classes of the module
+    docs: HashMap<String, String>,
+    /// exception class -> its base class
+    bases: HashMap<String, String>,
+    /// functions and methods which raise, directly or through calls, as `(class, name)`:
+    /// functions have no class and `__init__` is recorded as its class
+    raising: HashSet<Key>,
+}
+
+/// `(class, name)` of a method, `(None, name)` of a function
+type Key = (Option<String>, String);
+
+impl Exceptions {
+    /// collects the exception classes and raised exceptions of `body` and the functions
+    /// which raise them, `types` resolves the receivers of method calls
+    pub fn collect(&mut self, body: &[Stmt], types: &Types) {
+        self.bases.extend(
+            BUILTIN_BASES
+                .iter()
+                .map(|(e, base)| (e.to_string(), base.to_string())),
+        );
+
+        let mut fns: Vec<(Key, &[Stmt], HashMap<String, Ty>)> = vec![];
+        for s in body {
+            match s {
+                Stmt::ClassDef(class) if self.is_exception_class(class) => {
+                    self.add(&class.name);
+                    if let Some(doc) = class.body.first().and_then(docstring) {
+                        self.docs.insert(class.name.to_string(), doc.to_string());
+                    }
+                    if let Some(base) = class.bases.iter().filter_map(base_name).next() {
+                        self.bases.insert(class.name.to_string(), base.to_string());
+                    }
+                }
+                Stmt::ClassDef(class) => {
+                    for def in class.body.iter().filter_map(|b| b.as_function_def_stmt()) {
+                        // trait methods can't return a `Result`
+                        if dunder::is_dunder(&def.name) {
+                            continue;
+                        }
+                        let key = if def.name.as_str() == "__init__" {
+                            (None, class.name.to_string())
+                        } else {
+                            (Some(class.name.to_string()), def.name.to_string())
+                        };
+                        let mut params = types.params(def);
+                        if let Some(t) = params.get_mut("self") {
+                            *t = Ty::Class(class.name.to_string());
+                        }
+                        fns.push((key, &def.body, types.scope(&def.body, params)));
+                    }
+                }
+                Stmt::FunctionDef(def) => {
+                    let scope = types.scope(&def.body, types.params(def));
+                    fns.push(((None, def.name.to_string()), &def.body, scope));
+                }
+                Stmt::AsyncFunctionDef(def) => {
+                    fns.push(((None, def.name.to_string()), &def.body, Default::default()))
+                }
+                _ => {}
+            }
+        }
+
+        let bodies = std::iter::once(body).chain(fns.iter().map(|(_, b, _)| *b));
+        for body in bodies {
+            visit_stmts(body, &mut |s| match s {
+                Stmt::Raise(StmtRaise { exc: Some(exc), .. }) => {
+                    if let Some(name) = exception_name(exc).filter(|n| *n != "StopIteration") {
+                        self.add(name);
+                    }
+                }
+                Stmt::Try(t) => {
+                    for h in &t.handlers {
+                        let h = h.as_except_handler().unwrap();
+                        for name in handled(h.type_.as_deref()).into_iter().flatten() {
+                            self.add(name);
+                        }
+                    }
+                }
+                _ => {}
+            });
+        }
+
+        // `int(s)` of a string raises `ValueError` like in python
+        for (_, body, scope) in &fns {
+            let mut parses = false;
+            visit_exprs(body, &mut |e| {
+                visit_expr(e, &mut |e| {
+                    parses |= e.as_call_expr().is_some_and(|c| is_parse(c, types, scope));
+                })
+            });
+            if parses {
+                self.add("ValueError");
+            }
+        }
+
+        // callers of raising functions raise as well
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (key, body, scope) in &fns {
+                if !self.raising.contains(key) && self.escapes(body, &[], types, scope) {
+                    self.raising.insert(key.clone());
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    /// an exception can leave `body`, it is raised or a raising function is called outside
+    /// of a `try` which catches it. `caught` are the exceptions which are handled by the
+    /// enclosing `try` statements
+    fn escapes(
+        &self,
+        body: &[Stmt],
+        caught: &[&str],
+        types: &Types,
+        scope: &HashMap<String, Ty>,
+    ) -> bool {
+        // exceptions of other functions are unknown, only `int(s)` raises a known exception
+        let raises = |c: &ExprCall| {
+            if is_parse(c, types, scope) {
+                !self.is_caught("ValueError", caught)
+            } else {
+                self.raises(c, types, scope)
+            }
+        };
+        let calls = |e: &Expr| {
+            let mut calls = false;
+            visit_expr(e, &mut |e| {
+                calls |= e.as_call_expr().is_some_and(raises);
+            });
+            calls
+        };
+        let escapes = |body: &[Stmt]| self.escapes(body, caught, types, scope);
+        body.iter().any(|s| match s {
+            Stmt::Raise(StmtRaise { exc, .. }) => match exc.as_deref().and_then(exception_name) {
+                Some("StopIteration") => false,
+                Some(name) => !self.is_caught(name, caught),
+                None => true,
+            },
+            Stmt::Try(t) => {
+                let handlers = t.handlers.iter().map(|h| h.as_except_handler().unwrap());
+                let handled = handlers
+                    .clone()
+                    .map(|h| handled(h.type_.as_deref()))
+                    .collect::<Option<Vec<_>>>();
+                let body_escapes = match handled {
+                    // catches everything
+                    None => false,
+                    Some(handled) => {
+                        let mut inner = caught.to_vec();
+                        inner.extend(handled.into_iter().flatten());
+                        self.escapes(&t.body, &inner, types, scope)
+                    }
+                };
+                body_escapes
+                    || handlers.clone().any(|h| escapes(&h.body))
+                    || escapes(&t.orelse)
+                    || escapes(&t.finalbody)
+            }
+            Stmt::If(StmtIf {
+                test, body, orelse, ..
+            })
+            | Stmt::While(StmtWhile {
+                test, body, orelse, ..
+            }) => calls(test) || escapes(body) || escapes(orelse),
+            Stmt::For(StmtFor {
+                iter, body, orelse, ..
+            }) => calls(iter) || escapes(body) || escapes(orelse),
+            Stmt::With(StmtWith { items, body, .. }) => {
+                items.iter().any(|i| calls(&i.context_expr)) || escapes(body)
+            }
+            Stmt::Match(m) => calls(&m.subject) || m.cases.iter().any(|c| escapes(&c.body)),
+            Stmt::FunctionDef(_) | Stmt::AsyncFunctionDef(_) | Stmt::ClassDef(_) => false,
+            s => {
+                let mut calls = false;
+                visit_exprs(std::slice::from_ref(s), &mut |e| {
+                    visit_expr(e, &mut |e| {
+                        calls |= e.as_call_expr().is_some_and(raises);
+                    })
+                });
+                calls
+            }
+        })
+    }
+
+    /// `name` is one of the `caught` exceptions or derived from one of them
+    fn is_caught(&self, name: &str, caught: &[&str]) -> bool {
+        std::iter::successors(Some(name), |e| self.bases.get(*e).map(String::as_str))
+            .chain(["Exception", "BaseException"])
+            .any(|e| caught.contains(&e))
+    }
+
+    fn add(&mut self, name: &str) {
+        if !self.is_exception(name) {
+            self.variants.push(name.to_string());
+        }
+    }
+
+    /// `name` is a variant of `Error`
+    pub fn is_exception(&self, name: &str) -> bool {
+        self.variants.iter().any(|v| v == name)
+    }
+
+    /// subclasses of `Exception` or of other exceptions
+    pub fn is_exception_class(&self, class: &StmtClassDef) -> bool {
+        class.bases.iter().filter_map(base_name).any(|b| {
+            matches!(b, "Exception" | "BaseException")
+                || b.ends_with("Error")
+                || self.is_exception(b)
+        })
+    }
+
+    /// the function, method of `class` or class (`__init__`, without `class`) with the name
+    /// `name` raises
+    pub fn raises_in(&self, class: Option<&str>, name: &str) -> bool {
+        self.raising
+            .contains(&(class.map(str::to_string), name.to_string()))
+    }
+
+    /// `name` and the exceptions derived from it
+    fn caught_by(&self, name: &str) -> Vec<&str> {
+        self.variants
+            .iter()
+            .map(String::as_str)
+            .filter(|v| {
+                std::iter::successors(Some(*v), |v| self.bases.get(*v).map(String::as_str))
+                    .any(|v| v == name)
+            })
+            .collect()
+    }
+
+    /// the called function or method raises, methods only if the receiver is known to be
+    /// an instance (or the class) which defines them
+    pub fn raises(&self, call: &ExprCall, types: &Types, scope: &HashMap<String, Ty>) -> bool {
+        match call.func.as_ref() {
+            Expr::Name(f) => self.raises_in(None, &f.id) || is_parse(call, types, scope),
+            Expr::Attribute(ExprAttribute { value, attr, .. }) => {
+                let class = match value.as_ref() {
+                    Expr::Name(n) if types.classes.contains_key(n.id.as_str()) => {
+                        Some(n.id.to_string())
+                    }
+                    value => match types.ty(value, scope) {
+                        Ty::Class(class) => Some(class),
+                        _ => None,
+                    },
+                };
+                class.is_some_and(|c| self.raises_in(Some(&c), attr))
+            }
+            _ => false,
+        }
+    }
+
+    /// `enum Error` with `Display` and `std::error::Error`, nothing if no exceptions are raised
+    pub fn error_enum(&self) -> Vec<syn::Item> {
+        if self.variants.is_empty() {
+            return vec![];
+        }
+        let variants = self.variants.iter().map(|v| ident(v)).collect::<Vec<_>>();
+        let docs = self
+            .variants
+            .iter()
+            .map(|v| self.docs.get(v).map(|d| doc_attrs(d)).unwrap_or_default());
+        let formats = self.variants.iter().map(|v| format!("{v}: {{message}}"));
+
+        vec![
+            parse_quote! {
+                #[derive(Debug)]
+                enum Error {
+                    #(
+                        #(#docs)*
+                        #variants {
+                            message: String,
+                            source: Option<Box<Error>>,
+                        }
+                    ),*
+                }
+            },
+            parse_quote! {
+                impl std::fmt::Display for Error {
+                    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                        match self {
+                            #(Error::#variants { message, .. } => write!(f, #formats)),*
+                        }
+                    }
+                }
+            },
+            parse_quote! {
+                impl std::error::Error for Error {
+                    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                        match self {
+                            #(Error::#variants { source, .. })|* => {
+                                source.as_deref().map(|e| e as &(dyn std::error::Error + 'static))
+                            }
+                        }
+                    }
+                }
+            },
+        ]
+    }
+}
+
+/// `int(s)` or `float(s)` of a string, which becomes `s.parse()`
+pub fn is_parse(call: &ExprCall, types: &Types, scope: &HashMap<String, Ty>) -> bool {
+    matches!(call.func.as_ref(), Expr::Name(f) if matches!(f.id.as_str(), "int" | "float"))
+        && matches!(call.args.as_slice(), [arg] if types.ty(arg, scope) == Ty::Str)
+}
+
+/// name of the raised exception class, `ValueError` of `raise ValueError("...")`
+fn exception_name(exc: &Expr) -> Option<&str> {
+    match exc {
+        Expr::Call(c) => base_name(&c.func),
+        // `raise e` of a caught exception is not a class
+        e => base_name(e).filter(|n| n.starts_with(|c: char| c.is_ascii_uppercase())),
+    }
+}
+
+/// exception classes of an `except` clause, `None` if it catches every exception
+fn handled(type_: Option<&Expr>) -> Option<Vec<&str>> {
+    let names = match type_? {
+        Expr::Tuple(t) => t.elts.iter().filter_map(base_name).collect::<Vec<_>>(),
+        e => base_name(e).into_iter().collect(),
+    };
+    if names
+        .iter()
+        .any(|n| matches!(*n, "Exception" | "BaseException"))
+    {
+        return None;
+    }
+    Some(names)
+}
+
+/// the error of `raise exc from cause`
+pub fn r_raise(exc: &Expr, cause: Option<&Expr>, ctx: &mut Ctx) -> TResult<syn::Expr> {
+    let Some(name) = exception_name(exc).filter(|n| ctx.exceptions.is_exception(n)) else {
+        if cause.is_some() {
+            return Err(todo_link!());
+        }
+        // an exception which was caught before
+        return r_e(exc, ctx);
+    };
+
+    let args = match exc {
+        Expr::Call(c) => {
+            if !c.keywords.is_empty() {
+                return Err(todo_link!());
+            }
+            c.args
+                .iter()
+                .map(|a| r_e(a, ctx))
+                .collect::<TResult<Vec<_>>>()?
+        }
+        _ => vec![],
+    };
+    // like `str(exception)` in python
+    let message: syn::Expr = match args.as_slice() {
+        [] => parse_quote!(String::new()),
+        [arg] => method_call(arg.clone(), "to_string", []),
+        args => {
+            let fmt = format!("({})", vec!["{:?}"; args.len()].join(", "));
+            parse_quote!(format!(#fmt, #(#args),*))
+        }
+    };
+    let source: syn::Expr = match cause {
+        None
+        | Some(Expr::Constant(rustpython_parser::ast::ExprConstant {
+            value: Constant::None,
+            ..
+        })) => parse_quote!(None),
+        Some(cause) => {
+            let cause = r_e(cause, ctx)?;
+            parse_quote!(Some(Box::new(#cause)))
+        }
+    };
+
+    let variant = ident(name);
+    Ok(parse_quote!(Error::#variant { message: #message, source: #source }))
+}
+
+/// `return v`, in the closure of a `try` body the value is passed on as `Some(v)`
+pub fn r_return(v: syn::Expr, ctx: &Ctx) -> syn::Expr {
+    match (ctx.try_body, ctx.result_errors) {
+        (false, _) => parse_quote!(return #v),
+        (true, true) => parse_quote!(return Ok(Some(#v))),
+        (true, false) => parse_quote!(return Some(#v)),
+    }
+}
+
+/// passes the caught error `e` on to the caller
+fn propagate(e: syn::Expr, ctx: &Ctx) -> syn::Expr {
+    if !ctx.result_errors {
+        parse_quote!(std::panic::resume_unwind(#e))
+    } else if ctx.ret_result || ctx.try_body {
+        parse_quote!(return Err(#e))
+    } else {
+        // uncaught exceptions end the program
+        parse_quote!(panic!("{}", #e))
+    }
+}
+
+/// bare `raise` in an `except` clause
+pub fn r_reraise(ctx: &Ctx) -> TResult<syn::Expr> {
+    let caught = ctx.caught.as_deref().ok_or(todo_link!())?;
+    Ok(propagate(crate::path(caught), ctx))
+}
+
+/// `try`/`except`/`else`/`finally`
+///
+/// the body runs in a closure, which `?` and `raise` leave early, and its result is matched
+/// against the `except` clauses. with `finally` all of this runs in another closure so the
+/// `finally` block runs before anything is returned or propagated. without `result_errors`
+/// exceptions are panics, which are caught by `catch_unwind`. `return`, `break` and
+/// `continue` leave the closure with `Some(_)`, the jump happens after the call
+pub fn r_try(node: &StmtTry, ctx: &mut Ctx) -> TResult<Vec<syn::Stmt>> {
+    let StmtTry {
+        range,
+        body,
+        handlers,
+        orelse,
+        finalbody,
+    } = node;
+    let handlers = handlers
+        .iter()
+        .map(|h| h.as_except_handler().unwrap())
+        .collect::<Vec<_>>();
+
+    if finalbody.is_empty() {
+        return r_try_except(range, body, &handlers, orelse, ctx);
+    }
+
+    let returns =
+        has_return(body) || handlers.iter().any(|h| has_return(&h.body)) || has_return(orelse);
+    let jumps = crate::jumps(body, true)
+        || handlers.iter().any(|h| crate::jumps(&h.body, true))
+        || crate::jumps(orelse, true);
+    if returns && jumps {
+        return Err(todo_link!(
+            "a `try` with `return` and `break` or `continue` can't be transpiled"
+        ));
+    }
+    let outer = std::mem::replace(&mut ctx.try_body, true);
+    let outer_jumps = std::mem::replace(&mut ctx.try_jumps, jumps);
+    let inner = r_try_except(range, body, &handlers, orelse, ctx);
+    ctx.try_body = outer;
+    ctx.try_jumps = outer_jumps;
+    let outcome = closure(inner?, returns || jumps, false, ctx);
+
+    let mut stmts: Vec<syn::Stmt> = vec![parse_quote!(let outcome = #outcome;)];
+    stmts.extend(crate::r_body(finalbody, ctx)?);
+    let outcome: syn::Expr = if !ctx.result_errors {
+        parse_quote!(outcome.unwrap_or_else(|e| std::panic::resume_unwind(e)))
+    } else if ctx.ret_result || ctx.try_body {
+        parse_quote!(outcome?)
+    } else {
+        parse_quote!(outcome.unwrap())
+    };
+    if returns {
+        let ret = r_return(parse_quote!(value), ctx);
+        stmts.push(parse_quote!(if let Some(value) = #outcome {
+            #ret;
+        }));
+    } else if jumps {
+        let (brk, cont) = (crate::r_jump(true, ctx), crate::r_jump(false, ctx));
+        stmts.push(parse_quote!(match #outcome {
+            Some(std::ops::ControlFlow::Break(())) => #brk,
+            Some(std::ops::ControlFlow::Continue(())) => #cont,
+            None => {}
+        }));
+    } else {
+        stmts.push(parse_quote!(#outcome;));
+    }
+    Ok(stmts)
+}
+
+/// `try` with `except` and `else` clauses, the result of the body is matched
+fn r_try_except(
+    range: &rustpython_parser::text_size::TextRange,
+    body: &[Stmt],
+    handlers: &[&ExceptHandlerExceptHandler],
+    orelse: &[Stmt],
+    ctx: &mut Ctx,
+) -> TResult<Vec<syn::Stmt>> {
+    let returns = has_return(body);
+    let jumps = crate::jumps(body, true);
+    if returns && jumps {
+        return Err(todo_link!(
+            "a `try` with `return` and `break` or `continue` can't be transpiled"
+        ));
+    }
+    // the end of the body is not reachable
+    let diverges = matches!(
+        body.last(),
+        Some(Stmt::Return(_) | Stmt::Raise(_) | Stmt::Break(_) | Stmt::Continue(_))
+    );
+    let outer = std::mem::replace(&mut ctx.try_body, true);
+    let outer_jumps = std::mem::replace(&mut ctx.try_jumps, jumps);
+    let run = crate::r_body(body, ctx);
+    ctx.try_body = outer;
+    ctx.try_jumps = outer_jumps;
+    let run = closure(run?, returns || jumps, diverges, ctx);
+
+    let orelse: syn::Expr = if diverges {
+        parse_quote!(unreachable!())
+    } else {
+        crate::block_expr(crate::r_body(orelse, ctx)?)
+    };
+    let mut arms: Vec<syn::Arm> = if returns {
+        let ret = r_return(parse_quote!(value), ctx);
+        vec![
+            parse_quote!(Ok(Some(value)) => #ret,),
+            parse_quote!(Ok(None) => #orelse,),
+        ]
+    } else if jumps {
+        let (brk, cont) = (crate::r_jump(true, ctx), crate::r_jump(false, ctx));
+        vec![
+            parse_quote!(Ok(Some(std::ops::ControlFlow::Break(()))) => #brk,),
+            parse_quote!(Ok(Some(std::ops::ControlFlow::Continue(()))) => #cont,),
+            parse_quote!(Ok(None) => #orelse,),
+        ]
+    } else {
+        vec![parse_quote!(Ok(()) => #orelse)]
+    };
+
+    if !ctx.result_errors && handlers.iter().any(|h| h.type_.is_some()) {
+        ctx.warn(
+            "Try",
+            *range,
+            "without `result_errors` exceptions are panics, the first `except` catches all of them",
+        );
+    }
+
+    let mut catches_all = false;
+    let mut caught = vec![];
+    for h in handlers {
+        let mut reraises = false;
+        visit_stmts(&h.body, &mut |s| {
+            reraises |= matches!(s, Stmt::Raise(StmtRaise { exc: None, .. }));
+        });
+        let name = h
+            .name
+            .as_ref()
+            .map(|n| n.to_string())
+            .or(reraises.then(|| "error".to_string()));
+        let binding: syn::Pat = match &name {
+            Some(n) => crate::pat_ident(n, false),
+            None => parse_quote!(_),
+        };
+
+        let types = if ctx.result_errors {
+            handled(h.type_.as_deref())
+        } else {
+            None
+        };
+        let pat: syn::Pat = match types {
+            None => {
+                catches_all = true;
+                binding
+            }
+            Some(types) => {
+                let variants = types
+                    .iter()
+                    .flat_map(|t| ctx.exceptions.caught_by(t))
+                    .collect::<Vec<_>>();
+                caught.extend(variants.iter().map(|v| v.to_string()));
+                // every variant of `Error` is handled
+                catches_all = ctx.exceptions.variants.iter().all(|v| caught.contains(v));
+                let variants = variants.into_iter().map(ident).collect::<Vec<_>>();
+                match name {
+                    Some(_) if variants.len() > 1 => {
+                        parse_quote!(#binding @ (#(Error::#variants { .. })|*))
+                    }
+                    Some(_) => parse_quote!(#binding @ #(Error::#variants { .. })*),
+                    None => parse_quote!(#(Error::#variants { .. })|*),
+                }
+            }
+        };
+
+        let outer = std::mem::replace(&mut ctx.caught, name);
+        let handler = crate::r_body(&h.body, ctx);
+        ctx.caught = outer;
+        let handler = crate::block_expr(handler?);
+        arms.push(parse_quote!(Err(#pat) => #handler));
+        if catches_all {
+            // later handlers are unreachable
+            break;
+        }
+    }
+    if !catches_all {
+        let e = propagate(parse_quote!(e), ctx);
+        arms.push(parse_quote!(Err(e) => #e,));
+    }
+
+    Ok(vec![syn::Stmt::Expr(
+        parse_quote!(match #run {
+            #(#arms)*
+        }),
+        None,
+    )])
+}
+
+/// the statements run in a closure which is called right away, `diverges` if its end is
+/// not reachable
+fn closure(stmts: Vec<syn::Stmt>, returns: bool, diverges: bool, ctx: &Ctx) -> syn::Expr {
+    let end: Option<syn::Expr> = match (ctx.result_errors, returns) {
+        _ if diverges => None,
+        (true, true) => Some(parse_quote!(Ok(None))),
+        (true, false) => Some(parse_quote!(Ok(()))),
+        (false, true) => Some(parse_quote!(None)),
+        (false, false) => None,
+    };
+    match (ctx.result_errors, returns) {
+        (true, true) => parse_quote!((|| -> Result<Option<_>, Error> {
+            #(#stmts)*
+            #end
+        })()),
+        (true, false) => parse_quote!((|| -> Result<(), Error> {
+            #(#stmts)*
+            #end
+        })()),
+        (false, true) => parse_quote!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(
+            || -> Option<_> {
+                #(#stmts)*
+                #end
+            }
+        ))),
+        (false, false) => parse_quote!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(
+            || {
+                #(#stmts)*
+            }
+        ))),
+    }
+}
+
+fn has_return(body: &[Stmt]) -> bool {
+    let mut returns = false;
+    visit_stmts(body, &mut |s| returns |= matches!(s, Stmt::Return(_)));
+    returns
+}
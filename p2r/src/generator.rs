@@ -0,0 +1,348 @@
+//! generators become functions which return `impl Iterator`
+//!
+//! loops over an iterable which only yield become `map` and `flat_map`, other loops become a
+//! `std::iter::from_fn` closure which owns the state of the generator and returns at each
+//! `yield`, consecutive `yield`s become a `chain`
+
+use crate::infer::{is_generator, visit_exprs};
+use crate::scope::target_names;
+use crate::{method_call, r_body, r_e, r_s, r_target, todo_link, Ctx, TResult, TranspileError};
+use rustpython_parser::ast::{
+    Expr, ExprYield, ExprYieldFrom, Stmt, StmtAnnAssign, StmtAssign, StmtFor, StmtIf, StmtWhile,
+};
+use syn::parse_quote;
+
+/// how the closure of `from_fn` returns the yielded values
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Yields {
+    /// `yield v` -> `return Some(v)`
+    Item,
+    /// `yield from v` -> `return Some(v.into_iter())` and the iterators are flattened
+    Iter,
+    /// both, the iterators are boxed
+    Boxed,
+}
+
+/// body of a generator function, it evaluates to an iterator
+pub fn r_generator(body: &[Stmt], ctx: &mut Ctx) -> TResult<Vec<syn::Stmt>> {
+    let Some(first) = body.iter().position(has_yield) else {
+        return Err(todo_link!());
+    };
+    let (prelude, rest) = body.split_at(first);
+    let mut stmts = r_stmts(prelude, ctx)?;
+
+    // `yield a` and `yield from b` one after another
+    if rest.iter().all(|s| yielded(s).is_some()) {
+        let yields = rest.iter().filter_map(yielded).collect::<Vec<_>>();
+        let iter = if yields.iter().all(|y| matches!(y, Expr::Yield(_))) {
+            let values = yields
+                .iter()
+                .map(|y| r_yield_value(y, ctx))
+                .collect::<TResult<Vec<_>>>()?;
+            parse_quote!([#(#values),*].into_iter())
+        } else {
+            let mut iter: Option<syn::Expr> = None;
+            for y in yields {
+                let value = r_yield_value(y, ctx)?;
+                let next: syn::Expr = match y {
+                    Expr::Yield(_) => parse_quote!(std::iter::once(#value)),
+                    _ => value,
+                };
+                iter = Some(match iter {
+                    None if matches!(y, Expr::Yield(_)) => next,
+                    None => method_call(next, "into_iter", []),
+                    Some(iter) => method_call(iter, "chain", [next]),
+                });
+            }
+            iter.ok_or(todo_link!())?
+        };
+        stmts.push(syn::Stmt::Expr(iter, None));
+        return Ok(stmts);
+    }
+
+    let [lp, epilogue @ ..] = rest else {
+        return Err(todo_link!());
+    };
+    if epilogue.iter().any(has_yield) {
+        return Err(todo_link!(
+            "only generators which yield in a single loop can be translated"
+        ));
+    }
+
+    // `for x in xs: yield f(x)` -> `xs.into_iter().map(|x| f(x))`
+    if let Stmt::For(StmtFor {
+        target,
+        iter,
+        body,
+        orelse,
+        ..
+    }) = lp
+        && orelse.is_empty()
+        && epilogue.is_empty()
+        && let [s] = body.as_slice()
+        && let Some(y) = yielded(s)
+    {
+        let iter = method_call(r_e(iter, ctx)?, "into_iter", []);
+        let pat = r_target(target, ctx)?;
+        ctx.scopes.push();
+        ctx.scopes.declare(target);
+        let value = r_yield_value(y, ctx);
+        ctx.scopes.pop();
+        let value = value?;
+        let method = match y {
+            Expr::Yield(_) => "map",
+            _ => "flat_map",
+        };
+        stmts.push(syn::Stmt::Expr(
+            method_call(iter, method, [parse_quote!(move |#pat| #value)]),
+            None,
+        ));
+        return Ok(stmts);
+    }
+
+    let (body, orelse) = match lp {
+        Stmt::For(StmtFor { body, orelse, .. }) | Stmt::While(StmtWhile { body, orelse, .. }) => {
+            (body, orelse)
+        }
+        _ => {
+            return Err(todo_link!(
+                "only generators which yield in a single loop can be translated"
+            ));
+        }
+    };
+    if !orelse.is_empty() {
+        return Err(todo_link!());
+    }
+
+    // `while c: a; yield v; b` -> `b` runs when the iterator is resumed
+    let split = body.iter().position(|s| yielded(s).is_some());
+    let (before, after) = match split {
+        Some(i) => (&body[..i], &body[i + 1..]),
+        None => (body.as_slice(), &[][..]),
+    };
+    if split.is_some() && (before.iter().any(has_yield) || after.iter().any(has_yield)) {
+        return Err(todo_link!(
+            "`yield` has to be the last statement of the loop or in its own line"
+        ));
+    }
+    if split.is_none() && !yields_last(body) {
+        return Err(todo_link!(
+            "`yield` has to be the last statement of the loop or in its own line"
+        ));
+    }
+    if jumps(after) {
+        return Err(todo_link!());
+    }
+    if !after.is_empty() {
+        let mut bound = vec![];
+        if let Stmt::For(f) = lp {
+            target_names(&f.target, &mut bound);
+        }
+        for s in before {
+            match s {
+                Stmt::Assign(StmtAssign { targets, .. }) => {
+                    targets.iter().for_each(|t| target_names(t, &mut bound));
+                }
+                Stmt::AnnAssign(StmtAnnAssign { target, .. }) => target_names(target, &mut bound),
+                _ => {}
+            }
+        }
+        let mut used = false;
+        visit_exprs(after, &mut |e| {
+            used |= e
+                .as_name_expr()
+                .is_some_and(|n| bound.contains(&n.id.as_str()));
+        });
+        if used {
+            return Err(todo_link!(
+                "statements after `yield` can't use the variables of the loop"
+            ));
+        }
+    }
+
+    let mode = mode(body);
+    let outer = ctx.generator.replace(mode);
+    let closure = r_closure(lp, before, split.map(|i| &body[i]), after, epilogue, ctx);
+    ctx.generator = outer;
+    let (setup, closure) = closure?;
+    stmts.extend(setup);
+
+    let mut iter: syn::Expr = parse_quote!(std::iter::from_fn(move || #closure));
+    if mode != Yields::Item {
+        iter = method_call(iter, "flatten", []);
+    }
+    // the loop would run again after it ended
+    if !epilogue.is_empty() || jumps(body) {
+        iter = method_call(iter, "fuse", []);
+    }
+    stmts.push(syn::Stmt::Expr(iter, None));
+    Ok(stmts)
+}
+
+/// the closure of `from_fn` and the statements which set up its state
+fn r_closure(
+    lp: &Stmt,
+    before: &[Stmt],
+    yield_stmt: Option<&Stmt>,
+    after: &[Stmt],
+    epilogue: &[Stmt],
+    ctx: &mut Ctx,
+) -> TResult<(Vec<syn::Stmt>, syn::Block)> {
+    let mut setup = vec![];
+    let mut stmts: Vec<syn::Stmt> = vec![];
+    if !after.is_empty() {
+        setup.push(parse_quote!(let mut resumed = false;));
+        let after = r_body(after, ctx)?;
+        stmts.push(parse_quote!(if resumed {
+            resumed = false;
+            #(#after)*
+        }));
+    }
+
+    let target = match lp {
+        Stmt::For(f) => Some(f.target.as_ref()),
+        _ => None,
+    };
+    ctx.scopes.push();
+    if let Some(target) = target {
+        ctx.scopes.declare(target);
+    }
+    let body = r_loop_body(before, yield_stmt, !after.is_empty(), ctx);
+    ctx.scopes.pop();
+    let body = body?;
+    // the closure returns in every iteration
+    let once = yield_stmt.is_some() && !jumps(before);
+
+    match lp {
+        Stmt::For(StmtFor { target, iter, .. }) => {
+            let iter = method_call(r_e(iter, ctx)?, "into_iter", []);
+            setup.push(parse_quote!(let mut iter = #iter;));
+            let pat = r_target(target, ctx)?;
+            stmts.push(if once {
+                parse_quote!(if let Some(#pat) = iter.next() { #(#body)* })
+            } else {
+                parse_quote!(for #pat in iter.by_ref() { #(#body)* })
+            });
+        }
+        Stmt::While(StmtWhile { test, .. }) => {
+            let test = r_e(test, ctx)?;
+            stmts.push(if once {
+                parse_quote!(if #test { #(#body)* })
+            } else {
+                parse_quote!(while #test { #(#body)* })
+            });
+        }
+        _ => return Err(todo_link!()),
+    }
+    stmts.extend(r_stmts(epilogue, ctx)?);
+    stmts.push(syn::Stmt::Expr(parse_quote!(None), None));
+    Ok((setup, parse_quote!({ #(#stmts)* })))
+}
+
+/// body of the loop in the closure, `yield_stmt` is the `yield` statement between `before` and
+/// the statements which run when the iterator is resumed
+fn r_loop_body(
+    before: &[Stmt],
+    yield_stmt: Option<&Stmt>,
+    resumes: bool,
+    ctx: &mut Ctx,
+) -> TResult<Vec<syn::Stmt>> {
+    let mut stmts = r_stmts(before, ctx)?;
+    if let Some(y) = yield_stmt {
+        if resumes {
+            stmts.push(parse_quote!(resumed = true;));
+        }
+        stmts.extend(r_s(y, ctx)?);
+    }
+    Ok(stmts)
+}
+
+/// `yield v` in the closure of `from_fn`
+pub fn r_yield(node: &Expr, ctx: &mut Ctx) -> TResult<syn::Expr> {
+    let Some(mode) = ctx.generator else {
+        return Err(todo_link!());
+    };
+    let value = r_yield_value(node, ctx)?;
+    let value: syn::Expr = match (mode, node) {
+        (Yields::Item, _) => value,
+        (Yields::Iter, _) => method_call(value, "into_iter", []),
+        (Yields::Boxed, Expr::Yield(_)) => {
+            parse_quote!(Box::new(std::iter::once(#value)) as Box<dyn Iterator<Item = _>>)
+        }
+        (Yields::Boxed, _) => {
+            let value = method_call(value, "into_iter", []);
+            parse_quote!(Box::new(#value) as Box<dyn Iterator<Item = _>>)
+        }
+    };
+    Ok(parse_quote!(return Some(#value)))
+}
+
+/// the yielded value or iterable
+fn r_yield_value(node: &Expr, ctx: &mut Ctx) -> TResult<syn::Expr> {
+    match node {
+        Expr::Yield(ExprYield { value: Some(v), .. }) => r_e(v, ctx),
+        Expr::Yield(ExprYield { value: None, .. }) => Ok(parse_quote!(())),
+        Expr::YieldFrom(ExprYieldFrom { value, .. }) => r_e(value, ctx),
+        _ => Err(todo_link!()),
+    }
+}
+
+fn r_stmts(body: &[Stmt], ctx: &mut Ctx) -> TResult<Vec<syn::Stmt>> {
+    Ok(body
+        .iter()
+        .map(|s| r_s(s, ctx))
+        .collect::<TResult<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .collect())
+}
+
+/// `yield v` or `yield from v` as a statement
+fn yielded(s: &Stmt) -> Option<&Expr> {
+    match s {
+        Stmt::Expr(e) if matches!(e.value.as_ref(), Expr::Yield(_) | Expr::YieldFrom(_)) => {
+            Some(&e.value)
+        }
+        _ => None,
+    }
+}
+
+fn has_yield(s: &Stmt) -> bool {
+    is_generator(std::slice::from_ref(s))
+}
+
+/// every `yield` in `body` is the last statement which runs in an iteration of the loop
+fn yields_last(body: &[Stmt]) -> bool {
+    let Some((last, init)) = body.split_last() else {
+        return true;
+    };
+    !init.iter().any(has_yield)
+        && match last {
+            s if yielded(s).is_some() => true,
+            Stmt::If(StmtIf { body, orelse, .. }) => yields_last(body) && yields_last(orelse),
+            s => !has_yield(s),
+        }
+}
+
+/// `break` or `continue` of the loop in `body`
+fn jumps(body: &[Stmt]) -> bool {
+    body.iter().any(|s| match s {
+        Stmt::Break(_) | Stmt::Continue(_) => true,
+        Stmt::If(StmtIf { body, orelse, .. }) => jumps(body) || jumps(orelse),
+        _ => false,
+    })
+}
+
+fn mode(body: &[Stmt]) -> Yields {
+    let (mut items, mut iters) = (0, 0);
+    visit_exprs(body, &mut |e| match e {
+        Expr::Yield(_) => items += 1,
+        Expr::YieldFrom(_) => iters += 1,
+        _ => {}
+    });
+    match (items, iters) {
+        (_, 0) => Yields::Item,
+        (0, 1) => Yields::Iter,
+        _ => Yields::Boxed,
+    }
+}
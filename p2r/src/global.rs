@@ -0,0 +1,466 @@
+//! variables of the module which functions assign with `global` become statics in a `Mutex`,
+//! nested functions which assign variables of the enclosing function with `nonlocal` become
+//! closures which borrow them mutably
+//!
+//! globals which are `Copy` are read without keeping the `Mutex` locked, other globals are
+//! locked until the end of the statement which uses them. a statement which uses them twice
+//! (e.g. `xs.append(len(xs))`) locks them once and binds them to a reference, calls of functions
+//! which use a locked global are evaluated before the statement. loops iterate over a copy of the global,
+//! which is not locked while the loop runs
+
+use crate::infer::{visit_expr, visit_exprs, visit_stmts, Ty};
+use crate::params::names;
+use crate::scope::{declared_names, Scopes};
+use crate::{
+    assign, binary, block_expr, ident, pat_ident, r_annotation, r_e, r_fn, r_o_assign, r_owned,
+    stmt, stmt_kind, todo_link, Ctx, TResult, TranspileError,
+};
+use rustpython_parser::ast::{
+    Constant, Expr, ExprCall, ExprConstant, ExprUnaryOp, Operator, Ranged, Stmt, StmtAnnAssign,
+    StmtAssign, StmtAugAssign, StmtFunctionDef, StmtGlobal, UnaryOp,
+};
+use rustpython_parser::text_size::{TextRange, TextSize};
+use std::collections::{HashMap, HashSet};
+use syn::parse_quote;
+
+/// a variable of the module which is assigned with `global`
+#[derive(Debug, Clone)]
+pub struct Global {
+    /// start of the first assignment at the top level of the module, which defines the static
+    start: TextSize,
+    /// the type is not `Copy`, its value can't be read without keeping the `Mutex` locked
+    owned: bool,
+    /// type of the value of the first assignment
+    ty: Ty,
+    /// functions of the module which use the global, directly or through calls
+    users: HashSet<String>,
+}
+
+/// names of `global` statements which are assigned at the top level of the module, `locals` are
+/// the types of the variables of the module
+pub fn collect(body: &[Stmt], locals: &HashMap<String, Ty>) -> HashMap<String, Global> {
+    let mut names = HashSet::new();
+    global_names(body, &mut names);
+
+    let mut globals = HashMap::new();
+    for s in body {
+        if let Some((name, _, _)) = assignment(s)
+            && names.contains(name)
+        {
+            globals.entry(name.to_string()).or_insert(Global {
+                start: s.range().start(),
+                owned: locals.get(name).is_some_and(Ty::is_owned),
+                ty: locals.get(name).cloned().unwrap_or_default(),
+                users: HashSet::new(),
+            });
+        }
+    }
+
+    let fns = body
+        .iter()
+        .filter_map(|s| match s {
+            Stmt::FunctionDef(d) => Some((d.name.as_str(), used_names(&d.body))),
+            Stmt::AsyncFunctionDef(d) => Some((d.name.as_str(), used_names(&d.body))),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    for (name, global) in &mut globals {
+        // callers of functions which use the global use it as well
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (f, used) in &fns {
+                if !global.users.contains(*f)
+                    && used.iter().any(|n| n == name || global.users.contains(n))
+                {
+                    global.users.insert(f.to_string());
+                    changed = true;
+                }
+            }
+        }
+    }
+    globals
+}
+
+/// names which are read, assigned or declared `global` in `body`, including called functions
+fn used_names(body: &[Stmt]) -> HashSet<String> {
+    let mut used = HashSet::new();
+    let mut declared = vec![];
+    let mut add = |e: &Expr| {
+        if let Some(n) = e.as_name_expr() {
+            used.insert(n.id.to_string());
+        }
+    };
+    visit_exprs(body, &mut add);
+    visit_stmts(body, &mut |s| match s {
+        Stmt::Assign(StmtAssign { targets, .. }) => {
+            targets.iter().for_each(|t| visit_expr(t, &mut add))
+        }
+        Stmt::AugAssign(StmtAugAssign { target, .. })
+        | Stmt::AnnAssign(StmtAnnAssign { target, .. }) => visit_expr(target, &mut add),
+        Stmt::Global(StmtGlobal { names, .. }) => {
+            declared.extend(names.iter().map(|n| n.to_string()));
+        }
+        _ => {}
+    });
+    used.extend(declared);
+    used
+}
+
+/// names of the `global` statements in the functions and methods of `body`
+fn global_names(body: &[Stmt], names: &mut HashSet<String>) {
+    visit_stmts(body, &mut |s| match s {
+        Stmt::Global(StmtGlobal { names: n, .. }) => {
+            names.extend(n.iter().map(|n| n.to_string()));
+        }
+        Stmt::FunctionDef(d) => global_names(&d.body, names),
+        Stmt::AsyncFunctionDef(d) => global_names(&d.body, names),
+        Stmt::ClassDef(c) => global_names(&c.body, names),
+        _ => {}
+    });
+}
+
+/// `name = value` or `name: annotation = value`
+fn assignment(node: &Stmt) -> Option<(&str, Option<&Expr>, &Expr)> {
+    match node {
+        Stmt::Assign(StmtAssign { targets, value, .. }) if targets.len() == 1 => {
+            Some((targets[0].as_name_expr()?.id.as_str(), None, value))
+        }
+        Stmt::AnnAssign(StmtAnnAssign {
+            target,
+            annotation,
+            value: Some(value),
+            ..
+        }) => Some((target.as_name_expr()?.id.as_str(), Some(annotation), value)),
+        _ => None,
+    }
+}
+
+/// `global name`, the statics are defined by the assignments at the top level of the module
+pub fn r_global(node: &StmtGlobal, ctx: &Ctx) -> TResult<Vec<syn::Stmt>> {
+    if node
+        .names
+        .iter()
+        .any(|n| !ctx.globals.contains_key(n.as_str()))
+    {
+        return Err(todo_link!(
+            "`global` variables have to be assigned at the top level of the module"
+        ));
+    }
+    Ok(vec![])
+}
+
+/// the first assignment of a global at the top level of the module defines its static
+pub fn r_static(node: &Stmt, ctx: &mut Ctx) -> Option<TResult<syn::Item>> {
+    let (name, annotation, value) = assignment(node)?;
+    if ctx.globals.get(name).map(|g| g.start) != Some(node.range().start()) {
+        return None;
+    }
+    Some(r_static_inner(name, annotation, value, ctx))
+}
+
+fn r_static_inner(
+    name: &str,
+    annotation: Option<&Expr>,
+    value: &Expr,
+    ctx: &mut Ctx,
+) -> TResult<syn::Item> {
+    let t = match annotation {
+        Some(a) => r_annotation(a)?,
+        None => ctx
+            .types
+            .ty(value, &ctx.types.locals)
+            .to_syn()
+            .ok_or(todo_link!(
+                "the type of the global variable is unknown, annotate it"
+            ))?,
+    };
+    let name = static_name(name);
+    let v = r_owned(value, ctx)?;
+
+    let literal = match value {
+        Expr::UnaryOp(ExprUnaryOp {
+            op: UnaryOp::USub,
+            operand,
+            ..
+        }) => operand,
+        v => v,
+    };
+    Ok(match literal {
+        // `Mutex::new` is `const`
+        Expr::Constant(ExprConstant {
+            value: Constant::Int(_) | Constant::Float(_) | Constant::Bool(_),
+            ..
+        }) => parse_quote! {
+            static #name: std::sync::Mutex<#t> = std::sync::Mutex::new(#v);
+        },
+        _ => parse_quote! {
+            static #name: std::sync::LazyLock<std::sync::Mutex<#t>> =
+                std::sync::LazyLock::new(|| std::sync::Mutex::new(#v));
+        },
+    })
+}
+
+fn static_name(name: &str) -> syn::Ident {
+    ident(&name.to_uppercase())
+}
+
+/// `name` refers to the static of a global in the function which is transpiled
+pub fn is_static(name: &str, ctx: &Ctx) -> bool {
+    ctx.globals.contains_key(name) && ctx.scopes.is_global(name)
+}
+
+/// the type of `e` if it is a global, `None` otherwise
+pub fn ty(e: &Expr, ctx: &Ctx) -> Option<Ty> {
+    let name = e.as_name_expr()?.id.as_str();
+    is_static(name, ctx).then(|| ctx.globals[name].ty.clone())
+}
+
+/// the global `name` in the locked `Mutex`
+fn r_static_place(name: &str, ctx: &Ctx) -> syn::Expr {
+    if ctx.locked.iter().any(|l| l == name) {
+        // the statement locked it once
+        let name = ident(name);
+        return parse_quote!(*#name);
+    }
+    let name = static_name(name);
+    parse_quote!(*#name.lock().unwrap())
+}
+
+/// the value of the global `name`
+pub fn r_static_value(name: &str, ctx: &Ctx) -> syn::Expr {
+    if ctx.globals[name].owned {
+        return r_static_place(name, ctx);
+    }
+    if ctx.globals[name].ty == Ty::Unknown {
+        return r_static_clone(name);
+    }
+    // the guard is dropped at the end of the closure
+    let name = static_name(name);
+    parse_quote!(#name.lock().map(|v| *v).unwrap())
+}
+
+/// a copy of the value of the global `name`, the guard is dropped before the copy is used, e.g.
+/// while a loop iterates over it
+pub fn r_static_clone(name: &str) -> syn::Expr {
+    let name = static_name(name);
+    parse_quote!(#name.lock().map(|v| v.clone()).unwrap())
+}
+
+/// `name = value` or `name += value` of a global
+pub fn r_assign(
+    name: &str,
+    op: Option<&Operator>,
+    value: &Expr,
+    ctx: &mut Ctx,
+) -> TResult<Vec<syn::Stmt>> {
+    let target = r_static_place(name, ctx);
+    let v = r_owned(value, ctx)?;
+    let mut reads = false;
+    visit_expr(value, &mut |e| {
+        reads |= e.as_name_expr().is_some_and(|n| n.id.as_str() == name);
+    });
+    let assign = |v| -> TResult<syn::Expr> {
+        Ok(match op {
+            None => assign(target.clone(), v),
+            Some(op) => binary(target.clone(), r_o_assign(op)?, v),
+        })
+    };
+
+    if reads && ctx.globals[name].owned {
+        // the `Mutex` would still be locked by reading the value
+        let assign = assign(parse_quote!(value))?;
+        return Ok(vec![stmt(parse_quote!({
+            let value = #v;
+            #assign;
+        }))]);
+    }
+    Ok(vec![stmt(assign(v)?)])
+}
+
+/// transpiles `node` with `r_s`, owned globals which a simple statement uses more than once
+/// are locked once: `{ let xs = &mut *XS.lock().unwrap(); (*xs).push((*xs).len()); }`, calls
+/// of functions which use a global that the statement locks are evaluated before it:
+/// `let last_value = last(); (*XS.lock().unwrap()).push(last_value);`
+pub fn r_locking(
+    node: &Stmt,
+    ctx: &mut Ctx,
+    r_s: impl FnOnce(&Stmt, &mut Ctx) -> TResult<Vec<syn::Stmt>>,
+) -> TResult<Vec<syn::Stmt>> {
+    let (locked, hoisted) = locked(node, ctx);
+    if locked.is_empty() && hoisted.is_empty() {
+        return r_s(node, ctx);
+    }
+    let mut stmts: Vec<syn::Stmt> = vec![];
+    let mut names = vec![];
+    for (i, call) in hoisted.iter().enumerate() {
+        let f = call.func.as_name_expr().unwrap().id.as_str();
+        let name = match i {
+            0 => format!("{f}_value"),
+            i => format!("{f}_value{}", i + 1),
+        };
+        let local = ident(&name);
+        let value = r_e(&Expr::Call(call.clone()), ctx)?;
+        stmts.push(parse_quote!(let #local = #value;));
+        names.push((call.range, name));
+    }
+    let binds = locked
+        .iter()
+        .map(|name| -> syn::Stmt {
+            let local = ident(name);
+            let name = static_name(name);
+            parse_quote!(let #local = &mut *#name.lock().unwrap();)
+        })
+        .collect::<Vec<_>>();
+    let outer = std::mem::replace(&mut ctx.locked, locked);
+    let outer_hoisted = std::mem::replace(&mut ctx.hoisted, names);
+    let transpiled = r_s(node, ctx);
+    ctx.locked = outer;
+    ctx.hoisted = outer_hoisted;
+    if binds.is_empty() {
+        stmts.extend(transpiled?);
+        return Ok(stmts);
+    }
+
+    stmts.extend(transpiled?.into_iter().map(|s| match s {
+        // the guard can't outlive the value of a `let`
+        syn::Stmt::Local(mut local) => {
+            if let Some(init) = &mut local.init {
+                let mut block = binds.clone();
+                block.push(syn::Stmt::Expr((*init.expr).clone(), None));
+                *init.expr = block_expr(block);
+            }
+            syn::Stmt::Local(local)
+        }
+        item @ syn::Stmt::Item(_) => item,
+        s => {
+            let mut block = binds.clone();
+            block.push(s);
+            stmt(block_expr(block))
+        }
+    }));
+    Ok(stmts)
+}
+
+/// owned globals which the simple statement `node` uses more than once and the calls of
+/// functions which use a global that `node` locks, warns about calls which can't be evaluated
+/// before the statement
+fn locked(node: &Stmt, ctx: &mut Ctx) -> (Vec<String>, Vec<ExprCall>) {
+    let exprs: Vec<&Expr> = match node {
+        Stmt::Expr(e) => vec![&e.value],
+        Stmt::Return(r) => r.value.as_deref().into_iter().collect(),
+        // assignments of the global itself are handled by `r_assign`
+        Stmt::Assign(StmtAssign { targets, value, .. }) => targets
+            .iter()
+            .filter(|t| !t.is_name_expr())
+            .chain([value.as_ref()])
+            .collect(),
+        Stmt::AugAssign(StmtAugAssign { target, value, .. }) => [target.as_ref()]
+            .into_iter()
+            .filter(|t| !t.is_name_expr())
+            .chain([value.as_ref()])
+            .collect(),
+        Stmt::AnnAssign(StmtAnnAssign {
+            value: Some(value), ..
+        }) => vec![value.as_ref()],
+        _ => return (vec![], vec![]),
+    };
+
+    let mut uses: Vec<(String, usize)> = vec![];
+    // calls of functions of the module
+    let mut calls: Vec<ExprCall> = vec![];
+    // expressions which are evaluated conditionally or repeatedly
+    let mut deferred: Vec<TextRange> = vec![];
+    for e in exprs {
+        visit_expr(e, &mut |e| match e {
+            Expr::Name(n) if is_static(&n.id, ctx) && ctx.globals[n.id.as_str()].owned => {
+                match uses.iter_mut().find(|(u, _)| u == n.id.as_str()) {
+                    Some((_, count)) => *count += 1,
+                    None => uses.push((n.id.to_string(), 1)),
+                }
+            }
+            Expr::Call(c) if c.func.is_name_expr() => calls.push(c.clone()),
+            Expr::BoolOp(_)
+            | Expr::IfExp(_)
+            | Expr::Lambda(_)
+            | Expr::ListComp(_)
+            | Expr::SetComp(_)
+            | Expr::DictComp(_)
+            | Expr::GeneratorExp(_) => deferred.push(e.range()),
+            _ => {}
+        });
+    }
+
+    let inside =
+        |r: TextRange, outer: &[TextRange]| outer.iter().any(|o| *o != r && o.contains_range(r));
+    let mut hoisted: Vec<ExprCall> = vec![];
+    for (name, _) in &uses {
+        let users = &ctx.globals[name.as_str()].users;
+        let mut blocked = None;
+        for c in &calls {
+            let f = c.func.as_name_expr().unwrap().id.as_str();
+            if !users.contains(f) {
+                continue;
+            }
+            if inside(c.range, &deferred) {
+                blocked = Some(f.to_string());
+            } else if !hoisted.iter().any(|h| h.range == c.range) {
+                hoisted.push(c.clone());
+            }
+        }
+        if let Some(f) = blocked {
+            let message = format!(
+                "`{f}` uses the global `{name}`, which is locked by this statement, the call \
+                 deadlocks"
+            );
+            ctx.warn(stmt_kind(node), node.range(), message);
+        }
+    }
+    // calls in the arguments of hoisted calls are evaluated with them
+    let ranges = hoisted.iter().map(|c| c.range).collect::<Vec<_>>();
+    hoisted.retain(|c| !inside(c.range, &ranges));
+
+    let locked = uses
+        .into_iter()
+        .filter(|(_, count)| *count > 1)
+        .map(|(name, _)| name)
+        .collect();
+    (locked, hoisted)
+}
+
+/// a nested function with `nonlocal` statements
+pub fn is_closure(def: &StmtFunctionDef) -> bool {
+    declared_names(&def.body, false).next().is_some()
+}
+
+/// `def f(a): nonlocal x; ...` -> `let mut f = |a| { ... };`
+pub fn r_closure(def: &StmtFunctionDef, ctx: &mut Ctx) -> TResult<Vec<syn::Stmt>> {
+    if def.args.args.iter().any(|a| a.default.is_some()) {
+        return Err(todo_link!("closures can't have default values"));
+    }
+    let f = r_fn(def, ctx)?;
+    if !f.sig.generics.params.is_empty() {
+        return Err(todo_link!());
+    }
+    let inputs = f
+        .sig
+        .inputs
+        .into_iter()
+        .map(|arg| match arg {
+            syn::FnArg::Typed(t) => Ok(syn::Pat::Type(t)),
+            syn::FnArg::Receiver(_) => Err(todo_link!()),
+        })
+        .collect::<TResult<Vec<_>>>()?;
+    let output = f.sig.output;
+    let body = f.block;
+
+    // the closure is `FnMut` if it changes the variables
+    let params = names(&def.args);
+    let scopes = Scopes::function(params, &ctx.types.mutating_methods, &def.body);
+    let mutable = declared_names(&def.body, false).any(|n| scopes.is_mutated(&n));
+    let name = pat_ident(&def.name, mutable);
+
+    Ok(vec![
+        parse_quote!(let #name = |#(#inputs),*| #output #body;),
+    ])
+}
@@ -0,0 +1,1095 @@
+//! local type inference for python code without annotations
+//!
+//! this is not a type checker, it only looks at literals, arithmetic, calls to known
+//! functions and classes and `return` statements to fill in the types rust needs
+
+use crate::decorator;
+use rustpython_parser::ast::{
+    Arg, ArgWithDefault, Arguments, Comprehension, Constant, Expr, ExprAttribute, ExprAwait,
+    ExprBinOp, ExprCall, ExprConstant, ExprList, ExprName, ExprStarred, ExprSubscript, ExprTuple,
+    ExprYield, ExprYieldFrom, Operator, Stmt, StmtAnnAssign, StmtAssert, StmtAssign, StmtAsyncFor,
+    StmtAsyncWith, StmtAugAssign, StmtClassDef, StmtFor, StmtFunctionDef, StmtIf, StmtRaise,
+    StmtReturn, StmtTry, StmtWhile, StmtWith, UnaryOp,
+};
+use std::collections::{HashMap, HashSet};
+use syn::parse_quote;
+
+/// name of the called function, types of the positional and of the keyword arguments
+type CallSite = (String, Vec<Ty>, Vec<(String, Ty)>);
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Ty {
+    #[default]
+    Unknown,
+    None,
+    Bool,
+    Int,
+    /// result of `len`, which is a `usize` in rust
+    Usize,
+    Float,
+    Str,
+    List(Box<Ty>),
+    Set(Box<Ty>),
+    Dict(Box<Ty>, Box<Ty>),
+    Tuple(Vec<Ty>),
+    Class(String),
+    /// `threading.Lock`, a `Mutex` without data
+    Lock,
+    /// a generator, `impl Iterator`
+    Iter(Box<Ty>),
+}
+
+impl Ty {
+    /// the more precise of both types, ints are promoted to floats
+    fn merge(self, other: Ty) -> Ty {
+        match (self, other) {
+            (Ty::Unknown, t) | (t, Ty::Unknown) => t,
+            (Ty::Int | Ty::Usize, Ty::Float) | (Ty::Float, Ty::Int | Ty::Usize) => Ty::Float,
+            (Ty::Int, Ty::Usize) | (Ty::Usize, Ty::Int) => Ty::Int,
+            (Ty::List(a), Ty::List(b)) => Ty::List(Box::new(a.merge(*b))),
+            (Ty::Set(a), Ty::Set(b)) => Ty::Set(Box::new(a.merge(*b))),
+            (Ty::Iter(a), Ty::Iter(b)) => Ty::Iter(Box::new(a.merge(*b))),
+            (Ty::Dict(k1, v1), Ty::Dict(k2, v2)) => {
+                Ty::Dict(Box::new(k1.merge(*k2)), Box::new(v1.merge(*v2)))
+            }
+            (Ty::Tuple(a), Ty::Tuple(b)) if a.len() == b.len() => {
+                Ty::Tuple(a.into_iter().zip(b).map(|(a, b)| a.merge(b)).collect())
+            }
+            (a, _) => a,
+        }
+    }
+
+    /// types which are not `Copy` in rust, unknown types are assumed to be `Copy`
+    pub fn is_owned(&self) -> bool {
+        match self {
+            Ty::Str
+            | Ty::List(_)
+            | Ty::Set(_)
+            | Ty::Dict(..)
+            | Ty::Class(_)
+            | Ty::Lock
+            | Ty::Iter(_) => true,
+            Ty::Tuple(ts) => ts.iter().any(Ty::is_owned),
+            _ => false,
+        }
+    }
+
+    /// type of the elements when iterating over this type
+    fn elem(&self) -> Ty {
+        match self {
+            Ty::List(t) | Ty::Set(t) | Ty::Dict(t, _) | Ty::Iter(t) => *t.clone(),
+            Ty::Str => Ty::Str,
+            _ => Ty::Unknown,
+        }
+    }
+
+    /// `None` if the type is not (completely) known
+    pub fn to_syn(&self) -> Option<syn::Type> {
+        Some(match self {
+            Ty::Unknown => return None,
+            Ty::None => parse_quote!(()),
+            Ty::Bool => parse_quote!(bool),
+            Ty::Int => parse_quote!(isize),
+            Ty::Usize => parse_quote!(usize),
+            Ty::Float => parse_quote!(f64),
+            Ty::Str => parse_quote!(String),
+            Ty::List(t) => {
+                let t = t.to_syn()?;
+                parse_quote!(Vec<#t>)
+            }
+            Ty::Set(t) => {
+                let t = t.to_syn()?;
+                parse_quote!(std::collections::HashSet<#t>)
+            }
+            Ty::Dict(k, v) => {
+                let (k, v) = (k.to_syn()?, v.to_syn()?);
+                parse_quote!(std::collections::HashMap<#k, #v>)
+            }
+            Ty::Tuple(ts) => {
+                let ts = ts.iter().map(Ty::to_syn).collect::<Option<Vec<_>>>()?;
+                parse_quote!((#(#ts,)*))
+            }
+            Ty::Class(name) => {
+                let name = crate::util::ident(name);
+                parse_quote!(#name)
+            }
+            Ty::Lock => parse_quote!(std::sync::Mutex<()>),
+            Ty::Iter(t) => {
+                let t = t.to_syn()?;
+                parse_quote!(impl Iterator<Item = #t>)
+            }
+        })
+    }
+
+    pub fn from_annotation(e: &Expr) -> Ty {
+        match e {
+            Expr::Name(ExprName { id, .. }) => match id.as_str() {
+                "int" => Ty::Int,
+                "float" => Ty::Float,
+                "str" => Ty::Str,
+                "bool" => Ty::Bool,
+                "None" => Ty::None,
+                "list" | "List" => Ty::List(Box::default()),
+                "set" | "Set" => Ty::Set(Box::default()),
+                "dict" | "Dict" => Ty::Dict(Box::default(), Box::default()),
+                "Lock" | "RLock" => Ty::Lock,
+                // numpy arrays and other types which are only known by name
+                _ if id.chars().next().is_some_and(char::is_uppercase) => Ty::Class(id.to_string()),
+                _ => Ty::Unknown,
+            },
+            Expr::Constant(ExprConstant {
+                value: Constant::None,
+                ..
+            }) => Ty::None,
+            Expr::Attribute(ExprAttribute { attr, .. })
+                if matches!(attr.as_str(), "Lock" | "RLock") =>
+            {
+                Ty::Lock
+            }
+            Expr::Subscript(ExprSubscript { value, slice, .. }) => {
+                let args = match slice.as_ref() {
+                    Expr::Tuple(t) => t.elts.iter().map(Ty::from_annotation).collect(),
+                    s => vec![Ty::from_annotation(s)],
+                };
+                let arg = |i: usize| Box::new(args.get(i).cloned().unwrap_or_default());
+                match value.as_name_expr().map(|n| n.id.as_str()) {
+                    Some("list" | "List") => Ty::List(arg(0)),
+                    Some("set" | "Set") => Ty::Set(arg(0)),
+                    Some("dict" | "Dict") => Ty::Dict(arg(0), arg(1)),
+                    Some("tuple" | "Tuple") => Ty::Tuple(args),
+                    Some("Iterator" | "Iterable" | "Generator") => Ty::Iter(arg(0)),
+                    _ => Ty::Unknown,
+                }
+            }
+            _ => Ty::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Param {
+    pub name: String,
+    pub ty: Ty,
+    /// annotated types are never changed by inference
+    pub annotated: bool,
+    pub kind: Kind,
+}
+
+/// how the arguments of a call are passed to a parameter
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Kind {
+    #[default]
+    Positional,
+    /// with a default value, a field of the params struct
+    Default,
+    /// keyword-only without a default value
+    Keyword,
+    /// `*args`, a slice
+    Args,
+    /// `**kwargs`, a map
+    Kwargs,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Signature {
+    pub params: Vec<Param>,
+    /// number of parameters which can be passed by position, the ones before `*args`
+    pub positional: usize,
+    pub ret: Ty,
+    pub ret_annotated: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Types {
+    /// top level functions
+    pub functions: HashMap<String, Signature>,
+    /// class name -> fields
+    pub classes: HashMap<String, Vec<(String, Ty)>>,
+    /// classes which are the base class of another class, their methods are reached through
+    /// the `base` field so they can't consume `self`
+    pub base_classes: HashSet<String>,
+    /// variables of the scope which is currently transpiled
+    pub locals: HashMap<String, Ty>,
+    /// methods of classes which take `&mut self`
+    pub mutating_methods: HashSet<String>,
+    /// class of the methods which are currently transpiled, the type of `self`
+    pub class: Option<String>,
+    /// class name -> methods decorated with `@property`
+    pub properties: HashMap<String, HashSet<String>>,
+    /// class name -> properties with a `@prop.setter`
+    pub setters: HashMap<String, HashSet<String>>,
+    /// class name -> `@staticmethod`s and `@classmethod`s, which don't take `self`
+    pub associated: HashMap<String, HashSet<String>>,
+    /// classes with `__enter__` or `__exit__`
+    pub context_managers: HashMap<String, ContextManager>,
+}
+
+/// a class which is used in `with` statements
+#[derive(Debug, Clone, Copy)]
+pub struct ContextManager {
+    /// it has an `__enter__` method
+    pub enter: bool,
+    /// `__enter__` changes `self`
+    pub enter_mutates: bool,
+    /// `__enter__` returns `self` (or doesn't exist), so the value which is bound by `as` is
+    /// the guard
+    pub returns_self: bool,
+    /// `__aenter__` and `__aexit__` of `async with`, which are awaited
+    pub is_async: bool,
+    /// it has an `__exit__` method, which runs when the guard struct of the class is dropped
+    pub exit: bool,
+}
+
+impl Types {
+    /// collects classes and functions of `body` and infers the types of their
+    /// unannotated parameters (from call sites) and return types
+    pub fn collect(&mut self, body: &[Stmt]) {
+        for s in body {
+            match s {
+                Stmt::ClassDef(StmtClassDef {
+                    name, body, bases, ..
+                }) => {
+                    let mut fields: Vec<(String, Ty)> = body
+                        .iter()
+                        .filter_map(|b| b.as_ann_assign_stmt())
+                        .filter_map(|a| {
+                            let name = a.target.as_name_expr()?.id.to_string();
+                            Some((name, Ty::from_annotation(&a.annotation)))
+                        })
+                        .collect();
+                    if let Some(init) = body
+                        .iter()
+                        .filter_map(|b| b.as_function_def_stmt())
+                        .find(|d| d.name.as_str() == "__init__")
+                    {
+                        for (field, ty) in self.init_fields(init) {
+                            if !fields.iter().any(|(f, _)| *f == field) {
+                                fields.push((field, ty));
+                            }
+                        }
+                    }
+                    self.base_classes.extend(
+                        bases
+                            .iter()
+                            .filter_map(crate::inherit::base_name)
+                            .filter(|b| self.classes.contains_key(*b))
+                            .map(String::from),
+                    );
+                    // inherited fields belong to the `base` field
+                    let inherited = bases
+                        .iter()
+                        .filter_map(crate::inherit::base_name)
+                        .filter_map(|b| self.classes.get(b))
+                        .flatten()
+                        .map(|(f, _)| f.clone())
+                        .collect::<Vec<_>>();
+                    fields.retain(|(f, _)| !inherited.contains(f));
+
+                    let async_defs = body
+                        .iter()
+                        .filter_map(|b| b.as_async_function_def_stmt())
+                        .map(crate::asyncio::sync_def)
+                        .collect::<Vec<_>>();
+                    let defs = body
+                        .iter()
+                        .filter_map(|b| b.as_function_def_stmt())
+                        .chain(&async_defs)
+                        .collect::<Vec<_>>();
+                    let (setters, defs): (Vec<_>, Vec<_>) = defs
+                        .into_iter()
+                        .partition(|d| decorator::setter(&d.decorator_list).is_some());
+                    let with = |name: &str| {
+                        defs.iter()
+                            .filter(|d| decorator::has(&d.decorator_list, name))
+                            .map(|d| d.name.to_string())
+                            .collect::<HashSet<_>>()
+                    };
+                    self.properties.insert(name.to_string(), with("property"));
+                    let mut associated = with("staticmethod");
+                    associated.extend(with("classmethod"));
+                    self.associated.insert(name.to_string(), associated);
+                    self.setters.insert(
+                        name.to_string(),
+                        setters.iter().map(|d| d.name.to_string()).collect(),
+                    );
+
+                    let receivers = crate::scope::receivers(&defs, |f| {
+                        fields.iter().any(|(field, ty)| field == f && ty.is_owned())
+                    });
+                    let find = |m: &str| defs.iter().find(|d| d.name.as_str() == m);
+                    let is_async = find("__aenter__").is_some() || find("__aexit__").is_some();
+                    let (enter, exit) = if is_async {
+                        ("__aenter__", "__aexit__")
+                    } else {
+                        ("__enter__", "__exit__")
+                    };
+                    if find(enter).is_some() || find(exit).is_some() {
+                        let mut returns_self = true;
+                        if let Some(enter) = find(enter) {
+                            visit_stmts(&enter.body, &mut |s| {
+                                if let Stmt::Return(StmtReturn { value, .. }) = s {
+                                    returns_self &= value
+                                        .as_deref()
+                                        .and_then(Expr::as_name_expr)
+                                        .is_some_and(|n| n.id.as_str() == "self");
+                                }
+                            });
+                        }
+                        let manager = ContextManager {
+                            enter: find(enter).is_some(),
+                            enter_mutates: receivers.get(enter).is_some_and(|r| r.mutates),
+                            returns_self,
+                            is_async,
+                            exit: !is_async && find(exit).is_some(),
+                        };
+                        self.context_managers.insert(name.to_string(), manager);
+                    }
+                    self.mutating_methods.extend(
+                        receivers
+                            .into_iter()
+                            .filter(|(m, r)| r.mutates && m != "__init__")
+                            .map(|(m, _)| m),
+                    );
+                    self.classes.insert(name.to_string(), fields);
+                }
+                Stmt::FunctionDef(def) => {
+                    self.functions
+                        .insert(def.name.to_string(), self.signature(def));
+                }
+                Stmt::AsyncFunctionDef(def) => {
+                    let def = crate::asyncio::sync_def(def);
+                    self.functions
+                        .insert(def.name.to_string(), self.signature(&def));
+                }
+                _ => {}
+            }
+        }
+
+        // a few rounds are enough to propagate types through chains of calls
+        for _ in 0..3 {
+            let module_scope = self.scope(body, HashMap::new());
+            self.call_sites(body, &module_scope);
+
+            for s in body {
+                let def = match s {
+                    Stmt::FunctionDef(def) => std::borrow::Cow::Borrowed(def),
+                    Stmt::AsyncFunctionDef(def) => {
+                        std::borrow::Cow::Owned(crate::asyncio::sync_def(def))
+                    }
+                    _ => continue,
+                };
+                let scope = self.scope(&def.body, self.params(&def));
+                self.call_sites(&def.body, &scope);
+
+                let ret = returns(&def.body, self, &scope);
+                if let Some(sig) = self.functions.get_mut(def.name.as_str())
+                    && !sig.ret_annotated
+                {
+                    sig.ret = ret;
+                }
+            }
+        }
+    }
+
+    /// fields which are assigned as `self.field = value` in `__init__`
+    pub fn init_fields(&self, init: &StmtFunctionDef) -> Vec<(String, Ty)> {
+        let scope = self.scope(&init.body, self.params(init));
+        let mut fields: Vec<(String, Ty)> = vec![];
+        visit_stmts(&init.body, &mut |s| {
+            let (target, ty) = match s {
+                Stmt::Assign(StmtAssign { targets, value, .. }) if targets.len() == 1 => {
+                    (&targets[0], self.ty(value, &scope))
+                }
+                Stmt::AnnAssign(StmtAnnAssign {
+                    target, annotation, ..
+                }) => (target.as_ref(), Ty::from_annotation(annotation)),
+                _ => return,
+            };
+            if let Expr::Attribute(ExprAttribute { value, attr, .. }) = target
+                && value
+                    .as_name_expr()
+                    .is_some_and(|n| n.id.as_str() == "self")
+            {
+                match fields.iter_mut().find(|(f, _)| f == attr.as_str()) {
+                    Some((_, t)) => *t = std::mem::take(t).merge(ty),
+                    None => fields.push((attr.to_string(), ty)),
+                }
+            }
+        });
+        fields
+    }
+
+    fn signature(&self, def: &StmtFunctionDef) -> Signature {
+        let Arguments {
+            posonlyargs,
+            args,
+            vararg,
+            kwonlyargs,
+            kwarg,
+            ..
+        } = def.args.as_ref();
+        let param = |a: &ArgWithDefault, kind| {
+            let annotation = a.def.annotation.as_deref().map(Ty::from_annotation);
+            let default = a.default.as_deref().map(|d| self.ty(d, &HashMap::new()));
+            Param {
+                name: a.def.arg.to_string(),
+                annotated: annotation.is_some(),
+                kind: if default.is_some() {
+                    Kind::Default
+                } else {
+                    kind
+                },
+                ty: annotation.or(default).unwrap_or_default(),
+            }
+        };
+        // `*args: int` annotates the type of the elements
+        let variadic = |a: &Arg, kind| {
+            let t = a.annotation.as_deref().map(Ty::from_annotation);
+            Param {
+                name: a.arg.to_string(),
+                annotated: t.is_some(),
+                kind,
+                ty: match kind {
+                    Kind::Kwargs => Ty::Dict(Box::new(Ty::Str), Box::new(t.unwrap_or_default())),
+                    _ => Ty::List(Box::new(t.unwrap_or_default())),
+                },
+            }
+        };
+        let params = posonlyargs
+            .iter()
+            .chain(args)
+            .map(|a| param(a, Kind::Positional))
+            .chain(vararg.iter().map(|a| variadic(a, Kind::Args)))
+            .chain(kwonlyargs.iter().map(|a| param(a, Kind::Keyword)))
+            .chain(kwarg.iter().map(|a| variadic(a, Kind::Kwargs)))
+            .collect();
+
+        Signature {
+            params,
+            positional: posonlyargs.len() + args.len(),
+            ret: def
+                .returns
+                .as_deref()
+                .map(Ty::from_annotation)
+                .unwrap_or_default(),
+            ret_annotated: def.returns.is_some(),
+        }
+    }
+
+    /// types of the parameters of `def`, methods (with `self`) only use annotations
+    pub fn params(&self, def: &StmtFunctionDef) -> HashMap<String, Ty> {
+        let sig = self
+            .functions
+            .get(def.name.as_str())
+            .filter(|_| def.args.args.first().map(|a| a.def.arg.as_str()) != Some("self"))
+            .cloned()
+            .unwrap_or_else(|| self.signature(def));
+        let mut params: HashMap<_, _> = sig.params.into_iter().map(|p| (p.name, p.ty)).collect();
+        if let Some(class) = &self.class
+            && let Some(t) = params.get_mut("self")
+        {
+            *t = Ty::Class(class.clone());
+        }
+        params
+    }
+
+    /// return type of `def`, `Ty::Unknown` if it could not be inferred
+    pub fn ret(&self, def: &StmtFunctionDef, scope: &HashMap<String, Ty>) -> Ty {
+        returns(&def.body, self, scope)
+    }
+
+    /// refines the parameter types of known functions with the arguments of calls in `body`
+    fn call_sites(&mut self, body: &[Stmt], scope: &HashMap<String, Ty>) {
+        let mut calls = vec![];
+        visit_exprs(body, &mut |e| self.calls(e, scope, &mut calls));
+        for (f, args, kwargs) in calls {
+            let sig = self.functions.get_mut(&f).unwrap();
+            let refine = |p: &mut Param, t| {
+                if !p.annotated {
+                    p.ty = match std::mem::take(&mut p.ty) {
+                        // the arguments are the elements of `*args` and the values of `**kwargs`
+                        Ty::List(e) if p.kind == Kind::Args => Ty::List(Box::new(e.merge(t))),
+                        Ty::Dict(k, v) if p.kind == Kind::Kwargs => {
+                            Ty::Dict(k, Box::new(v.merge(t)))
+                        }
+                        ty => ty.merge(t),
+                    };
+                }
+            };
+            let rest = sig.params.iter().position(|p| p.kind == Kind::Args);
+            for (i, t) in args.into_iter().enumerate() {
+                if let Some(p) = (i < sig.positional).then_some(i).or(rest) {
+                    refine(&mut sig.params[p], t);
+                }
+            }
+            for (name, t) in kwargs {
+                let p = sig
+                    .params
+                    .iter()
+                    .position(|p| p.name == name && !matches!(p.kind, Kind::Args | Kind::Kwargs));
+                let p = p.or_else(|| sig.params.iter().position(|p| p.kind == Kind::Kwargs));
+                if let Some(p) = p {
+                    refine(&mut sig.params[p], t);
+                }
+            }
+        }
+    }
+
+    /// a call of a known function in `e` with the types of its arguments, the calls in the
+    /// elements of comprehensions see the targets of the comprehension
+    fn calls(&self, e: &Expr, scope: &HashMap<String, Ty>, calls: &mut Vec<CallSite>) {
+        if let Expr::Call(ExprCall {
+            func,
+            args,
+            keywords,
+            ..
+        }) = e
+            && let Some(f) = func.as_name_expr()
+            && self.functions.contains_key(f.id.as_str())
+        {
+            let args = args.iter().map(|a| self.ty(a, scope)).collect::<Vec<_>>();
+            let kwargs = keywords
+                .iter()
+                .filter_map(|k| Some((k.arg.as_ref()?.to_string(), self.ty(&k.value, scope))))
+                .collect::<Vec<_>>();
+            calls.push((f.id.to_string(), args, kwargs));
+        } else if let Some((elts, generators)) = comprehension(e) {
+            let scope = self.comprehension(generators, scope);
+            for elt in elts {
+                visit_expr(elt, &mut |e| self.calls(e, &scope, calls));
+            }
+        }
+    }
+
+    /// types of the variables which are assigned in `body` (without nested functions)
+    pub fn scope(&self, body: &[Stmt], params: HashMap<String, Ty>) -> HashMap<String, Ty> {
+        let mut scope = params;
+        // twice, for variables which are refined later, e.g. `x = []` and `x.append(1)`
+        for _ in 0..2 {
+            self.assignments(body, &mut scope);
+        }
+        scope
+    }
+
+    fn assignments(&self, body: &[Stmt], scope: &mut HashMap<String, Ty>) {
+        for s in body {
+            match s {
+                Stmt::Assign(StmtAssign { targets, value, .. }) => {
+                    let t = self.ty(value, scope);
+                    for target in targets {
+                        bind(scope, target, t.clone());
+                    }
+                }
+                Stmt::AnnAssign(StmtAnnAssign {
+                    target, annotation, ..
+                }) => bind(scope, target, Ty::from_annotation(annotation)),
+                Stmt::AugAssign(StmtAugAssign {
+                    target, op, value, ..
+                }) => {
+                    let t = binop(self.ty(target, scope), op, self.ty(value, scope));
+                    bind(scope, target, t);
+                }
+                Stmt::For(StmtFor {
+                    target,
+                    iter,
+                    body,
+                    orelse,
+                    ..
+                }) => {
+                    let t = self.ty(iter, scope).elem();
+                    bind(scope, target, t);
+                    self.assignments(body, scope);
+                    self.assignments(orelse, scope);
+                }
+                Stmt::While(StmtWhile { body, orelse, .. })
+                | Stmt::If(StmtIf { body, orelse, .. }) => {
+                    self.assignments(body, scope);
+                    self.assignments(orelse, scope);
+                }
+                Stmt::With(StmtWith { body, .. }) => self.assignments(body, scope),
+                Stmt::Try(StmtTry {
+                    body,
+                    handlers,
+                    orelse,
+                    finalbody,
+                    ..
+                }) => {
+                    self.assignments(body, scope);
+                    for h in handlers {
+                        self.assignments(&h.as_except_handler().unwrap().body, scope);
+                    }
+                    self.assignments(orelse, scope);
+                    self.assignments(finalbody, scope);
+                }
+                Stmt::Expr(e) => {
+                    // `x.append(v)` refines the element type of `x`
+                    if let Expr::Call(ExprCall { func, args, .. }) = e.value.as_ref()
+                        && let Expr::Attribute(ExprAttribute { value, attr, .. }) = func.as_ref()
+                        && let Some(name) = value.as_name_expr()
+                        && let [arg] = args.as_slice()
+                    {
+                        let arg = self.ty(arg, scope);
+                        let t = match attr.as_str() {
+                            "append" => Ty::List(Box::new(arg)),
+                            "add" => Ty::Set(Box::new(arg)),
+                            _ => continue,
+                        };
+                        let entry = scope.entry(name.id.to_string()).or_default();
+                        *entry = std::mem::take(entry).merge(t);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// type of an expression
+    pub fn ty(&self, e: &Expr, scope: &HashMap<String, Ty>) -> Ty {
+        match e {
+            Expr::Constant(c) => match &c.value {
+                Constant::None => Ty::None,
+                Constant::Bool(_) => Ty::Bool,
+                Constant::Str(_) => Ty::Str,
+                Constant::Int(_) => Ty::Int,
+                Constant::Float(_) => Ty::Float,
+                _ => Ty::Unknown,
+            },
+            Expr::JoinedStr(_) => Ty::Str,
+            Expr::Await(ExprAwait { value, .. }) => self.ty(value, scope),
+            Expr::Name(n) => scope.get(n.id.as_str()).cloned().unwrap_or_default(),
+            Expr::BinOp(ExprBinOp {
+                left, op, right, ..
+            }) => {
+                let (l, r) = self.operands(left, right, scope);
+                binop(l, op, r)
+            }
+            Expr::UnaryOp(u) => match u.op {
+                UnaryOp::Not => Ty::Bool,
+                _ => self.ty(&u.operand, scope),
+            },
+            Expr::Compare(_) => Ty::Bool,
+            Expr::BoolOp(b) => b
+                .values
+                .iter()
+                .map(|v| self.ty(v, scope))
+                .reduce(Ty::merge)
+                .unwrap_or_default(),
+            Expr::IfExp(i) => self.ty(&i.body, scope).merge(self.ty(&i.orelse, scope)),
+            Expr::List(l) => Ty::List(Box::new(self.elts(&l.elts, scope))),
+            Expr::Set(s) => Ty::Set(Box::new(self.elts(&s.elts, scope))),
+            Expr::Tuple(t) => Ty::Tuple(t.elts.iter().map(|e| self.ty(e, scope)).collect()),
+            Expr::Dict(d) => {
+                let (mut k, mut v) = (Ty::Unknown, Ty::Unknown);
+                for (key, value) in d.keys.iter().zip(&d.values) {
+                    let (kt, vt) = match key {
+                        Some(key) => (self.ty(key, scope), self.ty(value, scope)),
+                        // `**value`
+                        None => match self.ty(value, scope) {
+                            Ty::Dict(kt, vt) => (*kt, *vt),
+                            _ => (Ty::Unknown, Ty::Unknown),
+                        },
+                    };
+                    k = k.merge(kt);
+                    v = v.merge(vt);
+                }
+                Ty::Dict(Box::new(k), Box::new(v))
+            }
+            // `*value` in a list
+            Expr::Starred(s) => self.ty(&s.value, scope).elem(),
+            Expr::ListComp(c) => {
+                let scope = self.comprehension(&c.generators, scope);
+                Ty::List(Box::new(self.ty(&c.elt, &scope)))
+            }
+            Expr::GeneratorExp(c) => {
+                let scope = self.comprehension(&c.generators, scope);
+                Ty::List(Box::new(self.ty(&c.elt, &scope)))
+            }
+            Expr::SetComp(c) => {
+                let scope = self.comprehension(&c.generators, scope);
+                Ty::Set(Box::new(self.ty(&c.elt, &scope)))
+            }
+            Expr::DictComp(c) => {
+                let scope = self.comprehension(&c.generators, scope);
+                Ty::Dict(
+                    Box::new(self.ty(&c.key, &scope)),
+                    Box::new(self.ty(&c.value, &scope)),
+                )
+            }
+            Expr::Subscript(ExprSubscript { value, slice, .. }) => {
+                let v = self.ty(value, scope);
+                match (v, slice.as_ref()) {
+                    (t @ (Ty::List(_) | Ty::Str), Expr::Slice(_)) => t,
+                    (Ty::List(t), _) => *t,
+                    (Ty::Str, _) => Ty::Str,
+                    (Ty::Dict(_, v), _) => *v,
+                    (
+                        Ty::Tuple(ts),
+                        Expr::Constant(ExprConstant {
+                            value: Constant::Int(i),
+                            ..
+                        }),
+                    ) => i
+                        .to_string()
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|i| ts.get(i).cloned())
+                        .unwrap_or_default(),
+                    _ => Ty::Unknown,
+                }
+            }
+            Expr::Attribute(ExprAttribute { value, attr, .. }) => match self.ty(value, scope) {
+                Ty::Class(class) => self
+                    .classes
+                    .get(&class)
+                    .and_then(|fields| fields.iter().find(|(f, _)| f == attr.as_str()))
+                    .map(|(_, t)| t.clone())
+                    .unwrap_or_default(),
+                _ => Ty::Unknown,
+            },
+            Expr::Call(c) => self.call(c, scope),
+            _ => Ty::Unknown,
+        }
+    }
+
+    /// types of the operands of an arithmetic operation or comparison, an int literal next to a
+    /// `usize` is a `usize`
+    pub fn operands(&self, left: &Expr, right: &Expr, scope: &HashMap<String, Ty>) -> (Ty, Ty) {
+        match (self.ty(left, scope), self.ty(right, scope)) {
+            (Ty::Int, Ty::Usize) if is_int_literal(left) => (Ty::Usize, Ty::Usize),
+            (Ty::Usize, Ty::Int) if is_int_literal(right) => (Ty::Usize, Ty::Usize),
+            types => types,
+        }
+    }
+
+    fn elts(&self, elts: &[Expr], scope: &HashMap<String, Ty>) -> Ty {
+        elts.iter()
+            .map(|e| self.ty(e, scope))
+            .fold(Ty::Unknown, Ty::merge)
+    }
+
+    /// scope with the targets of the comprehension
+    fn comprehension(
+        &self,
+        generators: &[Comprehension],
+        scope: &HashMap<String, Ty>,
+    ) -> HashMap<String, Ty> {
+        let mut scope = scope.clone();
+        for g in generators {
+            let t = self.ty(&g.iter, &scope).elem();
+            bind(&mut scope, &g.target, t);
+        }
+        scope
+    }
+
+    fn call(&self, c: &ExprCall, scope: &HashMap<String, Ty>) -> Ty {
+        let arg = |i: usize| c.args.get(i).map(|a| self.ty(a, scope)).unwrap_or_default();
+
+        match c.func.as_ref() {
+            Expr::Name(ExprName { id, .. }) => match id.as_str() {
+                "len" => Ty::Usize,
+                "int" | "round" => Ty::Int,
+                "float" => Ty::Float,
+                "str" | "input" | "repr" => Ty::Str,
+                "bool" | "isinstance" => Ty::Bool,
+                "abs" => arg(0),
+                "sum" | "min" | "max" if c.args.len() == 1 => arg(0).elem(),
+                "min" | "max" => c
+                    .args
+                    .iter()
+                    .map(|a| self.ty(a, scope))
+                    .fold(Ty::Unknown, Ty::merge),
+                "range" => Ty::List(Box::new(Ty::Int)),
+                "list" | "sorted" | "reversed" => Ty::List(Box::new(arg(0).elem())),
+                "set" => Ty::Set(Box::new(arg(0).elem())),
+                "enumerate" => Ty::List(Box::new(Ty::Tuple(vec![Ty::Int, arg(0).elem()]))),
+                "zip" => Ty::List(Box::new(Ty::Tuple(
+                    (0..c.args.len()).map(|i| arg(i).elem()).collect(),
+                ))),
+                "print" => Ty::None,
+                f if self.classes.contains_key(f) => Ty::Class(f.to_string()),
+                "Lock" | "RLock" => Ty::Lock,
+                f => self
+                    .functions
+                    .get(f)
+                    .map(|s| s.ret.clone())
+                    .unwrap_or_default(),
+            },
+            Expr::Attribute(ExprAttribute { value, attr, .. }) => {
+                if value
+                    .as_name_expr()
+                    .is_some_and(|m| m.id.as_str() == "math")
+                {
+                    return Ty::Float;
+                }
+                if value
+                    .as_name_expr()
+                    .is_some_and(|m| m.id.as_str() == "threading")
+                    && matches!(attr.as_str(), "Lock" | "RLock")
+                {
+                    return Ty::Lock;
+                }
+                let receiver = self.ty(value, scope);
+                match attr.as_str() {
+                    "upper" | "lower" | "strip" | "lstrip" | "rstrip" | "join" | "replace"
+                    | "format" => Ty::Str,
+                    "split" | "splitlines" => Ty::List(Box::new(Ty::Str)),
+                    "startswith" | "endswith" | "isdigit" => Ty::Bool,
+                    "count" | "index" | "find" => Ty::Int,
+                    "copy" => receiver,
+                    "pop" => receiver.elem(),
+                    _ => Ty::Unknown,
+                }
+            }
+            _ => Ty::Unknown,
+        }
+    }
+}
+
+fn bind(scope: &mut HashMap<String, Ty>, target: &Expr, t: Ty) {
+    match target {
+        Expr::Name(n) => {
+            let entry = scope.entry(n.id.to_string()).or_default();
+            *entry = std::mem::take(entry).merge(t);
+        }
+        Expr::Tuple(ExprTuple { elts, .. }) | Expr::List(ExprList { elts, .. }) => {
+            // `first, *rest = value`
+            let star = elts.iter().position(Expr::is_starred_expr);
+            for (i, e) in elts.iter().enumerate() {
+                let t = match (&t, star) {
+                    (Ty::List(t), Some(s)) if i == s => Ty::List(t.clone()),
+                    (Ty::List(t), _) => *t.clone(),
+                    (Ty::Tuple(ts), Some(s)) if i == s => Ty::List(Box::new(
+                        ts.iter()
+                            .skip(s)
+                            .take((ts.len() + 1).saturating_sub(elts.len()))
+                            .cloned()
+                            .fold(Ty::Unknown, Ty::merge),
+                    )),
+                    (Ty::Tuple(ts), Some(s)) if i > s => (ts.len() + i)
+                        .checked_sub(elts.len())
+                        .and_then(|i| ts.get(i))
+                        .cloned()
+                        .unwrap_or_default(),
+                    (Ty::Tuple(ts), _) => ts.get(i).cloned().unwrap_or_default(),
+                    _ => Ty::Unknown,
+                };
+                bind(scope, e, t);
+            }
+        }
+        Expr::Starred(ExprStarred { value, .. }) => bind(scope, value, t),
+        _ => {}
+    }
+}
+
+pub fn is_int_literal(e: &Expr) -> bool {
+    matches!(
+        e,
+        Expr::Constant(ExprConstant {
+            value: Constant::Int(_),
+            ..
+        })
+    )
+}
+
+/// numeric types, the operand of the lower rank is cast to the type of the other
+pub fn rank(t: &Ty) -> Option<u8> {
+    match t {
+        Ty::Usize => Some(0),
+        Ty::Int => Some(1),
+        Ty::Float => Some(2),
+        _ => None,
+    }
+}
+
+fn binop(l: Ty, op: &Operator, r: Ty) -> Ty {
+    match (l, op, r) {
+        (Ty::Int | Ty::Usize, Operator::Div, Ty::Int | Ty::Usize) => Ty::Float,
+        (Ty::Usize, _, Ty::Usize) => Ty::Usize,
+        (Ty::Int | Ty::Usize, _, Ty::Int | Ty::Usize) => Ty::Int,
+        (Ty::Int | Ty::Usize | Ty::Float, _, Ty::Int | Ty::Usize | Ty::Float) => Ty::Float,
+        (Ty::Str, Operator::Add | Operator::Mod, _) => Ty::Str,
+        (Ty::Str, Operator::Mult, Ty::Int | Ty::Usize)
+        | (Ty::Int | Ty::Usize, Operator::Mult, Ty::Str) => Ty::Str,
+        (l @ Ty::List(_), Operator::Add, r @ Ty::List(_)) => l.merge(r),
+        (l @ Ty::List(_), Operator::Mult, Ty::Int | Ty::Usize) => l,
+        _ => Ty::Unknown,
+    }
+}
+
+/// merged type of all `return` statements, `Ty::None` if there is no `return value`
+///
+/// generators return an iterator of the yielded values
+fn returns(body: &[Stmt], types: &Types, scope: &HashMap<String, Ty>) -> Ty {
+    if is_generator(body) {
+        let mut item = Ty::Unknown;
+        visit_exprs(body, &mut |e| match e {
+            Expr::Yield(ExprYield { value: Some(v), .. }) => {
+                item = std::mem::take(&mut item).merge(types.ty(v, scope));
+            }
+            Expr::YieldFrom(ExprYieldFrom { value, .. }) => {
+                item = std::mem::take(&mut item).merge(types.ty(value, scope).elem());
+            }
+            _ => {}
+        });
+        return Ty::Iter(Box::new(item));
+    }
+    let mut ret: Option<Ty> = None;
+    visit_stmts(body, &mut |s| {
+        if let Stmt::Return(StmtReturn { value: Some(v), .. }) = s {
+            let t = types.ty(v, scope);
+            ret = Some(ret.take().unwrap_or_default().merge(t));
+        }
+    });
+    ret.unwrap_or(Ty::None)
+}
+
+/// `body` of a function contains `yield` or `yield from`
+pub(crate) fn is_generator(body: &[Stmt]) -> bool {
+    let mut generator = false;
+    visit_exprs(body, &mut |e| {
+        generator |= matches!(e, Expr::Yield(_) | Expr::YieldFrom(_));
+    });
+    generator
+}
+
+/// calls `f` for all statements in `body` and nested blocks, but not nested functions and classes
+pub(crate) fn visit_stmts(body: &[Stmt], f: &mut impl FnMut(&Stmt)) {
+    for s in body {
+        f(s);
+        match s {
+            Stmt::For(StmtFor { body, orelse, .. })
+            | Stmt::AsyncFor(StmtAsyncFor { body, orelse, .. })
+            | Stmt::While(StmtWhile { body, orelse, .. })
+            | Stmt::If(StmtIf { body, orelse, .. }) => {
+                visit_stmts(body, f);
+                visit_stmts(orelse, f);
+            }
+            Stmt::With(StmtWith { body, .. }) | Stmt::AsyncWith(StmtAsyncWith { body, .. }) => {
+                visit_stmts(body, f)
+            }
+            Stmt::Try(StmtTry {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+                ..
+            }) => {
+                visit_stmts(body, f);
+                for h in handlers {
+                    visit_stmts(&h.as_except_handler().unwrap().body, f);
+                }
+                visit_stmts(orelse, f);
+                visit_stmts(finalbody, f);
+            }
+            Stmt::Match(m) => {
+                for case in &m.cases {
+                    visit_stmts(&case.body, f);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// calls `f` for the expressions in the statements of `body` (see `visit_expr`), but not for
+/// assignment targets
+pub(crate) fn visit_exprs(body: &[Stmt], f: &mut impl FnMut(&Expr)) {
+    visit_stmts(body, &mut |s| match s {
+        Stmt::Expr(e) => visit_expr(&e.value, f),
+        Stmt::Assign(StmtAssign { value, .. }) | Stmt::AugAssign(StmtAugAssign { value, .. }) => {
+            visit_expr(value, f)
+        }
+        Stmt::AnnAssign(StmtAnnAssign { value: Some(v), .. }) => visit_expr(v, f),
+        Stmt::Return(StmtReturn { value: Some(v), .. }) => visit_expr(v, f),
+        Stmt::If(StmtIf { test, .. }) | Stmt::While(StmtWhile { test, .. }) => visit_expr(test, f),
+        Stmt::For(StmtFor { iter, .. }) | Stmt::AsyncFor(StmtAsyncFor { iter, .. }) => {
+            visit_expr(iter, f)
+        }
+        Stmt::With(StmtWith { items, .. }) | Stmt::AsyncWith(StmtAsyncWith { items, .. }) => {
+            items.iter().for_each(|i| visit_expr(&i.context_expr, f))
+        }
+        Stmt::Match(m) => visit_expr(&m.subject, f),
+        Stmt::Raise(StmtRaise { exc, cause, .. }) => {
+            exc.iter().chain(cause).for_each(|e| visit_expr(e, f))
+        }
+        Stmt::Assert(StmtAssert { test, msg, .. }) => {
+            visit_expr(test, f);
+            msg.iter().for_each(|m| visit_expr(m, f));
+        }
+        _ => {}
+    });
+}
+
+/// `e` and all expressions in it, but not the bodies of lambdas
+pub(crate) fn visit_expr(e: &Expr, f: &mut impl FnMut(&Expr)) {
+    f(e);
+    match e {
+        Expr::Call(c) => {
+            visit_expr(&c.func, f);
+            for a in &c.args {
+                visit_expr(a, f);
+            }
+            for k in &c.keywords {
+                visit_expr(&k.value, f);
+            }
+        }
+        Expr::BinOp(b) => {
+            visit_expr(&b.left, f);
+            visit_expr(&b.right, f);
+        }
+        Expr::BoolOp(b) => b.values.iter().for_each(|v| visit_expr(v, f)),
+        Expr::Compare(c) => {
+            visit_expr(&c.left, f);
+            c.comparators.iter().for_each(|v| visit_expr(v, f));
+        }
+        Expr::UnaryOp(u) => visit_expr(&u.operand, f),
+        Expr::Await(a) => visit_expr(&a.value, f),
+        Expr::IfExp(i) => {
+            visit_expr(&i.test, f);
+            visit_expr(&i.body, f);
+            visit_expr(&i.orelse, f);
+        }
+        Expr::List(l) => l.elts.iter().for_each(|v| visit_expr(v, f)),
+        Expr::Tuple(t) => t.elts.iter().for_each(|v| visit_expr(v, f)),
+        Expr::Set(s) => s.elts.iter().for_each(|v| visit_expr(v, f)),
+        Expr::Dict(d) => {
+            d.keys.iter().flatten().for_each(|k| visit_expr(k, f));
+            d.values.iter().for_each(|v| visit_expr(v, f));
+        }
+        Expr::Attribute(a) => visit_expr(&a.value, f),
+        Expr::Subscript(s) => {
+            visit_expr(&s.value, f);
+            visit_expr(&s.slice, f);
+        }
+        Expr::Slice(s) => [&s.lower, &s.upper, &s.step]
+            .into_iter()
+            .flatten()
+            .for_each(|v| visit_expr(v, f)),
+        Expr::Starred(s) => visit_expr(&s.value, f),
+        Expr::NamedExpr(n) => visit_expr(&n.value, f),
+        Expr::Yield(y) => y.value.iter().for_each(|v| visit_expr(v, f)),
+        Expr::YieldFrom(y) => visit_expr(&y.value, f),
+        Expr::JoinedStr(j) => j.values.iter().for_each(|v| visit_expr(v, f)),
+        Expr::FormattedValue(v) => visit_expr(&v.value, f),
+        _ => {
+            if let Some((elts, generators)) = comprehension(e) {
+                for g in generators {
+                    visit_expr(&g.iter, f);
+                    g.ifs.iter().for_each(|i| visit_expr(i, f));
+                }
+                elts.into_iter().for_each(|e| visit_expr(e, f));
+            }
+        }
+    }
+}
+
+/// elements and generators of a comprehension
+fn comprehension(e: &Expr) -> Option<(Vec<&Expr>, &[Comprehension])> {
+    match e {
+        Expr::ListComp(c) => Some((vec![&c.elt], &c.generators)),
+        Expr::SetComp(c) => Some((vec![&c.elt], &c.generators)),
+        Expr::GeneratorExp(c) => Some((vec![&c.elt], &c.generators)),
+        Expr::DictComp(c) => Some((vec![&c.key, &c.value], &c.generators)),
+        _ => None,
+    }
+}
@@ -0,0 +1,172 @@
+//! abstract base classes and protocols become traits, other base classes are embedded
+//! as a `base` field (composition) and reachable through `Deref`
+
+use crate::{ident, r_fn, scope, todo_link, Ctx, TResult, TranspileError};
+use rustpython_parser::ast::{
+    Constant, Expr, ExprAttribute, ExprCall, ExprContext, ExprName, Identifier, Ranged,
+    StmtClassDef,
+};
+use syn::parse_quote;
+
+/// name of a base class expression, e.g. `ABC` for `abc.ABC`
+pub fn base_name(e: &Expr) -> Option<&str> {
+    match e {
+        Expr::Name(ExprName { id, .. }) => Some(id.as_str()),
+        Expr::Attribute(ExprAttribute { attr, .. }) => Some(attr.as_str()),
+        _ => None,
+    }
+}
+
+/// classes which are translated into traits
+pub fn is_trait(class: &StmtClassDef) -> bool {
+    class
+        .bases
+        .iter()
+        .filter_map(base_name)
+        .any(|b| matches!(b, "ABC" | "Protocol"))
+        || class.keywords.iter().any(|k| {
+            k.arg.as_deref() == Some("metaclass") && base_name(&k.value) == Some("ABCMeta")
+        })
+}
+
+/// `super()`
+pub fn is_super(e: &Expr) -> bool {
+    matches!(e, Expr::Call(ExprCall { func, args, .. })
+        if args.is_empty() && base_name(func) == Some("super"))
+}
+
+/// convert an abstract base class or protocol into a trait
+///
+/// abstract methods (and methods of protocols with `...` as body) are only declared, the
+/// other methods are default implementations
+pub fn r_trait(class: &StmtClassDef, ctx: &mut Ctx) -> TResult<Vec<syn::Stmt>> {
+    let name = ident(&class.name);
+    let defs = class
+        .body
+        .iter()
+        .filter_map(|b| b.as_function_def_stmt())
+        .collect::<Vec<_>>();
+
+    for b in &class.body {
+        if b.as_function_def_stmt().is_none() && crate::docstring(b).is_none() {
+            ctx.warn(
+                crate::stmt_kind(b),
+                b.range(),
+                "traits can't have fields, only methods are translated",
+            );
+        }
+    }
+
+    // there are no fields, so every method gets at most `&mut self`
+    let receivers = scope::receivers(&defs, |_| false);
+    let outer_class = ctx.types.class.take();
+    let items = defs
+        .iter()
+        .map(|def| -> TResult<syn::TraitItem> {
+            ctx.receiver = receivers[def.name.as_str()];
+            let f = r_fn(def, ctx)?;
+            let declared_only = def
+                .decorator_list
+                .iter()
+                .any(|d| base_name(d) == Some("abstractmethod"))
+                || matches!(def.body.as_slice(), [b] if b
+                    .as_expr_stmt()
+                    .and_then(|e| e.value.as_constant_expr())
+                    .is_some_and(|c| matches!(c.value, Constant::Ellipsis)));
+
+            Ok(syn::TraitItem::Fn(syn::TraitItemFn {
+                attrs: f.attrs,
+                sig: f.sig,
+                default: (!declared_only).then_some(*f.block),
+                semi_token: declared_only.then(Default::default),
+            }))
+        })
+        .collect::<TResult<Vec<_>>>();
+    ctx.types.class = outer_class;
+    let items = items?;
+
+    ctx.traits.push((
+        class.name.to_string(),
+        defs.iter().map(|d| d.name.to_string()).collect(),
+    ));
+
+    Ok(vec![syn::Stmt::Item(parse_quote! {
+        trait #name {
+            #(#items)*
+        }
+    })])
+}
+
+/// `impl Trait for Class` for all traits of `class` with the methods which are
+/// defined in the trait
+pub fn trait_impls(
+    class: &StmtClassDef,
+    methods: &mut Vec<syn::ImplItem>,
+    ctx: &Ctx,
+) -> Vec<syn::Item> {
+    let name = ident(&class.name);
+    class
+        .bases
+        .iter()
+        .filter_map(base_name)
+        .filter_map(|b| ctx.traits.iter().find(|(t, _)| t == b))
+        .map(|(t, trait_methods)| {
+            let (items, rest): (Vec<_>, Vec<_>) = std::mem::take(methods)
+                .into_iter()
+                .partition(|m| {
+                    matches!(m, syn::ImplItem::Fn(f) if trait_methods.contains(&f.sig.ident.to_string()))
+                });
+            *methods = rest;
+            let t = ident(t);
+            parse_quote! {
+                impl #t for #name {
+                    #(#items)*
+                }
+            }
+        })
+        .collect()
+}
+
+/// `Deref` and `DerefMut` to the `base` field, which makes inherited fields and methods available
+pub fn deref_impls(class: &str, base: &str) -> [syn::Item; 2] {
+    let (class, base) = (ident(class), ident(base));
+    [
+        parse_quote! {
+            impl std::ops::Deref for #class {
+                type Target = #base;
+                fn deref(&self) -> &Self::Target {
+                    &self.base
+                }
+            }
+        },
+        parse_quote! {
+            impl std::ops::DerefMut for #class {
+                fn deref_mut(&mut self) -> &mut Self::Target {
+                    &mut self.base
+                }
+            }
+        },
+    ]
+}
+
+/// `super().__init__(args)` in `__init__` -> `let base = Base::new(args);`
+pub fn r_super_init(call: &ExprCall, ctx: &mut Ctx) -> TResult<syn::Stmt> {
+    let class = ctx.types.class.clone().ok_or(todo_link!())?;
+    let base = ctx.bases.get(&class).cloned().ok_or(todo_link!())?;
+    let base_call: Expr = ExprCall {
+        range: call.range,
+        func: Box::new(
+            ExprName {
+                range: call.func.range(),
+                id: Identifier::new(base),
+                ctx: ExprContext::Load,
+            }
+            .into(),
+        ),
+        args: call.args.clone(),
+        keywords: call.keywords.clone(),
+    }
+    .into();
+    let v = crate::r_e(&base_call, ctx)?;
+    Ok(parse_quote!(let base = #v;))
+}
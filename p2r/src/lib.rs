@@ -1,22 +1,45 @@
 #![forbid(unsafe_code)]
-#![feature(iter_intersperse)]
-#![feature(iter_map_windows)]
 #![feature(let_chains)]
 
 use rustpython_parser::ast::{
-    ArgWithDefault, BoolOp, CmpOp, Constant, ExceptHandlerExceptHandler, Expr, ExprAttribute,
-    ExprBinOp, ExprBoolOp, ExprCall, ExprCompare, ExprConstant, ExprContext, ExprDict,
-    ExprDictComp, ExprFormattedValue, ExprGeneratorExp, ExprIfExp, ExprJoinedStr, ExprLambda,
-    ExprList, ExprListComp, ExprName, ExprNamedExpr, ExprSet, ExprSetComp, ExprSlice, ExprStarred,
-    ExprSubscript, ExprTuple, ExprUnaryOp, MatchCase, Mod, Operator, Pattern, PatternMatchValue,
-    Stmt, StmtAnnAssign, StmtAssert, StmtAssign, StmtAugAssign, StmtClassDef, StmtDelete, StmtExpr,
-    StmtFor, StmtFunctionDef, StmtIf, StmtImport, StmtImportFrom, StmtMatch, StmtRaise, StmtReturn,
-    StmtTry, StmtTypeAlias, StmtWhile, UnaryOp,
+    ArgWithDefault, BoolOp, CmpOp, Constant, Expr, ExprAttribute, ExprAwait, ExprBinOp, ExprBoolOp,
+    ExprCall, ExprCompare, ExprConstant, ExprContext, ExprDict, ExprDictComp, ExprFormattedValue,
+    ExprGeneratorExp, ExprIfExp, ExprJoinedStr, ExprLambda, ExprList, ExprListComp, ExprName,
+    ExprNamedExpr, ExprSet, ExprSetComp, ExprSlice, ExprSubscript, ExprTuple, ExprUnaryOp,
+    Identifier, Mod, Operator, Pattern, Stmt, StmtAnnAssign, StmtAssert, StmtAssign, StmtAugAssign,
+    StmtClassDef, StmtDelete, StmtExpr, StmtFor, StmtFunctionDef, StmtIf, StmtImport,
+    StmtImportFrom, StmtRaise, StmtReturn, StmtTypeAlias, StmtWhile, UnaryOp,
 };
-use std::{fmt::Write, ops::Deref};
+use rustpython_parser::{
+    ast::Ranged,
+    text_size::{TextRange, TextSize},
+};
+use std::fmt::Write;
+use syn::parse_quote;
+
+mod asyncio;
+pub use asyncio::Runtime;
+mod context;
+mod decorator;
+mod diagnostic;
+mod dunder;
+mod exception;
+mod generator;
+mod global;
+mod infer;
+mod inherit;
+mod params;
+mod pattern;
+mod scope;
+mod unpack;
+pub use diagnostic::{Diagnostic, LineCol, PySpan, Severity};
 
 mod util;
-use util::PaddedT;
+use util::{
+    assign, awaited, binary, block, block_expr, call, cast, closure, collect, comment_attrs,
+    comment_item, doc_attrs, field, ident, if_expr, index, item_attrs, method_call, paren,
+    pat_ident, pat_tuple, path, range, reference, stmt, tail_block, tuple, unary,
+};
 
 type TResult<T> = Result<T, TranspileError>;
 
@@ -24,6 +47,10 @@ type TResult<T> = Result<T, TranspileError>;
 pub struct TranspileError {
     pub file: &'static str,
     pub line: u32,
+    /// the python code which could not be transpiled
+    pub span: Option<PySpan>,
+    /// why the python code can't be transpiled, if it is known
+    pub message: Option<&'static str>,
 }
 
 impl std::fmt::Display for TranspileError {
@@ -32,7 +59,19 @@ impl std::fmt::Display for TranspileError {
             "Error at {file}:{line}",
             file = self.file,
             line = self.line
-        ))
+        ))?;
+        if let Some(span) = &self.span {
+            f.write_fmt(format_args!(
+                " while transpiling python {kind} at {line}:{column}",
+                kind = span.kind,
+                line = span.start.line,
+                column = span.start.column
+            ))?;
+        }
+        if let Some(message) = self.message {
+            f.write_fmt(format_args!(": {message}"))?;
+        }
+        Ok(())
     }
 }
 
@@ -46,6 +85,32 @@ impl TranspileError {
             line = self.line
         )
     }
+
+    /// attaches the python node which failed, the innermost node wins
+    fn at(mut self, kind: &'static str, range: TextRange) -> Self {
+        if self.span.is_none() {
+            self.span = Some(PySpan::new(kind, range));
+        }
+        self
+    }
+
+    /// turns the byte offsets of the span into lines and columns of `code`
+    fn locate(mut self, code: &str) -> Self {
+        if let Some(span) = &mut self.span {
+            span.locate(code);
+        }
+        self
+    }
+
+    /// human readable error which underlines the python code which failed
+    pub fn report(&self, code: &str) -> String {
+        let snippet = self
+            .span
+            .as_ref()
+            .map(|s| s.snippet(code))
+            .unwrap_or_default();
+        format!("{self}\n{snippet}{}", self.to_link())
+    }
 }
 
 #[macro_export]
@@ -54,6 +119,16 @@ macro_rules! todo_link {
         TranspileError {
             file: file!(),
             line: line!(),
+            span: None,
+            message: None,
+        }
+    };
+    ($message:literal) => {
+        TranspileError {
+            file: file!(),
+            line: line!(),
+            span: None,
+            message: Some($message),
         }
     };
 }
@@ -70,48 +145,378 @@ impl From<TranspileError> for ParseError {
     }
 }
 
-pub fn fmt(code: &str) -> String {
-    if let Ok(t) = syn::parse_file(code) {
-        prettyplease::unparse(&t)
-    } else {
-        code.to_string()
+pub fn fmt(file: &syn::File) -> String {
+    restore_comments(&prettyplease::unparse(file))
+}
+
+/// syn can not represent comments, so they are passed through prettyplease as
+/// `#[p2r_comment = "..."]` attributes (see `util::comment_attrs`) and turned into `//` here,
+/// standalone comments are attached to an empty `p2r_comments!();` which is removed
+fn restore_comments(code: &str) -> String {
+    let mut res = String::with_capacity(code.len());
+    for line in code.lines() {
+        let trimmed = line.trim_start();
+        let indent = &line[..line.len() - trimmed.len()];
+        if let Some(lit) = trimmed
+            .strip_prefix("#[p2r_comment = ")
+            .and_then(|l| l.strip_suffix(']'))
+            && let Ok(lit) = syn::parse_str::<syn::LitStr>(lit)
+        {
+            let comment = lit.value();
+            if comment.is_empty() {
+                let _ = writeln!(res, "{indent}//");
+            } else {
+                let _ = writeln!(res, "{indent}// {comment}");
+            }
+        } else if trimmed != "p2r_comments!();" {
+            res.push_str(line);
+            res.push('\n');
+        }
+    }
+    res
+}
+
+/// wraps the statements into `fn main() { ... }`
+pub fn main_file(stmts: Vec<syn::Stmt>) -> syn::File {
+    syn::File {
+        shebang: None,
+        attrs: vec![],
+        items: vec![syn::Item::Fn(main_fn(stmts))],
+    }
+}
+
+/// exports the functions of `file` to python with `#[pyo3::pyfunction]`, the attribute
+/// goes on the `fn` items, not on the imports and structs in front of them
+pub fn pyfunctions(file: &mut syn::File) {
+    for item in &mut file.items {
+        if let syn::Item::Fn(f) = item
+            && f.sig.ident != "main"
+        {
+            f.attrs.insert(0, parse_quote!(#[pyo3::pyfunction]));
+        }
     }
 }
 
+fn main_fn(stmts: Vec<syn::Stmt>) -> syn::ItemFn {
+    let mut main: syn::ItemFn = parse_quote!(
+        fn main() {}
+    );
+    main.block.stmts = stmts;
+    main
+}
+
 #[cfg(test)]
 mod test;
 
-pub fn p2r(code: &str, ctx: &mut Ctx) -> Result<String, ParseError> {
-    let mut total = String::new();
+/// parses `code` and prepares `ctx` for transpiling it
+fn parse(
+    code: &str,
+    mode: rustpython_parser::Mode,
+    ctx: &mut Ctx,
+) -> Result<Vec<Stmt>, ParseError> {
+    ctx.source = code.to_string();
+    ctx.comments = rustpython_parser::lexer::lex(code, mode)
+        .filter_map(|t| match t {
+            Ok((rustpython_parser::Tok::Comment(c), range)) => Some((range.start(), c)),
+            _ => None,
+        })
+        .collect();
+    let ast = rustpython_parser::parse(code, mode, "./").map_err(ParseError::ParseError)?;
+    match ast {
+        Mod::Module(m) => Ok(m.body),
+        Mod::Interactive(body) => Ok(body.body),
+        Mod::Expression(_) => Err(todo_link!().into()),
+        Mod::FunctionType(_) => Err(todo_link!().into()),
+    }
+    .inspect(|body| {
+        ctx.types.collect(body);
+        ctx.types.locals = ctx.types.scope(body, Default::default());
+        ctx.globals = global::collect(body, &ctx.types.locals);
+        ctx.scopes = scope::Scopes::function([], &ctx.types.mutating_methods, body);
+        ctx.scopes.declare_globals(ctx.globals.keys());
+        if ctx.result_errors {
+            ctx.exceptions.collect(body, &ctx.types);
+        }
+    })
+}
 
-    let ast = rustpython_parser::parse(code, rustpython_parser::Mode::Interactive, "./")
-        .map_err(ParseError::ParseError)?;
-    let body = match ast {
-        Mod::Module(_) => Err(todo_link!()),
-        Mod::Interactive(body) => Ok(body),
-        Mod::Expression(_) => Err(todo_link!()),
-        Mod::FunctionType(_) => Err(todo_link!()),
-    }?;
+pub fn p2r(code: &str, ctx: &mut Ctx) -> Result<Vec<syn::Stmt>, ParseError> {
+    let mut total = vec![];
 
-    for b in body.body {
-        let rust = r_s(&b, ctx)?;
-        total += &rust;
-        if b.is_expr_stmt() {
-            // TOOD this handling of expr is a bit hacky?
-            total += ";\n"
-        }
+    let body = parse(code, rustpython_parser::Mode::Interactive, ctx)?;
+    for b in body {
+        total.extend(r_s(&b, ctx).map_err(|e| e.locate(code))?);
     }
 
+    // comments after the last statement
+    let comments = std::mem::take(&mut ctx.comments);
+    total.extend(comment_item(comments.iter().map(|(_, c)| comment_text(c))).map(syn::Stmt::Item));
+
     // TODO move the imports and prelude to the front?
-    total += &format!(
-        "{}\n{}",
-        ctx.imports.gen_imports(),
-        ctx.imports.gen_prelude(),
-    );
+    total.extend(ctx.imports.gen_imports().map(syn::Stmt::Item));
+    total.extend(ctx.imports.gen_prelude().map(syn::Stmt::Item));
+    total.extend(ctx.exceptions.error_enum().into_iter().map(syn::Stmt::Item));
 
     Ok(total)
 }
 
+/// transpiles a whole python file
+///
+/// functions, classes and constants become top level items, the remaining statements
+/// or the body of `if __name__ == "__main__":` go into `fn main`
+pub fn p2r_module(code: &str, ctx: &mut Ctx) -> Result<syn::File, ParseError> {
+    let body = parse(code, rustpython_parser::Mode::Module, ctx)?;
+    let mut body = body.as_slice();
+
+    let mut attrs = vec![];
+    if let Some((first, rest)) = body.split_first()
+        && let Some(doc) = docstring(first)
+    {
+        // module docstring
+        attrs = doc_attrs(doc);
+        for attr in &mut attrs {
+            attr.style = syn::AttrStyle::Inner(Default::default());
+        }
+        body = rest;
+    }
+
+    // functions can read constants which are defined after them
+    ctx.str_consts = body
+        .iter()
+        .filter_map(const_parts)
+        .filter(|(name, .., c)| {
+            matches!(c, Constant::Str(_)) && !ctx.globals.contains_key(name.as_str())
+        })
+        .map(|(name, ..)| name.to_string())
+        .collect();
+
+    let mut items = vec![];
+    let mut main = vec![];
+
+    for b in body {
+        if let Some(main_body) = main_guard(b) {
+            let comments = ctx.take_comments(b.range().start());
+            main.extend(
+                comment_item(comments.iter().map(|c| comment_text(c))).map(syn::Stmt::Item),
+            );
+            for s in main_body {
+                main.extend(r_s(s, ctx).map_err(|e| e.locate(code))?);
+            }
+            continue;
+        }
+
+        let item = global::r_static(b, ctx).or_else(|| r_const(b, ctx));
+        if item.is_none()
+            && !matches!(
+                b,
+                Stmt::FunctionDef(_)
+                    | Stmt::AsyncFunctionDef(_)
+                    | Stmt::ClassDef(_)
+                    | Stmt::TypeAlias(_)
+                    | Stmt::Import(_)
+                    | Stmt::ImportFrom(_)
+            )
+        {
+            main.extend(r_s(b, ctx).map_err(|e| e.locate(code))?);
+            continue;
+        }
+
+        // comments above an item are attached to it
+        let mut comments = ctx.take_comments(b.range().start());
+        let stmts = match item {
+            Some(Ok(item)) => vec![syn::Stmt::Item(item)],
+            Some(Err(e)) => vec![r_todo(b, e, ctx).map_err(|e| e.locate(code))?],
+            None => r_s(b, ctx).map_err(|e| e.locate(code))?,
+        };
+        for s in stmts {
+            let comments = std::mem::take(&mut comments);
+            let comments = comments.iter().map(|c| comment_text(c));
+            match s {
+                syn::Stmt::Item(mut item) => {
+                    if let Some(attrs) = item_attrs(&mut item) {
+                        attrs.splice(0..0, comment_attrs(comments));
+                    }
+                    items.push(item);
+                }
+                // `todo!()` of best effort mode
+                s => {
+                    main.extend(comment_item(comments).map(syn::Stmt::Item));
+                    main.push(s);
+                }
+            }
+        }
+        // e.g. above an import, which only adds a `use`
+        items.extend(comment_item(comments.iter().map(|c| comment_text(c))));
+    }
+
+    items.splice(0..0, ctx.imports.gen_imports());
+    items.extend(ctx.imports.gen_prelude());
+    items.extend(ctx.exceptions.error_enum());
+
+    if !main.is_empty() {
+        items.push(syn::Item::Fn(main_fn(main)));
+    }
+
+    // comments after the last statement
+    let comments = std::mem::take(&mut ctx.comments);
+    items.extend(comment_item(comments.iter().map(|(_, c)| comment_text(c))));
+
+    Ok(syn::File {
+        shebang: None,
+        attrs,
+        items,
+    })
+}
+
+/// body of `if __name__ == "__main__":`
+fn main_guard(node: &Stmt) -> Option<&[Stmt]> {
+    let Stmt::If(StmtIf {
+        test, body, orelse, ..
+    }) = node
+    else {
+        return None;
+    };
+    let Expr::Compare(ExprCompare {
+        left,
+        ops,
+        comparators,
+        ..
+    }) = test.as_ref()
+    else {
+        return None;
+    };
+
+    let is_name = left
+        .as_name_expr()
+        .is_some_and(|n| n.id.as_str() == "__name__");
+    let is_main = comparators.first().is_some_and(|c| {
+        c.as_constant_expr()
+            .and_then(|c| c.value.as_str())
+            .is_some_and(|s| s == "__main__")
+    });
+
+    (is_name && is_main && ops.as_slice() == [CmpOp::Eq] && orelse.is_empty())
+        .then_some(body.as_slice())
+}
+
+/// free standing string
+fn docstring(node: &Stmt) -> Option<&str> {
+    node.as_expr_stmt()?
+        .value
+        .as_constant_expr()?
+        .value
+        .as_str()
+        .map(|s| s.as_str())
+}
+
+/// `NAME = <literal>` at the top level of a module becomes a `const`
+fn r_const(node: &Stmt, ctx: &mut Ctx) -> Option<TResult<syn::Item>> {
+    let (name, annotation, value, c) = const_parts(node)?;
+
+    let t: syn::Type = match (annotation, c) {
+        // `String` can not be const
+        (_, Constant::Str(_)) => parse_quote!(&str),
+        (Some(a), _) => match r_annotation(a) {
+            Ok(t) => t,
+            Err(e) => return Some(Err(e)),
+        },
+        (None, Constant::Int(_)) => parse_quote!(isize),
+        (None, Constant::Float(_)) => parse_quote!(f64),
+        (None, Constant::Bool(_)) => parse_quote!(bool),
+        _ => return None,
+    };
+
+    let name = ident(name);
+    Some(r_e(value, ctx).map(|v| parse_quote!(const #name: #t = #v;)))
+}
+
+/// name, annotation, value and literal of an assignment of a literal to an uppercase name
+fn const_parts(node: &Stmt) -> Option<(&Identifier, Option<&Expr>, &Expr, &Constant)> {
+    let (target, annotation, value) = match node {
+        Stmt::Assign(StmtAssign { targets, value, .. }) if targets.len() == 1 => {
+            (&targets[0], None, value.as_ref())
+        }
+        Stmt::AnnAssign(StmtAnnAssign {
+            target,
+            annotation,
+            value: Some(value),
+            ..
+        }) => (target.as_ref(), Some(annotation.as_ref()), value.as_ref()),
+        _ => return None,
+    };
+
+    let name = &target.as_name_expr()?.id;
+    if !name.chars().any(|c| c.is_ascii_uppercase()) || name.chars().any(|c| c.is_ascii_lowercase())
+    {
+        return None;
+    }
+
+    let literal = match value {
+        Expr::UnaryOp(ExprUnaryOp {
+            op: UnaryOp::USub,
+            operand,
+            ..
+        }) => operand.as_ref(),
+        v => v,
+    };
+    let Expr::Constant(ExprConstant { value: c, .. }) = literal else {
+        return None;
+    };
+    Some((name, annotation, value, c))
+}
+
+/// (possibly partial) output of `transpile`
+#[derive(Clone)]
+pub struct Transpiled<T = Vec<syn::Stmt>> {
+    pub output: T,
+    /// every statement which could not be transpiled and every lossy translation
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// like `p2r` in `best_effort` mode, but reports all problems at once
+pub fn transpile(code: &str, ctx: &mut Ctx) -> Result<Transpiled, rustpython_parser::ParseError> {
+    ctx.best_effort = true;
+    let res = p2r(code, ctx);
+    collect_diagnostics(res, vec![], ctx)
+}
+
+/// like `p2r_module` in `best_effort` mode, but reports all problems at once
+pub fn transpile_module(
+    code: &str,
+    ctx: &mut Ctx,
+) -> Result<Transpiled<syn::File>, rustpython_parser::ParseError> {
+    ctx.best_effort = true;
+    let res = p2r_module(code, ctx);
+    let empty = syn::File {
+        shebang: None,
+        attrs: vec![],
+        items: vec![],
+    };
+    collect_diagnostics(res, empty, ctx)
+}
+
+/// `empty` is returned if nothing could be transpiled
+fn collect_diagnostics<T>(
+    res: Result<T, ParseError>,
+    empty: T,
+    ctx: &mut Ctx,
+) -> Result<Transpiled<T>, rustpython_parser::ParseError> {
+    let output = match res {
+        Ok(output) => output,
+        Err(ParseError::ParseError(e)) => return Err(e),
+        Err(ParseError::TranspileError(e)) => {
+            // only errors outside of statements end up here
+            ctx.diagnostics.push(e.into());
+            empty
+        }
+    };
+
+    Ok(Transpiled {
+        output,
+        diagnostics: std::mem::take(&mut ctx.diagnostics),
+    })
+}
+
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 enum PyMath {
     Sin,
@@ -141,24 +546,24 @@ impl PyMath {
     /// polyfill for the python math module
     ///
     /// this can all be inlined into the callers using rust-analyzer
-    fn to_rust(&self) -> &'static str {
+    fn to_rust(&self) -> syn::Item {
         match self {
-            PyMath::Sin => indoc::indoc! {"
+            PyMath::Sin => parse_quote! {
                 #[inline(always)] pub fn sin(v : f64) -> f64 { v.sin() }
-            "},
-            PyMath::Cos => indoc::indoc! {"
+            },
+            PyMath::Cos => parse_quote! {
                 #[inline(always)] pub fn cos(v : f64) -> f64 { v.cos() }
-            "},
-            PyMath::Pow => indoc::indoc! {"
+            },
+            PyMath::Pow => parse_quote! {
                 #[inline(always)] pub fn pow(a : f64, b : f64) -> f64 { a.powf(b) }
-            "},
-            PyMath::Abs => indoc::indoc! {"
+            },
+            PyMath::Abs => parse_quote! {
                 #[inline(always)] pub fn abs(a : f64) -> f64 { a.abs() }
-            "},
-            PyMath::Sqrt => indoc::indoc! {"
+            },
+            PyMath::Sqrt => parse_quote! {
                 #[inline(always)] pub fn sqrt(a : f64) -> f64 { a.sqrt() }
-            "},
-            PyMath::Pi => indoc::indoc! {"pub const pi: f64 = std::f64::consts::PI;"},
+            },
+            PyMath::Pi => parse_quote! {pub const pi: f64 = std::f64::consts::PI;},
         }
     }
 }
@@ -171,6 +576,14 @@ pub enum Promotion {
     Into,
     /// returning numpy arrays need py arg and need into_pyarray on return
     IntoPyArray,
+    /// `__getitem__` returns a reference for `Index`
+    Ref,
+    /// `__str__` and `__repr__` write the returned value to `formatter`
+    Write,
+    /// `__hash__` feeds the returned value into `state`
+    Hash,
+    /// `__enter__` returns `self` and `__exit__` runs in `Drop`, returned values are dropped
+    Ignore,
 }
 
 /// Mapping of:
@@ -189,40 +602,36 @@ pub struct Imports {
 }
 
 impl Imports {
-    fn gen_prelude(&self) -> String {
+    fn gen_prelude(&self) -> Option<syn::Item> {
         if self.math.is_empty() {
-            return String::new();
+            return None;
         }
 
-        let mut res = "\nmod prelude {\n".to_string();
-        for (name, _rename) in &self.math {
-            let rust = PyMath::from(name).to_rust();
-            res += rust;
-            res += "\n"
-        }
-
-        res += "\n}\n";
-        res
+        let fns = self.math.keys().map(|name| PyMath::from(name).to_rust());
+        Some(parse_quote! {
+            mod prelude {
+                #(#fns)*
+            }
+        })
     }
 
-    fn gen_imports(&self) -> String {
+    fn gen_imports(&self) -> Option<syn::Item> {
         if self.math.is_empty() {
-            return String::new();
+            return None;
         }
 
-        let imports = &self
-            .math
-            .iter()
-            .map(|(name, rename)| {
-                if let Some(rename) = rename {
-                    format!("{name} as {rename}")
-                } else {
-                    name.to_string()
-                }
-            })
-            .intersperse_with(|| ",".to_string())
-            .collect::<String>();
-        format!("use prelude::{{{imports}}};")
+        let imports = self.math.iter().map(|(name, rename)| -> syn::UseTree {
+            let name = ident(name);
+            if let Some(rename) = rename {
+                let rename = ident(rename);
+                parse_quote!(#name as #rename)
+            } else {
+                parse_quote!(#name)
+            }
+        });
+        Some(parse_quote! {
+            use prelude::{#(#imports),*};
+        })
     }
 }
 
@@ -231,12 +640,72 @@ pub struct Ctx {
     /// List of mappings of name -> member names
     pub classes: Vec<(String, Vec<String>)>,
     pub enums: Vec<String>,
-    pub in_enum: bool,
-    pub declare_var_mut: bool,
+    /// classes with an `__init__` method, which is translated to `new`
+    pub constructors: Vec<String>,
+    /// abstract base classes and protocols, name -> method names
+    pub traits: Vec<(String, Vec<String>)>,
     pub ret_needs_promotion: Promotion,
     /// List of arguments (name, type_comment) which are np arrays
     pub numpy_array_args: Vec<(String, String)>,
     pub imports: Imports,
+    /// instead of failing, emit `todo!()` with the python code of the statement
+    /// which could not be transpiled as a comment and continue
+    pub best_effort: bool,
+    /// translate exceptions into a generated `Error` enum, functions which raise return
+    /// `Result<T, Error>` instead of panicking
+    pub result_errors: bool,
+    /// async runtime which the functions of `asyncio` map to
+    pub runtime: Runtime,
+    /// errors of `best_effort` mode and warnings
+    pub diagnostics: Vec<Diagnostic>,
+    /// python code which is being transpiled, set by `p2r`
+    source: String,
+    /// python comments which are not yet emitted (start, text)
+    comments: std::collections::VecDeque<(TextSize, String)>,
+    /// inferred types of functions, classes and local variables
+    types: infer::Types,
+    /// bound and mutated names of the function which is transpiled
+    scopes: scope::Scopes,
+    /// how the method which is transpiled takes `self`
+    receiver: scope::Receiver,
+    /// fields of `self` which are local variables, while `__init__` is transpiled into `new`
+    init_fields: Vec<String>,
+    /// class -> base class which is embedded as the `base` field
+    bases: std::collections::HashMap<String, String>,
+    /// a `@classmethod` is transpiled, `cls` is `Self`
+    class_method: bool,
+    /// exceptions of the module and the functions which raise them, in `result_errors` mode
+    exceptions: exception::Exceptions,
+    /// the function which is transpiled returns `Result<T, Error>`
+    ret_result: bool,
+    /// type of the values which the function which is transpiled returns
+    ret: infer::Ty,
+    /// the body of a `try` is transpiled, it runs in a closure
+    try_body: bool,
+    /// `break` and `continue` of the loop around the `try` whose body is transpiled leave its
+    /// closure, the loop jumps after the call
+    try_jumps: bool,
+    /// name of the exception of the `except` clause which is transpiled
+    caught: Option<String>,
+    /// files which are opened by the `with` statements which are transpiled
+    files: Vec<String>,
+    /// the loop of a generator is transpiled into the closure of `from_fn`
+    generator: Option<generator::Yields>,
+    /// enum -> names of its variants
+    variants: std::collections::HashMap<String, Vec<String>>,
+    /// labels of the blocks around the loops which are transpiled, innermost last, `break`
+    /// leaves the block of a loop with `else`
+    breaks: Vec<Option<ElseBlock>>,
+    /// variables of the module which are assigned with `global`, they become statics
+    globals: std::collections::HashMap<String, global::Global>,
+    /// owned globals which are locked once for the statement which is transpiled, they are
+    /// bound to references with their name
+    locked: Vec<String>,
+    /// calls which are evaluated into a variable before the statement which is transpiled locks
+    /// a global, with the name of the variable
+    hoisted: Vec<(TextRange, String)>,
+    /// string constants of the module, they are `&str` and become a `String` where they are read
+    str_consts: std::collections::HashSet<String>,
 }
 
 impl Ctx {
@@ -253,84 +722,160 @@ impl Ctx {
     fn has_enum(&self, e: &str) -> bool {
         self.enums.iter().any(|s| s.as_str() == e)
     }
-}
-
-/// convert statement
-fn r_s(node: &Stmt, ctx: &mut Ctx) -> TResult<String> {
-    match node {
-        Stmt::FunctionDef(StmtFunctionDef {
-            name,
-            args,
-            body,
-            decorator_list: _,
-            returns,
-            type_comment: _,
-            range: _,
-            type_params: _,
-        }) => {
-            let args = &args
-                .args
-                .iter()
-                .map(|a| r_a(a, ctx))
-                .intersperse_with(|| Ok(", ".to_string()))
-                .collect::<TResult<String>>()?;
 
-            let (args, lifetimes) = if ctx.numpy_array_args.is_empty() {
-                (args.to_string(), String::new())
-            } else {
-                (
-                    format!("py: pyo3::Python<'py>, {args}"),
-                    "<'py>".to_string(),
-                )
-            };
+    fn has_constructor(&self, class: &str) -> bool {
+        self.constructors.iter().any(|s| s.as_str() == class)
+    }
 
-            let ret_type = match returns {
-                Some(r) => r_annotation(r)?,
-                None => "()".to_string(),
-            };
+    /// `self.field` as a local variable while `__init__` is transpiled
+    fn init_field(&self, e: &Expr) -> Option<Expr> {
+        if let Expr::Attribute(ExprAttribute { value, attr, .. }) = e
+            && value
+                .as_name_expr()
+                .is_some_and(|n| n.id.as_str() == "self")
+            && self.init_fields.iter().any(|f| f == attr.as_str())
+        {
+            return Some(
+                ExprName {
+                    range: e.range(),
+                    id: attr.clone(),
+                    ctx: ExprContext::Store,
+                }
+                .into(),
+            );
+        }
+        None
+    }
 
-            // TODO do this with types instead of string comparisons?
-            if ret_type.starts_with("Option<") {
-                ctx.ret_needs_promotion = Promotion::Into;
+    /// `value.attr` is a property (with a setter if `setter`) of the class of `value`, values of
+    /// unknown type are assumed to not have properties
+    fn is_property(&mut self, value: &Expr, attr: &str, range: TextRange, setter: bool) -> bool {
+        let properties = match setter {
+            true => &self.types.setters,
+            false => &self.types.properties,
+        };
+        match self.types.ty(value, &self.types.locals) {
+            infer::Ty::Class(class) => properties.get(&class).is_some_and(|p| p.contains(attr)),
+            infer::Ty::Unknown if properties.values().any(|p| p.contains(attr)) => {
+                self.warn(
+                    "Attribute",
+                    range,
+                    format!("`{attr}` is a property of a class, but the type of the value is unknown, it is accessed as a field"),
+                );
+                false
             }
-            let ret_type = if ret_type.starts_with("numpy::") {
-                ctx.ret_needs_promotion = Promotion::IntoPyArray;
-                format!("&'py {ret_type}")
-            } else {
-                ret_type
-            };
+            _ => false,
+        }
+    }
 
-            let pyo3_conversions =
-                ctx.numpy_array_args
-                    .iter()
-                    .fold(String::new(), |mut output, (name, _t)| {
-                        let _ = write!(output, "let {name} = {name}.as_array().to_owned();");
-                        output
-                    });
+    /// records python code which was transpiled but might behave differently
+    fn warn(&mut self, kind: &'static str, range: TextRange, message: impl Into<String>) {
+        let mut span = PySpan::new(kind, range);
+        span.locate(&self.source);
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Warning,
+            span: Some(span),
+            message: message.into(),
+            link: None,
+        });
+    }
 
-            let mut doc_comment = None;
-            let body = body
-                .iter()
-                .map(|s| {
-                    if let Some(Some(Some(doc))) = s
-                        .as_expr_stmt()
-                        .map(|e| e.value.as_constant_expr().map(|c| c.value.as_str()))
-                    {
-                        // free standing string
-                        // -> doc comment
-                        doc_comment = Some(format!("/*! {doc} */"));
-                        Ok(String::new())
-                    } else {
-                        r_s(s, ctx)
-                    }
-                })
-                .collect::<TResult<String>>()?;
+    /// comments before `start` and on the same line as `start`
+    fn take_comments(&mut self, start: TextSize) -> Vec<String> {
+        let start = usize::from(start);
+        let line_end = self.source[start.min(self.source.len())..]
+            .find('\n')
+            .map_or(self.source.len(), |i| start + i);
+
+        let mut res = vec![];
+        while let Some((offset, _)) = self.comments.front()
+            && usize::from(*offset) < line_end
+        {
+            let (_, c) = self.comments.pop_front().unwrap();
+            res.push(c);
+        }
+        res
+    }
 
-            Ok(format!(
-                "{doc}\nfn {n}{lifetimes}({args}) -> {ret_type} {{\n{pyo3_conversions}\n\n{body}}}\n",
-                n = name,
-                doc = doc_comment.unwrap_or_default()
-            ))
+    /// python code of `range`, later lines are dedented to the first one
+    fn python_source(&self, range: TextRange) -> Vec<&str> {
+        let (start, end) = (usize::from(range.start()), usize::from(range.end()));
+        let Some(code) = self.source.get(start..end) else {
+            return vec![];
+        };
+        let line_start = self.source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let indent = self.source[line_start..start].len();
+
+        code.lines()
+            .enumerate()
+            .map(|(i, l)| {
+                if i == 0 {
+                    l
+                } else {
+                    let strip = l.len() - l.trim_start_matches(' ').len();
+                    &l[strip.min(indent)..]
+                }
+            })
+            .collect()
+    }
+}
+
+/// convert a list of statements
+fn r_body(body: &[Stmt], ctx: &mut Ctx) -> TResult<Vec<syn::Stmt>> {
+    r_body_binding(body, None, ctx)
+}
+
+/// convert `body` into a new rust block in which the names of `target` are bound
+fn r_body_binding(body: &[Stmt], target: Option<&Expr>, ctx: &mut Ctx) -> TResult<Vec<syn::Stmt>> {
+    ctx.scopes.push();
+    if let Some(target) = target {
+        ctx.scopes.declare(target);
+    }
+    let stmts = body
+        .iter()
+        .map(|s| r_s(s, ctx))
+        .collect::<TResult<Vec<_>>>();
+    ctx.scopes.pop();
+    Ok(stmts?.into_iter().flatten().collect())
+}
+
+/// convert statement
+fn r_s(node: &Stmt, ctx: &mut Ctx) -> TResult<Vec<syn::Stmt>> {
+    let comments = ctx.take_comments(node.range().start());
+    let comments = comment_item(comments.iter().map(|c| comment_text(c))).map(syn::Stmt::Item);
+
+    match global::r_locking(node, ctx, r_s_inner) {
+        Ok(stmts) => Ok(comments.into_iter().chain(stmts).collect()),
+        Err(e) => Ok(comments
+            .into_iter()
+            .chain([r_todo(node, e, ctx)?])
+            .collect()),
+    }
+}
+
+/// in best effort mode `todo!()` with the python source of `node` above it and a diagnostic,
+/// otherwise the error
+fn r_todo(node: &Stmt, e: TranspileError, ctx: &mut Ctx) -> TResult<syn::Stmt> {
+    let e = e.at(stmt_kind(node), node.range());
+    if !ctx.best_effort {
+        return Err(e);
+    }
+    ctx.diagnostics.push(e.locate(&ctx.source).into());
+
+    let mut todo: syn::Stmt = parse_quote!(todo!(););
+    if let syn::Stmt::Macro(m) = &mut todo {
+        m.attrs = comment_attrs(ctx.python_source(node.range()));
+    }
+    Ok(todo)
+}
+
+fn r_s_inner(node: &Stmt, ctx: &mut Ctx) -> TResult<Vec<syn::Stmt>> {
+    match node {
+        Stmt::FunctionDef(def) if global::is_closure(def) => global::r_closure(def, ctx),
+        Stmt::FunctionDef(def) => {
+            let mut items = params::r_struct(def, ctx)?;
+            items.push(syn::Stmt::Item(syn::Item::Fn(r_fn(def, ctx)?)));
+            Ok(items)
         }
         Stmt::ClassDef(StmtClassDef {
             name,
@@ -338,12 +883,42 @@ fn r_s(node: &Stmt, ctx: &mut Ctx) -> TResult<String> {
             range: _,
             bases,
             keywords: _,
-            decorator_list: _,
+            decorator_list,
             type_params: _,
         }) => {
+            if ctx.result_errors
+                && ctx
+                    .exceptions
+                    .is_exception_class(node.as_class_def_stmt().unwrap())
+            {
+                // a variant of `Error`
+                if body
+                    .iter()
+                    .any(|b| docstring(b).is_none() && !matches!(b, Stmt::Pass(_)))
+                {
+                    ctx.warn(
+                        "ClassDef",
+                        node.range(),
+                        "exception classes become variants of `Error` which only carry a message",
+                    );
+                }
+                return Ok(vec![]);
+            }
+            if inherit::is_trait(node.as_class_def_stmt().unwrap()) {
+                return inherit::r_trait(node.as_class_def_stmt().unwrap(), ctx);
+            }
+
+            // `async def` methods are translated like `def` and made `async`
+            let async_defs = body
+                .iter()
+                .filter_map(|b| b.as_async_function_def_stmt())
+                .map(asyncio::sync_def)
+                .collect::<Vec<_>>();
             let mut defs = vec![];
+            let mut default_impl = None;
+            let class = ident(name);
 
-            let struct_enum_def = if bases
+            let struct_enum_def: syn::Item = if bases
                 .iter()
                 .filter_map(|v| v.as_name_expr())
                 .any(|v| v.id.as_str() == "Enum")
@@ -351,99 +926,290 @@ fn r_s(node: &Stmt, ctx: &mut Ctx) -> TResult<String> {
                 // enum
                 ctx.enums.push(name.to_string());
 
-                let mut fields = vec![];
+                let mut variants = vec![];
+                let mut values = vec![];
 
                 for b in body.iter() {
-                    if let Some(field) = b.clone().assign_stmt() {
-                        fields.push(field);
-                    } else if let Some(def) = b.clone().function_def_stmt() {
+                    if let Some(field) = b.as_assign_stmt()
+                        && let [Expr::Name(target)] = field.targets.as_slice()
+                    {
+                        ctx.variants
+                            .entry(name.to_string())
+                            .or_default()
+                            .push(target.id.to_string());
+                        variants.push(ident(&target.id));
+                        values.push(r_e(&field.value, ctx)?);
+                    } else if let Some(def) = b.as_function_def_stmt() {
                         defs.push(def)
+                    } else if b.as_async_function_def_stmt().is_some() {
+                        // in `async_defs`
                     } else {
                         dbg!(b);
                         return Err(todo_link!());
                     }
                 }
 
-                ctx.in_enum = true;
-                let res = format!(
-                    "\n#[derive(Debug, Clone)]\nenum {name} {{\n{body}\n}}",
-                    body = fields
-                        .iter()
-                        // TODO this reboxing is not very elegant
-                        .map(|v| r_s(&Stmt::Assign(v.clone()), ctx))
-                        .intersperse_with(|| Ok(",\n".to_string()))
-                        .collect::<TResult<String>>()?
-                );
-                ctx.in_enum = false;
-                res
+                defs.extend(&async_defs);
+                let derives = dunder::derives(decorator_list, &defs, &[], ctx);
+                parse_quote! {
+                    #[derive(#(#derives),*)]
+                    enum #class {
+                        #(#variants = #values),*
+                    }
+                }
             } else {
                 // struct
-                let mut typed_fields = vec![];
                 let mut field_names = vec![];
+                let mut field_types = vec![];
+                let mut defaults = vec![];
+
+                // composition instead of inheritance
+                let base = bases
+                    .iter()
+                    .filter_map(inherit::base_name)
+                    .find(|b| ctx.get_class_members(b).is_some() || ctx.has_constructor(b));
+                if let Some(base) = base {
+                    ctx.bases.insert(name.to_string(), base.to_string());
+                    field_names.push("base".to_string());
+                    field_types.push(syn::Type::Path(syn::TypePath {
+                        qself: None,
+                        path: ident(base).into(),
+                    }));
+                    defaults.push(None);
+                }
 
                 for b in body.iter() {
-                    if let Some(field) = b.clone().ann_assign_stmt() {
-                        field_names.push(r_e(&field.target, ctx)?);
-                        // TODO r_annotation?
-                        typed_fields.push(r_s(&Stmt::AnnAssign(field.clone()), ctx)?);
-                    } else if let Some(def) = b.clone().function_def_stmt() {
+                    if let Some(field) = b.as_ann_assign_stmt()
+                        && let Expr::Name(target) = field.target.as_ref()
+                    {
+                        field_names.push(target.id.to_string());
+                        field_types.push(r_annotation(&field.annotation)?);
+                        defaults.push(
+                            field
+                                .value
+                                .as_deref()
+                                .map(|v| r_default(v, ctx))
+                                .transpose()?,
+                        );
+                    } else if let Some(def) = b.as_function_def_stmt() {
                         defs.push(def)
+                    } else if b.as_async_function_def_stmt().is_some() {
+                        // in `async_defs`
                     } else {
                         return Err(todo_link!());
                     }
                 }
 
-                ctx.classes.push((name.to_string(), field_names));
+                defs.extend(&async_defs);
 
-                format!(
-                    "\n#[derive(Debug, Clone)]\nstruct {name} {{\n{body}\n}}",
-                    body = typed_fields
-                        .iter()
-                        .cloned()
-                        .intersperse(",\n".to_string())
-                        .collect::<String>()
-                )
-            };
+                // fields which are only assigned in `__init__`
+                for (field, ty) in ctx.types.classes.get(name.as_str()).into_iter().flatten() {
+                    if !field_names.contains(field) {
+                        field_names.push(field.clone());
+                        field_types.push(ty.to_syn().unwrap_or_else(|| parse_quote!(())));
+                        defaults.push(None);
+                    }
+                }
 
-            let impls = if defs.is_empty() {
-                String::new()
-            } else {
-                // TODO impl new
-                format!(
-                    "impl {name} {{\n{impls} }}\n",
-                    impls = defs
-                        .iter()
-                        .map(|v| r_s(&Stmt::FunctionDef(v.clone()), ctx))
-                        .intersperse_with(|| Ok("\n".to_string()))
-                        .collect::<TResult<String>>()?
-                )
+                if defaults.iter().any(Option::is_some) {
+                    let fields = field_names.iter().map(|f| ident(f));
+                    let values = defaults
+                        .into_iter()
+                        .map(|d| d.unwrap_or_else(|| parse_quote!(Default::default())));
+                    default_impl = Some(parse_quote! {
+                        impl Default for #class {
+                            fn default() -> Self {
+                                Self {
+                                    #(#fields: #values),*
+                                }
+                            }
+                        }
+                    });
+                }
+
+                let fields = field_names.iter().map(|f| ident(f));
+                if defs.iter().any(|d| d.name.as_str() == "__init__") {
+                    ctx.constructors.push(name.to_string());
+                } else {
+                    ctx.classes.push((name.to_string(), field_names.clone()));
+                }
+
+                let mut tys = base
+                    .map(|b| infer::Ty::Class(b.to_string()))
+                    .into_iter()
+                    .collect::<Vec<_>>();
+                tys.extend(
+                    ctx.types
+                        .classes
+                        .get(name.as_str())
+                        .into_iter()
+                        .flatten()
+                        .map(|(_, ty)| ty.clone()),
+                );
+                let derives = dunder::derives(decorator_list, &defs, &tys, ctx);
+                parse_quote! {
+                    #[derive(#(#derives),*)]
+                    struct #class {
+                        #(#fields: #field_types),*
+                    }
+                }
             };
 
-            Ok(format!("{struct_enum_def}\n{impls}"))
+            let mut res = vec![syn::Stmt::Item(struct_enum_def)];
+            res.extend(default_impl.map(syn::Stmt::Item));
+
+            if !defs.is_empty() {
+                let fields = ctx.types.classes.get(name.as_str()).cloned();
+                // setters have the same name as their property
+                let getters = defs
+                    .iter()
+                    .copied()
+                    .filter(|d| decorator::setter(&d.decorator_list).is_none())
+                    .collect::<Vec<_>>();
+                // methods of a base class return clones of fields instead of consuming `self`
+                let is_base = ctx.types.base_classes.contains(name.as_str());
+                let receivers = scope::receivers(&getters, |f| {
+                    !is_base
+                        && fields
+                            .iter()
+                            .flatten()
+                            .any(|(field, ty)| field == f && ty.is_owned())
+                });
+
+                let outer_class = ctx.types.class.replace(name.to_string());
+                let impls = defs
+                    .iter()
+                    .filter(|def| !dunder::is_dunder(&def.name))
+                    .map(|def| {
+                        let setter = decorator::setter(&def.decorator_list);
+                        ctx.receiver = match setter {
+                            Some(_) => scope::Receiver {
+                                mutates: true,
+                                consumes: false,
+                            },
+                            None => receivers[def.name.as_str()],
+                        };
+                        if def.name.as_str() == "__init__" {
+                            return Ok(syn::ImplItem::Fn(impl_fn(r_init(def, name, ctx)?)));
+                        }
+
+                        let class_method = decorator::has(&def.decorator_list, "classmethod");
+                        let outer = std::mem::replace(&mut ctx.class_method, class_method);
+                        let f = r_fn(def, ctx);
+                        ctx.class_method = outer;
+                        let mut f = f?;
+                        if class_method {
+                            // `cls` is `Self`
+                            f.sig.inputs = f.sig.inputs.into_iter().skip(1).collect();
+                        }
+                        if let Some(property) = setter {
+                            f.sig.ident = ident(&format!("set_{property}"));
+                        }
+                        if async_defs.iter().any(|d| d.name == def.name) {
+                            f.sig.asyncness = Some(Default::default());
+                        }
+                        Ok(syn::ImplItem::Fn(impl_fn(f)))
+                    })
+                    .collect::<TResult<Vec<_>>>();
+                let traits = dunder::impls(name, &defs, ctx);
+                ctx.types.class = outer_class;
+                let mut impls = impls?;
+                let (traits, methods) = traits?;
+                impls.extend(methods);
+                let trait_impls =
+                    inherit::trait_impls(node.as_class_def_stmt().unwrap(), &mut impls, ctx);
+
+                if !impls.is_empty() {
+                    res.push(syn::Stmt::Item(parse_quote! {
+                        impl #class {
+                            #(#impls)*
+                        }
+                    }));
+                }
+                res.extend(trait_impls.into_iter().map(syn::Stmt::Item));
+                res.extend(traits.into_iter().map(syn::Stmt::Item));
+            }
+
+            if let Some(base) = ctx.bases.get(name.as_str()) {
+                res.extend(inherit::deref_impls(name, base).map(syn::Stmt::Item));
+            }
+
+            Ok(res)
         }
         Stmt::Return(StmtReturn { value, range: _ }) => {
-            if let Some(v) = value {
-                let v = r_e(v, ctx)?;
-                match ctx.ret_needs_promotion {
-                    Promotion::None => Ok(format!("return {v};\n")),
-                    Promotion::Into => Ok(format!("return ({v}).into();\n")),
-                    Promotion::IntoPyArray => Ok(format!("return ({v}).into_pyarray(py);\n")),
+            if let Promotion::Ignore = ctx.ret_needs_promotion {
+                return Ok(vec![parse_quote!(return;)]);
+            }
+            if ctx.generator.is_some() {
+                // the value of `StopIteration`
+                if value.is_some() {
+                    return Err(todo_link!());
                 }
+                return Ok(vec![parse_quote!(return None;)]);
+            }
+            if let Some(value) = value {
+                let v = match ctx.ret_needs_promotion {
+                    Promotion::None if ctx.ret == infer::Ty::Str => r_owned(value, ctx)?,
+                    _ => r_e(value, ctx)?,
+                };
+                let v = ret_cast(value, v, ctx);
+                // an owned field can only be moved out of a consumed `self`
+                let v = match value.as_ref() {
+                    Expr::Attribute(ExprAttribute { value: obj, .. })
+                        if obj.as_name_expr().is_some_and(|n| n.id.as_str() == "self")
+                            && !ctx.receiver.consumes
+                            && ctx.types.ty(value, &ctx.types.locals).is_owned() =>
+                    {
+                        method_call(v, "clone", [])
+                    }
+                    _ => v,
+                };
+                let v = match ctx.ret_needs_promotion {
+                    Promotion::None | Promotion::Ignore => v,
+                    Promotion::Into => method_call(v, "into", []),
+                    Promotion::IntoPyArray => method_call(v, "into_pyarray", [path("py")]),
+                    Promotion::Ref => reference(v),
+                    Promotion::Write => {
+                        parse_quote!(write!(formatter, "{}", #v))
+                    }
+                    Promotion::Hash => match v {
+                        // `hash(x)` -> `x.hash(state)`
+                        syn::Expr::Call(c)
+                            if matches!(c.func.as_ref(), syn::Expr::Path(p) if p.path.is_ident("hash"))
+                                && c.args.len() == 1 =>
+                        {
+                            let arg = match value.as_ref() {
+                                Expr::Call(ExprCall { args, .. }) => &args[0],
+                                value => value,
+                            };
+                            let v = dunder::float_bits(arg, c.args[0].clone(), ctx);
+                            method_call(v, "hash", [path("state")])
+                        }
+                        v => {
+                            method_call(dunder::float_bits(value, v, ctx), "hash", [path("state")])
+                        }
+                    },
+                };
+                let v = if ctx.ret_result {
+                    parse_quote!(Ok(#v))
+                } else {
+                    v
+                };
+                Ok(vec![stmt(exception::r_return(v, ctx))])
+            } else if ctx.ret_result {
+                Ok(vec![stmt(exception::r_return(parse_quote!(Ok(())), ctx))])
+            } else if ctx.try_body {
+                Ok(vec![stmt(exception::r_return(parse_quote!(()), ctx))])
             } else {
                 Err(todo_link!())
             }
         }
         Stmt::Delete(StmtDelete { range: _, targets }) => {
             // TODO for copy types we could add a call like `_ = name; to remove them from the scope`
-            Ok(targets
-                .iter()
-                .map(|t| r_e(t, ctx))
-                .collect::<TResult<Vec<String>>>()?
+            targets
                 .iter()
-                .cloned()
-                .map(|name| format!("drop({name})"))
-                .intersperse_with(|| ";".to_string())
-                .collect::<String>())
+                .map(|t| Ok(stmt(call(path("drop"), [r_e(t, ctx)?]))))
+                .collect()
         }
         Stmt::Assign(StmtAssign {
             targets,
@@ -451,54 +1217,66 @@ fn r_s(node: &Stmt, ctx: &mut Ctx) -> TResult<String> {
             type_comment: _,
             range: _,
         }) => {
-            if targets.len() != 1 {
-                return Err(todo_link!());
+            if let Some(s) = global::r_static(node, ctx) {
+                return Ok(vec![syn::Stmt::Item(s?)]);
             }
-
-            if ctx.in_enum {
-                Ok(format!(
-                    "{t} = {v}",
-                    t = r_e(&targets[0], ctx)?,
-                    v = r_e(value, ctx)?
-                ))
-            } else {
-                let v = r_e(value, ctx)?;
-                let t = r_e(&targets[0], ctx)?;
-                if t.starts_with("self") {
-                    Ok(format!("{t} = {v};\n"))
-                } else {
-                    ctx.declare_var_mut = true;
-                    let t = r_e(&targets[0], ctx)?;
-                    ctx.declare_var_mut = false;
-                    Ok(format!("let {t} = {v};\n"))
+            let [first, rest @ ..] = targets.as_slice() else {
+                return Err(todo_link!());
+            };
+            let mut stmts = r_assign(first, value, None, ctx)?;
+            // `a = b = value` assigns `a` to `b`, the value is only evaluated once
+            let owned = ctx.types.ty(value, &ctx.types.locals).is_owned();
+            for target in rest {
+                let mut v = r_e(first, ctx)?;
+                if owned {
+                    v = method_call(v, "clone", []);
                 }
+                stmts.extend(r_assign(target, first, Some(v), ctx)?);
             }
+            Ok(stmts)
         }
         Stmt::AugAssign(StmtAugAssign {
             range: _,
             target,
             op,
             value,
-        }) => Ok(format!(
-            "{l} {o}= {r};",
-            l = r_e(target, ctx)?,
-            o = r_o(op)?,
-            r = r_e(value, ctx)?
-        )),
+        }) => {
+            if let Expr::Name(n) = target.as_ref()
+                && global::is_static(&n.id, ctx)
+            {
+                return global::r_assign(&n.id, Some(op), value, ctx);
+            }
+            let (t, v) = (r_e(target, ctx)?, r_e(value, ctx)?);
+            let (_, v) = numeric_cast((target, t.clone()), (value, v), ctx);
+            Ok(vec![stmt(binary(t, r_o_assign(op)?, v))])
+        }
         Stmt::AnnAssign(StmtAnnAssign {
             range: _,
             target,
             annotation,
-            value: _,
+            value,
             simple: _,
         }) => {
-            // this could be used to impl Default for this struct
-            // dbg!(&value);
-            Ok(format!(
-                "{target} : {annotation}",
-                target = r_e(target, ctx)?,
-                annotation = r_annotation(annotation)?
-            ))
+            if let Some(s) = global::r_static(node, ctx) {
+                return Ok(vec![syn::Stmt::Item(s?)]);
+            }
+            let annotation = r_annotation(annotation)?;
+            let field = ctx.init_field(target);
+            let target = field.as_ref().unwrap_or(target);
+            if let Some(value) = value
+                && ctx.scopes.is_assignable(target)
+            {
+                return Ok(vec![stmt(assign(r_e(target, ctx)?, r_e(value, ctx)?))]);
+            }
+            let t = r_target(target, ctx)?;
+            ctx.scopes.declare(target);
+
+            if let Some(value) = value {
+                let v = r_e(value, ctx)?;
+                Ok(vec![parse_quote!(let #t: #annotation = #v;)])
+            } else {
+                Ok(vec![parse_quote!(let #t: #annotation;)])
+            }
         }
         Stmt::For(StmtFor {
             target,
@@ -508,25 +1286,27 @@ fn r_s(node: &Stmt, ctx: &mut Ctx) -> TResult<String> {
             type_comment: _,
             range: _,
         }) => {
-            // https://book.pythontips.com/en/latest/for_-_else.html#else-clause
-            if !orelse.is_empty() {
-                return Err(todo_link!());
-            }
-
-            let iter = r_e(iter, ctx)?;
+            let iter = match context::r_file_lines(iter, ctx) {
+                Some(lines) => lines,
+                None => r_iter(iter, ctx)?,
+            };
+            let label = else_block(body, orelse, ctx);
+            ctx.breaks.push(label.clone());
+            let outer_jumps = std::mem::replace(&mut ctx.try_jumps, false);
+            let body = r_body_binding(body, Some(target), ctx);
+            ctx.try_jumps = outer_jumps;
+            ctx.breaks.pop();
             // TODO translate `target` into nested tuple if the iter is a zip
-            Ok(format!(
-                "for {target} in {iter} {{\n{body}}}\n",
-                target = r_e(target, ctx)?,
-                body = body
-                    .iter()
-                    .map(|s| r_s(s, ctx))
-                    .collect::<TResult<Vec<String>>>()?
-                    .iter()
-                    .cloned()
-                    .padded(";\n".to_string())
-                    .collect::<String>(),
-            ))
+            let for_loop = syn::Expr::ForLoop(syn::ExprForLoop {
+                attrs: vec![],
+                label: None,
+                for_token: Default::default(),
+                pat: Box::new(r_target(target, ctx)?),
+                in_token: Default::default(),
+                expr: Box::new(iter),
+                body: block(body?),
+            });
+            r_loop_else(for_loop, label, orelse, ctx)
         }
         Stmt::While(StmtWhile {
             range: _,
@@ -534,30 +1314,22 @@ fn r_s(node: &Stmt, ctx: &mut Ctx) -> TResult<String> {
             body,
             orelse,
         }) => {
-            let orelse = orelse
-                .iter()
-                .map(|s| r_s(s, ctx))
-                .collect::<TResult<Vec<String>>>()?
-                .iter()
-                .cloned()
-                .padded(";\n".to_string())
-                .collect::<String>();
-
             let test = r_e(test, ctx)?;
-            let orelse = if orelse.is_empty() {
-                String::new()
-            } else {
-                format!("if !({test}) {{{orelse}}}")
-            };
-
-            Ok(format!(
-                "while {test} {{\n{body}\n}}\n{orelse}",
-                body = body
-                    .iter()
-                    .map(|s| r_s(s, ctx))
-                    .intersperse_with(|| Ok("\n".to_string()))
-                    .collect::<TResult<String>>()?
-            ))
+            let label = else_block(body, orelse, ctx);
+            ctx.breaks.push(label.clone());
+            let outer_jumps = std::mem::replace(&mut ctx.try_jumps, false);
+            let body = r_body(body, ctx);
+            ctx.try_jumps = outer_jumps;
+            ctx.breaks.pop();
+
+            let while_loop = syn::Expr::While(syn::ExprWhile {
+                attrs: vec![],
+                label: None,
+                while_token: Default::default(),
+                cond: Box::new(test),
+                body: block(body?),
+            });
+            r_loop_else(while_loop, label, orelse, ctx)
         }
         Stmt::If(StmtIf {
             test,
@@ -567,140 +1339,71 @@ fn r_s(node: &Stmt, ctx: &mut Ctx) -> TResult<String> {
         }) => {
             // https://docs.python.org/3/library/ast.html#ast.If
             let test = r_e(test, ctx)?;
-            // todo
-            let body = body
-                .iter()
-                .map(|s| r_s(s, ctx))
-                .collect::<TResult<Vec<String>>>()?
-                .iter()
-                .cloned()
-                .padded(";\n".to_string())
-                .collect::<String>();
-
-            let orelse = orelse
-                .iter()
-                .map(|s| r_s(s, ctx))
-                .collect::<TResult<Vec<String>>>()?
-                .iter()
-                .cloned()
-                .padded(";\n".to_string())
-                .collect::<String>();
-
-            Ok(if orelse.is_empty() {
-                format!("if {test} {{\n{body}\n}}\n")
-            } else {
-                format!("if {test} {{\n{body}\n}} else {{{orelse}}}\n")
-            })
+            let body = r_body(body, ctx)?;
+            let orelse = r_body(orelse, ctx)?;
+
+            Ok(vec![stmt(if_expr(
+                test,
+                body,
+                (!orelse.is_empty()).then(|| block_expr(orelse)),
+            ))])
         }
-        Stmt::Match(StmtMatch {
-            range: _,
-            subject,
-            cases,
-        }) => Ok(format!(
-            "match {subject} {{\n{cases}\n}}",
-            subject = r_e(subject, ctx)?,
-            cases = cases
-                .iter()
-                .map(
-                    |MatchCase {
-                         range: _,
-                         pattern,
-                         guard,
-                         body,
-                     }| {
-                        if guard.is_some() {
-                            // "https://peps.python.org/pep-0622/#guards"
-                            Err(todo_link!())
-                        } else {
-                            Ok(format!(
-                                "{pattern} => {{ {body} }},",
-                                pattern = r_p(pattern, ctx)?,
-                                body = body
-                                    .iter()
-                                    .map(|s| r_s(s, ctx))
-                                    .collect::<TResult<String>>()?
-                            ))
-                        }
-                    }
-                )
-                .collect::<TResult<String>>()?
-        )),
-        Stmt::Raise(StmtRaise {
-            range: _,
-            exc,
-            cause,
-        }) => {
+        Stmt::Match(node) => pattern::r_match(node, ctx),
+        Stmt::Raise(StmtRaise { range, exc, cause }) => {
+            if exc.is_none() && ctx.caught.is_some() {
+                return Ok(vec![stmt(exception::r_reraise(ctx)?)]);
+            }
+            if ctx.result_errors
+                && (ctx.ret_result || ctx.try_body)
+                && let Some(exc) = exc
+            {
+                let e = exception::r_raise(exc, cause.as_deref(), ctx)?;
+                return Ok(vec![stmt(parse_quote!(return Err(#e)))]);
+            }
             if cause.is_some() {
                 // https://docs.python.org/3/library/ast.html#ast.Raise
                 // raise x from y
                 return Err(todo_link!());
             }
 
+            // the end of `__next__`
+            if let Some(exc) = exc
+                && let Some(n) = exc
+                    .as_call_expr()
+                    .map_or(exc.as_ref(), |c| c.func.as_ref())
+                    .as_name_expr()
+                && n.id.as_str() == "StopIteration"
+            {
+                return Ok(vec![stmt(parse_quote!(return None))]);
+            }
+
             if let Some(exc) = exc {
                 // TODO create an error enum member with this
                 // name automatically
                 // this is a bit hard, because we'd ideally collect all
                 // possible exceptions
-                let exeption_class = exc.as_call_expr().unwrap();
-                let class_name = exeption_class.func.as_name_expr().unwrap().id.to_string();
+                let exeption_class = exc.as_call_expr().ok_or(todo_link!())?;
+                let class_name = exeption_class
+                    .func
+                    .as_name_expr()
+                    .ok_or(todo_link!())?
+                    .id
+                    .to_string();
 
                 let args = exeption_class
                     .args
                     .iter()
                     .map(|arg| r_e(arg, ctx))
-                    .intersperse_with(|| Ok(",".to_string()))
-                    .collect::<TResult<String>>()?;
+                    .collect::<TResult<Vec<_>>>()?;
 
-                return Ok(format!("panic!(\"{class_name}({args})\")"));
+                let msg = format!("{class_name}({})", vec!["{:?}"; args.len()].join(", "));
+                ctx.warn("Raise", *range, "exceptions are translated to panics");
+                Ok(vec![stmt(parse_quote!(panic!(#msg, #(#args),*)))])
             } else {
-                return Ok(format!("panic!()"));
+                Ok(vec![stmt(parse_quote!(panic!()))])
             }
         }
-        Stmt::Try(StmtTry {
-            range: _,
-            body,
-            handlers,
-            orelse: _,
-            finalbody: _,
-        }) => {
-            let body = body
-                .iter()
-                .map(|s| r_s(s, ctx))
-                .intersperse_with(|| Ok(";\n".to_string()))
-                .collect::<TResult<String>>()?;
-
-            let handlers = handlers
-                .iter()
-                .map(|handler| {
-                    let ExceptHandlerExceptHandler {
-                        range: _,
-                        type_: _,
-                        name,
-                        body,
-                    } = handler.as_except_handler().unwrap();
-
-                    let body = body
-                        .iter()
-                        .map(|s| r_s(s, ctx))
-                        .collect::<TResult<String>>()
-                        .unwrap();
-
-                    let header = name
-                        .as_ref()
-                        .map(|name| name.to_string())
-                        .unwrap_or("error_name".to_string());
-
-                    format!("catch_it(|{header}| {{\n{body}\n}});")
-                })
-                .collect::<String>();
-
-            // TODO
-            // The else block lets you execute code when there is no error.
-            // The finally block lets you execute code, regardless of the result of the try- and except blocks.
-            // dbg!(orelse);
-            // dbg!(finalbody);
-            Ok(format!("try_it(|| {{{body}}});\n {handlers}"))
-        }
+        Stmt::Try(t) => exception::r_try(t, ctx),
         Stmt::TypeAlias(StmtTypeAlias {
             range: _,
             name,
@@ -708,34 +1411,32 @@ fn r_s(node: &Stmt, ctx: &mut Ctx) -> TResult<String> {
             value,
         }) => {
             let value = r_annotation(value)?;
-            let name = r_e(name, ctx)?;
-            Ok(format!("type {name} = {value};\n"))
+            let name = ident(&name.as_name_expr().ok_or(todo_link!())?.id);
+            Ok(vec![parse_quote!(type #name = #value;)])
         }
         Stmt::Assert(StmtAssert {
             range: _,
             test,
             msg,
-        }) => match msg {
-            Some(msg) => Ok(format!(
-                "assert!({test}, {msg});\n",
-                test = r_e(test, ctx)?,
-                msg = r_e(msg, ctx)?
-            )),
-            None => Ok(format!("assert!({test});\n", test = r_e(test, ctx)?)),
-        },
+        }) => {
+            let test = r_e(test, ctx)?;
+            match msg {
+                Some(msg) => {
+                    let msg = r_e(msg, ctx)?;
+                    Ok(vec![stmt(parse_quote!(assert!(#test, #msg)))])
+                }
+                None => Ok(vec![stmt(parse_quote!(assert!(#test)))]),
+            }
+        }
         Stmt::Import(StmtImport { range: _, names }) => {
             for name in names {
                 if name.name.as_str() == "math" {
-                    ctx.imports.math_import_name = Some(
-                        name.asname
-                            .as_ref()
-                            .unwrap_or_else(|| &name.name)
-                            .to_string(),
-                    );
+                    ctx.imports.math_import_name =
+                        Some(name.asname.as_ref().unwrap_or(&name.name).to_string());
                 }
             }
 
-            Ok(String::new())
+            Ok(vec![])
         }
         Stmt::ImportFrom(StmtImportFrom {
             range: _,
@@ -788,47 +1489,649 @@ fn r_s(node: &Stmt, ctx: &mut Ctx) -> TResult<String> {
                 }
             }
 
-            Ok(String::new())
+            Ok(vec![])
+        }
+        Stmt::Expr(StmtExpr { value, range: _ }) => {
+            if let Expr::Call(call) = value.as_ref()
+                && let Expr::Attribute(ExprAttribute { value, attr, .. }) = call.func.as_ref()
+                && inherit::is_super(value)
+                && attr.as_str() == "__init__"
+            {
+                return Ok(vec![inherit::r_super_init(call, ctx)?]);
+            }
+            Ok(vec![stmt(r_e(value, ctx)?)])
+        }
+        Stmt::Pass(_) => Ok(vec![stmt(parse_quote!(todo!()))]),
+        Stmt::Break(_) => Ok(vec![stmt(r_jump(true, ctx))]),
+        Stmt::Continue(_) => Ok(vec![stmt(r_jump(false, ctx))]),
+        Stmt::Global(node) => global::r_global(node, ctx),
+        Stmt::AsyncFor(node) => asyncio::r_async_for(node, ctx),
+        Stmt::AsyncFunctionDef(def) => {
+            let mut items = params::r_struct(&asyncio::sync_def(def), ctx)?;
+            items.push(syn::Stmt::Item(syn::Item::Fn(asyncio::r_async_fn(
+                def, ctx,
+            )?)));
+            Ok(items)
+        }
+        Stmt::AsyncWith(node) => context::r_async_with(node, ctx),
+        // the nested function is a closure which borrows the variables
+        Stmt::Nonlocal(_) => Ok(vec![]),
+        Stmt::TryStar(_) => Err(todo_link!(
+            "exception groups can't be translated, `except*` has no equivalent in rust"
+        )),
+        Stmt::With(node) => context::r_with(node, ctx),
+    }
+}
+
+/// `target = value`, `v` is the already transpiled value of the targets of `a = b = value`
+/// after the first one
+fn r_assign(
+    target: &Expr,
+    value: &Expr,
+    v: Option<syn::Expr>,
+    ctx: &mut Ctx,
+) -> TResult<Vec<syn::Stmt>> {
+    if let Expr::Name(n) = target
+        && global::is_static(&n.id, ctx)
+    {
+        return global::r_assign(&n.id, None, value, ctx);
+    }
+
+    let field = ctx.init_field(target);
+    let target = field.as_ref().unwrap_or(target);
+    if field.is_some()
+        && let (Expr::Name(t), Expr::Name(v)) = (target, value)
+        && t.id == v.id
+    {
+        // `self.x = x` in `new` is just `Self { x }`
+        return Ok(vec![]);
+    }
+
+    if v.is_none()
+        && let Some(swap) = unpack::r_swap(target, value, ctx)?
+    {
+        return Ok(vec![stmt(swap)]);
+    }
+    let (unpacked, v) = match v {
+        Some(v) => (None, v),
+        None => match unpack::r_starred(target, value, ctx) {
+            Some(unpacked) => {
+                let (t, v) = unpacked?;
+                (Some(t), v)
+            }
+            None => (None, r_e(value, ctx)?),
+        },
+    };
+    let target = unpacked.as_ref().unwrap_or(target);
+
+    if let Some(stmts) = unpack::r_places(target, v.clone(), ctx) {
+        // e.g. `a, b = b, a` or `self.a, b = 1, 2`
+        stmts
+    } else if ctx.scopes.is_assignable(target) {
+        // the name is already bound, e.g. `x = x + 1`
+        let t = r_e(target, ctx)?;
+        Ok(vec![stmt(assign(t, v))])
+    } else if matches!(target, Expr::Name(_) | Expr::Tuple(_) | Expr::List(_)) {
+        let t = r_target(target, ctx)?;
+        ctx.scopes.declare(target);
+        // literals alone don't tell rust which type to use
+        if needs_annotation(value)
+            && let Expr::Name(name) = target
+            && let Some(ty) = ctx.types.locals.get(name.id.as_str())
+            && let Some(ty) = ty.to_syn()
+        {
+            return Ok(vec![parse_quote!(let #t: #ty = #v;)]);
         }
-        Stmt::Expr(StmtExpr { value, range: _ }) => Ok(r_e(value, ctx)?.to_string()),
-        Stmt::Pass(_) => Ok("todo!()".to_string()),
-        Stmt::Break(_) => Ok("break".to_string()),
-        Stmt::Continue(_) => Ok("continue".to_string()),
-        Stmt::Global(_) => {
-            // TODO translate to static/ and or once_cell
-            Err(todo_link!())
+        Ok(vec![parse_quote!(let #t = #v;)])
+    } else {
+        if let Expr::Attribute(ExprAttribute {
+            value, attr, range, ..
+        }) = target
+            && ctx.is_property(value, attr, *range, true)
+        {
+            let setter = format!("set_{attr}");
+            return Ok(vec![stmt(method_call(r_e(value, ctx)?, &setter, [v]))]);
         }
-        Stmt::AsyncFor(_) => Err(todo_link!()),
-        Stmt::AsyncFunctionDef { .. } => Err(todo_link!()),
-        Stmt::AsyncWith(_) => Err(todo_link!()),
-        Stmt::Nonlocal(_) => Err(todo_link!()),
-        Stmt::TryStar(_) => Err(todo_link!()),
-        Stmt::With(_) => Err(todo_link!()),
+        // e.g. self.a = 3
+        let t = r_e(target, ctx)?;
+        Ok(vec![stmt(assign(t, v))])
     }
 }
 
+/// a collection which is iterated by a `for` loop, collections of `Copy` values are iterated by
+/// reference so they can still be used after the loop
+fn r_iter(iter: &Expr, ctx: &mut Ctx) -> TResult<syn::Expr> {
+    let e = match iter {
+        // the `Mutex` of a global would stay locked while the loop runs
+        Expr::Name(n) if global::is_static(&n.id, ctx) => global::r_static_clone(&n.id),
+        iter => r_e(iter, ctx)?,
+    };
+    Ok(match ctx.types.ty(iter, &ctx.types.locals) {
+        infer::Ty::List(t) | infer::Ty::Set(t)
+            if matches!(iter, Expr::Name(_) | Expr::Attribute(_))
+                && *t != infer::Ty::Unknown
+                && !t.is_owned() =>
+        {
+            method_call(method_call(e, "iter", []), "copied", [])
+        }
+        _ => e,
+    })
+}
+
+/// a returned number is cast to the return type, e.g. `return len(xs)` in a function which
+/// returns `int`
+fn ret_cast(value: &Expr, v: syn::Expr, ctx: &Ctx) -> syn::Expr {
+    let t = ctx.types.ty(value, &ctx.types.locals);
+    match (infer::rank(&t), infer::rank(&ctx.ret)) {
+        (Some(a), Some(b)) if a != b && !(infer::is_int_literal(value) && b < a) => {
+            cast(v, ctx.ret.to_syn().unwrap())
+        }
+        _ => v,
+    }
+}
+
+/// the operand of the lower numeric type is cast to the type of the other one,
+/// e.g. `total / len(xs)` -> `total / xs.len() as f64`
+fn numeric_cast(
+    (left, l): (&Expr, syn::Expr),
+    (right, r): (&Expr, syn::Expr),
+    ctx: &Ctx,
+) -> (syn::Expr, syn::Expr) {
+    let (lt, rt) = ctx.types.operands(left, right, &ctx.types.locals);
+    match (infer::rank(&lt), infer::rank(&rt)) {
+        (Some(a), Some(b)) if a < b => (cast(l, rt.to_syn().unwrap()), r),
+        (Some(a), Some(b)) if a > b => (l, cast(r, lt.to_syn().unwrap())),
+        _ => (l, r),
+    }
+}
+
+/// labeled block around a loop with `else` which `break` leaves
+#[derive(Debug, Clone)]
+struct ElseBlock {
+    label: String,
+    /// the block evaluates to whether the loop was left by `break`, an `else` which leaves the
+    /// enclosing loop can't be in the labeled block
+    broke: bool,
+}
+
+/// block around a loop with `else` which is left by `break`, loops which are not left by
+/// `break` always run their `else`
+fn else_block(body: &[Stmt], orelse: &[Stmt], ctx: &Ctx) -> Option<ElseBlock> {
+    if orelse.is_empty() || !jumps(body, false) {
+        return None;
+    }
+    let label = match ctx.breaks.iter().flatten().count() {
+        0 => "'search".to_string(),
+        n => format!("'search{}", n + 1),
+    };
+    Some(ElseBlock {
+        label,
+        broke: jumps(orelse, true),
+    })
+}
+
+fn lifetime(label: &str) -> syn::Lifetime {
+    syn::Lifetime::new(label, proc_macro2::Span::call_site())
+}
+
+/// `break` or `continue`, in the closure of a `try` body they are passed on as
+/// `ControlFlow::Break(())` or `ControlFlow::Continue(())`
+fn r_jump(breaks: bool, ctx: &Ctx) -> syn::Expr {
+    if ctx.try_jumps {
+        return exception::r_return(
+            match breaks {
+                true => parse_quote!(std::ops::ControlFlow::Break(())),
+                false => parse_quote!(std::ops::ControlFlow::Continue(())),
+            },
+            ctx,
+        );
+    }
+    match ctx.breaks.last() {
+        _ if !breaks => parse_quote!(continue),
+        // skips the `else` of the loop
+        Some(Some(ElseBlock { label, broke })) => {
+            let label = lifetime(label);
+            if *broke {
+                parse_quote!(break #label true)
+            } else {
+                parse_quote!(break #label)
+            }
+        }
+        _ => parse_quote!(break),
+    }
+}
+
+/// `break` (or `continue`) of the loop around `body`, not of nested loops
+fn jumps(body: &[Stmt], continues: bool) -> bool {
+    let jumps = |body| jumps(body, continues);
+    body.iter().any(|s| match s {
+        Stmt::Break(_) => true,
+        Stmt::Continue(_) => continues,
+        Stmt::If(StmtIf { body, orelse, .. }) => jumps(body) || jumps(orelse),
+        Stmt::With(w) => jumps(&w.body),
+        Stmt::AsyncWith(w) => jumps(&w.body),
+        Stmt::Try(t) => {
+            jumps(&t.body)
+                || jumps(&t.orelse)
+                || jumps(&t.finalbody)
+                || t.handlers
+                    .iter()
+                    .any(|h| jumps(&h.as_except_handler().unwrap().body))
+        }
+        Stmt::Match(m) => m.cases.iter().any(|c| jumps(&c.body)),
+        _ => false,
+    })
+}
+
+/// `for`/`while` with `else` -> `'search: { loop; else }`, `break` leaves the labeled block, an
+/// `else` which jumps itself -> `let broke = 'search: { loop; false }; if !broke { else }`
+fn r_loop_else(
+    lp: syn::Expr,
+    block: Option<ElseBlock>,
+    orelse: &[Stmt],
+    ctx: &mut Ctx,
+) -> TResult<Vec<syn::Stmt>> {
+    let orelse = r_body(orelse, ctx)?;
+    Ok(match block {
+        Some(ElseBlock {
+            label,
+            broke: false,
+        }) => {
+            let label = lifetime(&label);
+            vec![stmt(parse_quote!(#label: { #lp #(#orelse)* }))]
+        }
+        Some(ElseBlock { label, broke: true }) => {
+            let label = lifetime(&label);
+            vec![
+                parse_quote!(let broke = #label: { #lp false };),
+                stmt(parse_quote!(if !broke { #(#orelse)* })),
+            ]
+        }
+        None => [stmt(lp)].into_iter().chain(orelse).collect(),
+    })
+}
+
+/// a single expression is used as is, everything else is wrapped in a block
+fn arm_body(mut stmts: Vec<syn::Stmt>) -> syn::Expr {
+    if let [syn::Stmt::Expr(e, _)] = stmts.as_mut_slice()
+        && !matches!(
+            e,
+            syn::Expr::If(_) | syn::Expr::While(_) | syn::Expr::ForLoop(_)
+        )
+    {
+        return e.clone();
+    }
+    block_expr(stmts)
+}
+
+/// method of an `impl` block
+fn impl_fn(f: syn::ItemFn) -> syn::ImplItemFn {
+    syn::ImplItemFn {
+        attrs: f.attrs,
+        vis: f.vis,
+        defaultness: None,
+        sig: f.sig,
+        block: *f.block,
+    }
+}
+
+/// default value of a class field, including `field(default=...)` and
+/// `field(default_factory=...)` of dataclasses
+fn r_default(value: &Expr, ctx: &mut Ctx) -> TResult<syn::Expr> {
+    if let Expr::Call(ExprCall {
+        func,
+        args,
+        keywords,
+        range,
+    }) = value
+        && inherit::base_name(func) == Some("field")
+    {
+        if !args.is_empty() {
+            return Err(todo_link!());
+        }
+        for k in keywords {
+            match (k.arg.as_deref(), &k.value) {
+                (Some("default"), v) => return r_default(v, ctx),
+                (Some("default_factory"), Expr::Name(ExprName { id, .. })) => {
+                    return match id.as_str() {
+                        "list" => Ok(parse_quote!(Vec::new())),
+                        "dict" => Ok(parse_quote!(std::collections::HashMap::new())),
+                        "set" => Ok(parse_quote!(std::collections::HashSet::new())),
+                        _ => r_e(
+                            &ExprCall {
+                                range: *range,
+                                func: Box::new(k.value.clone()),
+                                args: vec![],
+                                keywords: vec![],
+                            }
+                            .into(),
+                            ctx,
+                        ),
+                    };
+                }
+                (Some("default_factory"), Expr::Lambda(l)) => return r_e(&l.body, ctx),
+                (Some("default_factory"), _) => return Err(todo_link!()),
+                _ => {}
+            }
+        }
+        return Ok(parse_quote!(Default::default()));
+    }
+
+    r_owned(value, ctx)
+}
+
+/// `value` where a `String` is stored, string literals are converted
+fn r_owned(value: &Expr, ctx: &mut Ctx) -> TResult<syn::Expr> {
+    let v = r_e(value, ctx)?;
+    if matches!(
+        value,
+        Expr::Constant(ExprConstant {
+            value: Constant::Str(_),
+            ..
+        })
+    ) {
+        return Ok(method_call(v, "to_string", []));
+    }
+    Ok(v)
+}
+
+/// convert `__init__` of `class` into a `new` function which returns the struct
+fn r_init(def: &StmtFunctionDef, class: &str, ctx: &mut Ctx) -> TResult<syn::ItemFn> {
+    let mut fields = ctx
+        .types
+        .classes
+        .get(class)
+        .into_iter()
+        .flatten()
+        .map(|(f, _)| f.clone())
+        .collect::<Vec<_>>();
+    let mut assigned = ctx.types.init_fields(def);
+    if ctx.bases.contains_key(class) {
+        fields.insert(0, "base".to_string());
+        // `super().__init__(..)` binds `base`
+        let calls_super = def.body.iter().any(|s| {
+            s.as_expr_stmt()
+                .and_then(|e| e.value.as_call_expr())
+                .is_some_and(|c| {
+                    matches!(c.func.as_ref(), Expr::Attribute(a) if inherit::is_super(&a.value))
+                })
+        });
+        if calls_super {
+            assigned.push(("base".to_string(), infer::Ty::Unknown));
+        }
+    }
+
+    // fields which are assigned in the branches of an `if` are declared before it
+    let nested = scope::nested_fields(&fields, &def.body);
+    if nested.iter().any(|f| !scope::always_assigns(&def.body, f)) {
+        return Err(todo_link!(
+            "a field which `__init__` doesn't assign on every path can't be initialized"
+        )
+        .at("FunctionDef", def.range));
+    }
+    let scopes = scope::Scopes::constructor(
+        params::names(&def.args),
+        &fields,
+        &ctx.types.mutating_methods,
+        &def.body,
+    );
+
+    ctx.init_fields = fields.clone();
+    let f = r_fn(def, ctx);
+    ctx.init_fields.clear();
+    let mut f = f?;
+    f.block.stmts.splice(
+        0..0,
+        nested.iter().map(|n| -> syn::Stmt {
+            let n = pat_ident(n, scopes.is_mutated(n));
+            parse_quote!(let #n;)
+        }),
+    );
+
+    f.vis = parse_quote!(pub);
+    f.sig.ident = ident("new");
+    f.sig.inputs = std::mem::take(&mut f.sig.inputs)
+        .into_iter()
+        .filter(|a| !matches!(a, syn::FnArg::Receiver(_)))
+        .collect();
+    let ret_result = ctx.result_errors && ctx.exceptions.raises_in(None, class);
+    f.sig.output = if ret_result {
+        parse_quote!(-> Result<Self, Error>)
+    } else {
+        parse_quote!(-> Self)
+    };
+
+    let values = fields.iter().map(|f| -> syn::FieldValue {
+        let name = ident(f);
+        if assigned.iter().any(|(a, _)| a == f) {
+            parse_quote!(#name)
+        } else {
+            // fields which `__init__` doesn't set
+            parse_quote!(#name: Default::default())
+        }
+    });
+    let value: syn::Expr = parse_quote!(Self { #(#values),* });
+    let value = if ret_result {
+        parse_quote!(Ok(#value))
+    } else {
+        value
+    };
+    f.block.stmts.push(syn::Stmt::Expr(value, None));
+    Ok(f)
+}
+
+/// convert function definition
+fn r_fn(node: &StmtFunctionDef, ctx: &mut Ctx) -> TResult<syn::ItemFn> {
+    // only non empty for methods, for functions `r_s` already took them
+    let comments = ctx.take_comments(node.range.start());
+    let locals = ctx.types.scope(&node.body, ctx.types.params(node));
+    let outer = std::mem::replace(&mut ctx.types.locals, locals);
+    let params = params::names(&node.args);
+    let scopes = scope::Scopes::constructor(
+        params,
+        &ctx.init_fields,
+        &ctx.types.mutating_methods,
+        &node.body,
+    );
+    let outer_scopes = std::mem::replace(&mut ctx.scopes, scopes);
+    let outer_promotion = ctx.ret_needs_promotion.clone();
+    let ret_result = ctx.result_errors && !dunder::is_dunder(&node.name) && {
+        let class = ctx.types.class.as_deref();
+        match class {
+            Some(class) if node.name.as_str() == "__init__" => {
+                ctx.exceptions.raises_in(None, class)
+            }
+            class => ctx.exceptions.raises_in(class, &node.name),
+        }
+    };
+    let outer_result = std::mem::replace(&mut ctx.ret_result, ret_result);
+    let ret = match &node.returns {
+        // `__len__` becomes `len`, which returns `usize`
+        _ if node.name.as_str() == "__len__" => infer::Ty::Usize,
+        Some(r) => infer::Ty::from_annotation(r),
+        None => ctx.types.ret(node, &ctx.types.locals),
+    };
+    let outer_ret = std::mem::replace(&mut ctx.ret, ret);
+    let outer_generator = ctx.generator.take();
+    let outer_breaks = std::mem::take(&mut ctx.breaks);
+    let outer_jumps = std::mem::take(&mut ctx.try_jumps);
+    let f = r_fn_inner(node, ctx);
+    ctx.generator = outer_generator;
+    ctx.breaks = outer_breaks;
+    ctx.try_jumps = outer_jumps;
+    ctx.types.locals = outer;
+    ctx.scopes = outer_scopes;
+    ctx.ret_needs_promotion = outer_promotion;
+    ctx.ret_result = outer_result;
+    ctx.ret = outer_ret;
+    let mut f = f.map_err(|e| e.at("FunctionDef", node.range))?;
+    f.attrs.splice(
+        0..0,
+        comment_attrs(comments.iter().map(|c| comment_text(c))),
+    );
+    Ok(f)
+}
+
+fn r_fn_inner(node: &StmtFunctionDef, ctx: &mut Ctx) -> TResult<syn::ItemFn> {
+    let StmtFunctionDef {
+        name,
+        args: _,
+        body,
+        decorator_list: _,
+        returns,
+        type_comment: _,
+        range: _,
+        type_params: _,
+    } = node;
+
+    let (mut args, defaults) = params::r_params(node, ctx)?;
+
+    let lifetimes: syn::Generics = if ctx.numpy_array_args.is_empty() {
+        Default::default()
+    } else {
+        args.insert(0, parse_quote!(py: pyo3::Python<'py>));
+        parse_quote!(<'py>)
+    };
+
+    let generator = infer::is_generator(body);
+    let ret_type = match returns {
+        Some(r) => r_annotation(r)?,
+        None => match ctx.types.ret(node, &ctx.types.locals).to_syn() {
+            Some(t) => t,
+            None if generator => {
+                return Err(todo_link!(
+                    "the type of the yielded values is unknown, annotate the return type"
+                ));
+            }
+            None => parse_quote!(()),
+        },
+    };
+
+    if type_starts_with(&ret_type, "Option") {
+        ctx.ret_needs_promotion = Promotion::Into;
+    }
+    let ret_type = if type_starts_with(&ret_type, "numpy") {
+        ctx.ret_needs_promotion = Promotion::IntoPyArray;
+        parse_quote!(&'py #ret_type)
+    } else {
+        ret_type
+    };
+    let returns_unit = matches!(&ret_type, syn::Type::Tuple(t) if t.elems.is_empty());
+    let ret_type = if ctx.ret_result {
+        parse_quote!(Result<#ret_type, Error>)
+    } else {
+        ret_type
+    };
+
+    let mut stmts = ctx
+        .numpy_array_args
+        .iter()
+        .map(|(name, _t)| -> syn::Stmt {
+            let pat = pat_ident(name, ctx.scopes.is_mutated(name));
+            let name = ident(name);
+            parse_quote!(let #pat = #name.as_array().to_owned();)
+        })
+        .collect::<Vec<_>>();
+    stmts.extend(defaults);
+
+    let mut doc_comment = None;
+    for s in body {
+        if let Some(doc) = docstring(s) {
+            // free standing string
+            // -> doc comment
+            doc_comment = Some(doc_attrs(doc));
+        } else if !generator {
+            stmts.extend(r_s(s, ctx)?);
+        }
+    }
+    if generator {
+        let code = body
+            .iter()
+            .filter(|s| docstring(s).is_none())
+            .cloned()
+            .collect::<Vec<_>>();
+        stmts.extend(generator::r_generator(&code, ctx)?);
+    }
+    // the end of the function is reachable, `new` returns the struct instead
+    if ctx.ret_result
+        && returns_unit
+        && name.as_str() != "__init__"
+        && !matches!(body.last(), Some(Stmt::Return(_) | Stmt::Raise(_)))
+    {
+        stmts.push(syn::Stmt::Expr(parse_quote!(Ok(())), None));
+    }
+
+    let name = ident(name);
+    let mut f: syn::ItemFn = parse_quote! {
+        fn #name #lifetimes (#(#args),*) -> #ret_type {}
+    };
+    f.attrs = doc_comment.unwrap_or_default();
+    f.block.stmts = stmts;
+    Ok(f)
+}
+
+/// numeric literals and empty collections
+fn needs_annotation(e: &Expr) -> bool {
+    match e {
+        Expr::Constant(c) => matches!(c.value, Constant::Int(_) | Constant::Float(_)),
+        Expr::UnaryOp(u) => u.op == UnaryOp::USub && needs_annotation(&u.operand),
+        Expr::List(l) => l.elts.is_empty(),
+        Expr::Set(s) => s.elts.is_empty(),
+        Expr::Dict(d) => d.keys.is_empty(),
+        Expr::Call(c) => {
+            c.args.is_empty()
+                && c.func
+                    .as_name_expr()
+                    .is_some_and(|f| matches!(f.id.as_str(), "list" | "dict" | "set"))
+        }
+        _ => false,
+    }
+}
+
+/// first path segment of a type, e.g. `Option` for `Option<T>`
+fn type_starts_with(t: &syn::Type, name: &str) -> bool {
+    matches!(t, syn::Type::Path(p) if p.path.segments.first().is_some_and(|s| s.ident == name))
+}
+
 /// convert expression
-fn r_e(node: &Expr, ctx: &mut Ctx) -> TResult<String> {
+fn r_e(node: &Expr, ctx: &mut Ctx) -> TResult<syn::Expr> {
+    r_e_inner(node, ctx).map_err(|e| e.at(expr_kind(node), node.range()))
+}
+
+fn r_e_inner(node: &Expr, ctx: &mut Ctx) -> TResult<syn::Expr> {
     match node {
         Expr::BoolOp(ExprBoolOp {
             op,
             values,
             range: _,
-        }) => Ok(format!(
-            "{l} {o} {r}",
-            l = r_e(&values[0], ctx)?,
-            o = r_bool(op),
-            r = r_e(&values[1], ctx)?
-        )),
+        }) => {
+            let op = r_bool(op);
+            Ok(values
+                .iter()
+                .map(|v| r_e(v, ctx))
+                .collect::<TResult<Vec<_>>>()?
+                .into_iter()
+                .reduce(|l, r| binary(l, op, r))
+                .ok_or(todo_link!())?)
+        }
         Expr::NamedExpr(ExprNamedExpr {
             target,
             value,
             range: _,
-        }) => Ok(format!(
-            "let Some({t}) = {v}",
-            t = r_e(target, ctx)?,
-            v = r_e(value, ctx)?
-        )),
+        }) => {
+            let t = r_target(target, ctx)?;
+            Ok(syn::Expr::Let(syn::ExprLet {
+                attrs: vec![],
+                let_token: Default::default(),
+                pat: Box::new(parse_quote!(Some(#t))),
+                eq_token: Default::default(),
+                // `let` binds weaker than `&&` and `||`
+                expr: Box::new(match r_e(value, ctx)? {
+                    v @ syn::Expr::Binary(_) => paren(v),
+                    v => v,
+                }),
+            }))
+        }
         Expr::BinOp(ExprBinOp {
             left,
             op,
@@ -836,34 +2139,26 @@ fn r_e(node: &Expr, ctx: &mut Ctx) -> TResult<String> {
             range: _,
         }) => {
             if op == &Operator::Pow {
-                Ok(format!(
-                    "{l}.powf({r})",
-                    l = r_e(left, ctx)?,
-                    r = r_e(right, ctx)?
-                ))
+                Ok(method_call(r_e(left, ctx)?, "powf", [r_e(right, ctx)?]))
             } else {
-                Ok(format!(
-                    "{l} {o} {r}",
-                    l = r_e(left, ctx)?,
-                    o = r_o(op)?,
-                    r = r_e(right, ctx)?
-                ))
+                let (l, r) = (r_e(left, ctx)?, r_e(right, ctx)?);
+                let (l, r) = numeric_cast((left, l), (right, r), ctx);
+                Ok(binary(l, r_o(op)?, r))
             }
         }
         Expr::UnaryOp(ExprUnaryOp {
             op,
             operand,
             range: _,
-        }) => Ok(format!(
-            "{o}{r}",
-            o = match op {
-                UnaryOp::Invert => "~",
-                UnaryOp::Not => "!",
-                UnaryOp::UAdd => "+",
-                UnaryOp::USub => "-",
-            },
-            r = r_e(operand, ctx)?
-        )),
+        }) => {
+            let operand = r_e(operand, ctx)?;
+            Ok(match op {
+                // `!` is the bitwise not for integers in rust
+                UnaryOp::Invert | UnaryOp::Not => unary(parse_quote!(!), operand),
+                UnaryOp::UAdd => operand,
+                UnaryOp::USub => unary(parse_quote!(-), operand),
+            })
+        }
         Expr::Lambda(ExprLambda {
             args,
             body,
@@ -878,22 +2173,20 @@ fn r_e(node: &Expr, ctx: &mut Ctx) -> TResult<String> {
                 .into_python_arguments()
                 .args
                 .iter()
-                .map(|a| a.arg.to_string())
-                .intersperse_with(|| ", ".to_string())
-                .collect::<String>();
+                .map(|a| pat_ident(&a.arg, false))
+                .collect();
 
-            Ok(format!("|{args}| {{\n{b}\n}}", b = r_e(body, ctx)?))
+            Ok(closure(args, tail_block(r_e(body, ctx)?)))
         }
         Expr::IfExp(ExprIfExp {
             test,
             body,
             orelse,
             range: _,
-        }) => Ok(format!(
-            "if {t} {{ {b} }} else {{ {o} }}",
-            t = r_e(test, ctx)?,
-            b = r_e(body, ctx)?,
-            o = r_e(orelse, ctx)?
+        }) => Ok(if_expr(
+            r_e(test, ctx)?,
+            vec![syn::Stmt::Expr(r_e(body, ctx)?, None)],
+            Some(tail_block(r_e(orelse, ctx)?)),
         )),
         Expr::Dict(ExprDict {
             keys,
@@ -901,47 +2194,47 @@ fn r_e(node: &Expr, ctx: &mut Ctx) -> TResult<String> {
             range: _,
         }) => {
             if keys.is_empty() {
-                return Ok("HashMap::new()".to_string());
+                return Ok(parse_quote!(HashMap::new()));
+            }
+            if keys.iter().any(Option::is_none) {
+                return unpack::r_dict(keys, values, ctx);
             }
 
-            Ok(format!(
-                "[{k}].into_iter().zip([{v}].into_iter()).collect::<HashMap<_, _>>()",
-                k = keys
-                    .iter()
-                    .map(|k| r_e(&k.clone().unwrap(), ctx))
-                    .intersperse_with(|| Ok(", ".to_string()))
-                    .collect::<TResult<String>>()?,
-                v = values
-                    .iter()
-                    .map(|e| r_e(e, ctx))
-                    .intersperse_with(|| Ok(", ".to_string()))
-                    .collect::<TResult<String>>()?
+            let k = keys
+                .iter()
+                .map(|k| r_e(k.as_ref().ok_or(todo_link!())?, ctx))
+                .collect::<TResult<Vec<_>>>()?;
+            let v = values
+                .iter()
+                .map(|e| r_e(e, ctx))
+                .collect::<TResult<Vec<_>>>()?;
+
+            Ok(parse_quote!(
+                [#(#k),*].into_iter().zip([#(#v),*].into_iter()).collect::<HashMap<_, _>>()
             ))
         }
         Expr::Set(ExprSet { elts, range: _ }) => {
             if elts.is_empty() {
-                return Ok("HashSet::new()".to_string());
+                return Ok(parse_quote!(HashSet::new()));
             }
 
-            Ok(format!(
-                "[{e}].into_iter().collect::<HashSet<_>>()",
-                e = elts
-                    .iter()
-                    .map(|e| r_e(e, ctx))
-                    .intersperse_with(|| Ok(", ".to_string()))
-                    .collect::<TResult<String>>()?
-            ))
+            let e = elts
+                .iter()
+                .map(|e| r_e(e, ctx))
+                .collect::<TResult<Vec<_>>>()?;
+
+            Ok(parse_quote!([#(#e),*].into_iter().collect::<HashSet<_>>()))
         }
         Expr::ListComp(ExprListComp {
             elt,
             generators,
             range: _,
-        }) => gen_generator(generators, elt, Some("Vec::<_>"), ctx),
+        }) => gen_generator(generators, elt, Some(parse_quote!(Vec<_>)), ctx),
         Expr::SetComp(ExprSetComp {
             elt,
             generators,
             range: _,
-        }) => gen_generator(generators, elt, Some("HashSet::<_,_>"), ctx),
+        }) => gen_generator(generators, elt, Some(parse_quote!(HashSet<_, _>)), ctx),
 
         Expr::DictComp(ExprDictComp {
             range,
@@ -955,226 +2248,91 @@ fn r_e(node: &Expr, ctx: &mut Ctx) -> TResult<String> {
                 ctx: ExprContext::Load,
             });
 
-            gen_generator(generators, &body, Some("HashMap::<_,_>"), ctx)
+            gen_generator(generators, &body, Some(parse_quote!(HashMap<_, _>)), ctx)
         }
         Expr::Compare(ExprCompare {
             left,
             ops,
             comparators,
-            range: _,
+            range,
         }) => {
-            let mut s = vec![];
-            for ((lhs, rhs), op) in std::iter::once(left.deref())
-                .chain(comparators.iter())
-                .map_windows(|[lhs, rhs]| (r_e(lhs, ctx).unwrap(), r_e(rhs, ctx).unwrap()))
-                .zip(ops.iter())
-            {
-                if op == &CmpOp::In {
-                    s.push(format!("{rhs}.into_iter().any(|v| v == {lhs})"));
-                } else {
-                    s.push(format!("{lhs} {op} {rhs}", op = r_c(op)?));
-                }
+            if ops.iter().any(|op| matches!(op, CmpOp::Is | CmpOp::IsNot)) {
+                ctx.warn("Compare", *range, "`is` is translated as `==`");
             }
-
-            Ok(s.iter()
-                .cloned()
-                .intersperse_with(|| "&&".to_string())
-                .collect())
-        }
-        Expr::Call(ExprCall {
-            func,
-            args,
-            keywords,
-            range: _,
-        }) => {
-            let args: Vec<String> = args
+            let exprs = std::iter::once(left.as_ref())
+                .chain(comparators.iter())
+                .collect::<Vec<_>>();
+            let operands = exprs
                 .iter()
                 .map(|e| r_e(e, ctx))
                 .collect::<TResult<Vec<_>>>()?;
 
-            let function_name = r_e(func, ctx)?;
-
-            let positional_args = args
-                .iter()
-                .cloned()
-                .intersperse_with(|| ", ".to_string())
-                .collect::<String>();
-
-            let kwargs = keywords
-                .iter()
-                .map(|kw| {
-                    format!(
-                        "{name}: {value}",
-                        name = kw.arg.clone().unwrap(),
-                        value = r_e(&kw.value, ctx).unwrap()
-                    )
-                })
-                .intersperse_with(|| ", ".to_string())
-                .collect::<String>();
-
-            let args_str = match (positional_args.is_empty(), kwargs.is_empty()) {
-                (true, true) => String::new(),
-                (true, false) => {
-                    // TODO generate a struct with the FnName + "Params"
-                    // to get named arguments in rust
-
-                    format!("{function_name}Params {{\n{kwargs}\n}}")
-                }
-                (false, true) => positional_args,
-                (false, false) => {
-                    // args must come before kwargs
-                    format!("{positional_args}, {function_name}Params {{\n{kwargs}\n}}")
-                }
-            };
-
-            // support for Dataclass like classes
-            // TOOD handle __init__ method as well
-            if let Some(members) = ctx.get_class_members(&function_name) {
-                // Class/ Struct Init
-                let body = if keywords.is_empty() {
-                    // only args
-                    members
-                        .iter()
-                        .zip(args.iter())
-                        .map(|(m, a)| format!("{m} : {a},"))
-                        .intersperse("\n".to_string())
-                        .collect::<String>()
-                } else {
-                    if !args.is_empty() {
-                        // mixing keyword args and non keyword args here is probably an error
-                        // double check the python spec on the exact sematics and be sure to translate them
-                        return Err(todo_link!());
-                    }
-
-                    // only kwargs
-                    keywords
-                        .iter()
-                        .map(|k| {
-                            r_e(&k.value, ctx).map(|a| {
-                                let m = &k.arg.as_deref().unwrap().to_string();
-                                format!("{m} : {a},")
-                            })
-                        })
-                        .intersperse(Ok("\n".to_string()))
-                        .collect::<TResult<String>>()?
-                };
-
-                return Ok(format!("{function_name} {{\n{body}\n}}"));
-            } else if function_name == "print" {
-                let fmt = "\"{:?}\"";
-                return Ok(format!("println!({fmt}, {args_str})"));
-            } else if function_name == "enumerate" {
-                return Ok(format!("{args_str}.iter().enumerate()"));
-            } else if function_name == "zip" {
-                let add_mapping = args.len() > 2;
-
-                let args_str: String = args
-                    .iter()
-                    .cloned()
-                    .intersperse_with(|| ", ".to_string())
-                    .collect();
-
-                let mut args = args.iter();
-                let first = args.next().unwrap();
-
-                let mut zips = format!("{first}.iter()");
-                let mut maps = first.to_string();
-
-                for arg in args {
-                    zips = format!("{zips}.zip({arg}.iter())");
-                    maps = format!("({maps}, {arg})");
-                }
-
-                if add_mapping {
-                    zips = format!("{zips}.map(|{maps}| ({args_str}))")
-                }
-
-                return Ok(zips);
-            } else if function_name == "str" {
-                return Ok(format!("{args_str}.to_string()"));
-            } else if function_name == "len" {
-                return Ok(format!("{args_str}.len()"));
-            } else if function_name == "sum" {
-                return Ok(format!("{args_str}.iter().sum()"));
-            } else if function_name == "int" || function_name == "float" {
-                let f = r_annotation(func)?;
-                return Ok(format!("(({args_str}) as {f})"));
-            } else if function_name == "range" {
-                // TODO handle start and step interval here in a more robust way
-                // keyword args start stop step need to be handled, ideally
-                // without any string based logic
-                let args: Vec<_> = args_str.split(',').collect();
-                match args.as_slice() {
-                    [end] => return Ok(format!("(0..{end})")),
-                    [start, end] => return Ok(format!("({start}..{end})")),
-                    [_start, _step, _stop] => {
-                        // start stop step
-                        return Err(todo_link!());
+            let mut s = vec![];
+            for ((w, e), op) in operands.windows(2).zip(exprs.windows(2)).zip(ops.iter()) {
+                let (lhs, rhs) = numeric_cast((e[0], w[0].clone()), (e[1], w[1].clone()), ctx);
+                if op == &CmpOp::In || op == &CmpOp::NotIn {
+                    let any = method_call(
+                        method_call(rhs, "into_iter", []),
+                        "any",
+                        [closure(
+                            vec![pat_ident("v", false)],
+                            binary(path("v"), parse_quote!(==), lhs),
+                        )],
+                    );
+                    if op == &CmpOp::In {
+                        s.push(any);
+                    } else {
+                        s.push(unary(parse_quote!(!), any));
                     }
-                    _ => unreachable!(),
-                }
-            } else if let Some(module) = &ctx.imports.math_import_name
-                && let Some(math_method) = function_name.strip_prefix(module)
-            {
-                let math_method = math_method.strip_prefix('.').unwrap();
-                // TOOD automate this for all modules not just math
-                // ctx.imports.called_math_fns.push(PyMath::from(math_method));
-                ctx.imports.math.insert(math_method.to_string(), None);
-                return Ok(format!("prelude::{math_method}({args_str})"));
-            } else if let Some(json_method) = function_name.strip_prefix("json.") {
-                if json_method == "loads" {
-                    return Ok(format!("serde_json::from_string({args_str}).unwrap()"));
-                } else if json_method == "dumps" {
-                    return Ok(format!("serde_json::to_string({args_str}).unwrap()"));
-                }
-            } else if let Some(json_method) = function_name.strip_prefix("np.") {
-                if json_method == "where" {
-                    return Ok(format!("ndarray::azip(({args_str}), {{ TODO zip body }})"));
                 } else {
-                    // TODO add numpy polyfill like done for math
-                    return Err(todo_link!());
+                    s.push(binary(lhs, r_c(op)?, rhs));
                 }
             }
 
-            Ok(format!("{function_name}({args_str})"))
+            Ok(s.into_iter()
+                .reduce(|l, r| binary(l, parse_quote!(&&), r))
+                .unwrap())
+        }
+        Expr::Call(call_expr) => {
+            if let Some((_, name)) = ctx.hoisted.iter().find(|(r, _)| *r == call_expr.range) {
+                return Ok(path(name));
+            }
+            let c = r_call(call_expr, ctx)?;
+            if !(ctx.result_errors
+                && ctx
+                    .exceptions
+                    .raises(call_expr, &ctx.types, &ctx.types.locals))
+            {
+                Ok(c)
+            } else if ctx.ret_result || ctx.try_body {
+                Ok(parse_quote!(#c?))
+            } else {
+                // uncaught exceptions end the program
+                Ok(method_call(c, "unwrap", []))
+            }
         }
         Expr::FormattedValue(ExprFormattedValue {
             value,
             conversion: _,
             format_spec: _,
             range: _,
-        }) => Ok(r_e(value, ctx)?.to_string()),
+        }) => r_e(value, ctx),
         Expr::JoinedStr(ExprJoinedStr { values, range: _ }) => {
-            let mut interpolations = vec![];
-            let mut format_str = "format!(\"".to_string();
-            for v in values {
-                // if matches!(v, Expr::Constant { .. }) {
-                //     let node = r_e(v, ctx)?;
-                //     dbg!(&node);
-                //     format_str.push_str(&node);
-                // } else if matches!(v, Expr::FormattedValue { .. }) {
-                interpolations.push(r_e(v, ctx)?);
-                format_str.push_str("{:?}")
-                // } else {
-                // return Err(todo_link!());
-                // }
-            }
-            format_str.push('\"');
-
-            for interp in interpolations {
-                format_str.push_str(&format!(", {interp}"));
-            }
+            let interpolations = values
+                .iter()
+                .map(|v| r_e(v, ctx))
+                .collect::<TResult<Vec<_>>>()?;
+            let format_str = "{:?}".repeat(interpolations.len());
 
-            format_str.push(')');
-            Ok(format_str)
+            Ok(parse_quote!(format!(#format_str, #(#interpolations),*)))
         }
         Expr::Constant(ExprConstant {
             value,
             kind: _,
             range: _,
         }) => match value {
-            Constant::None => Ok("None".to_string()),
-            Constant::Str(s) => Ok(format!("\"{}\"", s)),
+            Constant::None => Ok(parse_quote!(None)),
+            Constant::Str(s) => Ok(parse_quote!(#s)),
             Constant::Bytes(bytes) => {
                 // TODO read this in more detail
                 // https://peps.python.org/pep-3112/
@@ -1188,18 +2346,22 @@ fn r_e(node: &Expr, ctx: &mut Ctx) -> TResult<String> {
                     output
                 });
 
-                Ok(format!("b\"{bytes}\""))
+                let lit: proc_macro2::Literal =
+                    format!("b\"{bytes}\"").parse().map_err(|_| todo_link!())?;
+                let lit = syn::Lit::new(lit);
+                Ok(parse_quote!(#lit))
             }
-            Constant::Bool(b) => {
-                if *b {
-                    Ok("true".to_string())
-                } else {
-                    Ok("false".to_string())
-                }
+            Constant::Bool(b) => Ok(parse_quote!(#b)),
+            Constant::Int(i) => {
+                let lit = syn::LitInt::new(&i.to_string(), proc_macro2::Span::call_site());
+                Ok(parse_quote!(#lit))
             }
-            Constant::Int(i) => Ok(i.to_string()),
             Constant::Tuple(_) => Err(todo_link!()),
-            Constant::Float(f) => Ok(f.to_string()),
+            Constant::Float(f) => {
+                // debug formatting keeps the `.0` of whole numbers
+                let lit = syn::LitFloat::new(&format!("{f:?}"), proc_macro2::Span::call_site());
+                Ok(parse_quote!(#lit))
+            }
             Constant::Complex { .. } => Err(todo_link!()),
             Constant::Ellipsis => Err(todo_link!()),
         },
@@ -1207,60 +2369,112 @@ fn r_e(node: &Expr, ctx: &mut Ctx) -> TResult<String> {
             value,
             attr,
             ctx: _,
-            range: _,
+            range,
         }) => {
-            let value = r_e(value, ctx)?;
-            if ctx.has_enum(&value) {
-                return Ok(format!("{value}::{attr}"));
-            } else if attr == "append" {
-                // attr is the function name which is being called
-                return Ok(format!("{value}.push"));
+            if ctx.init_field(node).is_some() {
+                return Ok(path(attr));
+            }
+            if let Expr::Name(ExprName { id, .. }) = value.as_ref()
+                && ctx.has_enum(id)
+            {
+                let (e, attr) = (ident(id), ident(attr));
+                return Ok(parse_quote!(#e::#attr));
+            }
+            if ctx.is_property(value, attr, *range, false) {
+                return Ok(method_call(r_e(value, ctx)?, attr, []));
             }
 
-            Ok(format!("{value}.{attr}"))
+            Ok(field(r_e(value, ctx)?, attr))
         }
         Expr::Subscript(ExprSubscript {
             value,
             slice,
             ctx: _,
             range: _,
-        }) => Ok(format!(
-            "{v}[{s}]",
-            v = r_e(value, ctx)?,
-            s = r_e(slice, ctx)?
-        )),
-        Expr::Name(ExprName {
-            id,
-            ctx: _,
-            range: _,
-        }) => Ok(format!(
-            "{prefix}{name}",
-            prefix = if ctx.declare_var_mut { "mut " } else { "" },
-            name = id
-        )),
+        }) => {
+            let v = r_e(value, ctx)?;
+
+            if let Expr::Slice(ExprSlice {
+                lower,
+                upper,
+                step: Some(step),
+                range: _,
+            }) = slice.as_ref()
+            {
+                let lower = lower.as_deref().map(|e| r_e(e, ctx)).transpose()?;
+                let upper = upper.as_deref().map(|e| r_e(e, ctx)).transpose()?;
+                let s = range(lower, upper);
+                let indexed = index(v, s);
+                let stepped = method_call(
+                    method_call(indexed, "iter", []),
+                    "step_by",
+                    [r_e(step, ctx)?],
+                );
+                return Ok(collect(stepped, parse_quote!(Vec<_>)));
+            }
+
+            // `xs[-1]`, a `usize` index can't be negative
+            if let Expr::UnaryOp(ExprUnaryOp {
+                op: UnaryOp::USub,
+                operand,
+                ..
+            }) = slice.as_ref()
+                && matches!(operand.as_constant_expr().map(|c| &c.value), Some(Constant::Int(i)) if *i == 1.into())
+                && let infer::Ty::List(t) =
+                    global::ty(value, ctx).unwrap_or_else(|| ctx.types.ty(value, &ctx.types.locals))
+            {
+                let last = method_call(method_call(v, "last", []), "unwrap", []);
+                return Ok(if t.is_owned() {
+                    method_call(last, "clone", [])
+                } else {
+                    parse_quote!(*#last)
+                });
+            }
+
+            let s = r_e(slice, ctx)?;
+            Ok(index(v, s))
+        }
+        Expr::Name(ExprName { id, ctx: _, range }) => {
+            if id.as_str() == "self" && !ctx.init_fields.is_empty() {
+                ctx.warn(
+                    "Name",
+                    *range,
+                    "`self` is not available in `new` before the struct is created",
+                );
+            }
+            if id.as_str() == "cls" && ctx.class_method {
+                return Ok(parse_quote!(Self));
+            }
+            if global::is_static(id, ctx) {
+                return Ok(global::r_static_value(id, ctx));
+            }
+            if ctx.str_consts.contains(id.as_str()) {
+                return Ok(method_call(path(id), "to_string", []));
+            }
+            Ok(path(id))
+        }
         Expr::List(ExprList {
             elts,
             ctx: _,
             range: _,
-        }) => Ok(format!(
-            "vec![{e}]",
-            e = elts
+        }) => {
+            if elts.iter().any(Expr::is_starred_expr) {
+                return unpack::r_list(elts, ctx);
+            }
+            let e = elts
                 .iter()
                 .map(|e| r_e(e, ctx))
-                .intersperse_with(|| Ok(", ".to_string()))
-                .collect::<TResult<String>>()?
-        )),
+                .collect::<TResult<Vec<_>>>()?;
+            Ok(parse_quote!(vec![#(#e),*]))
+        }
         Expr::Tuple(ExprTuple {
             elts,
             ctx: _,
             range: _,
-        }) => Ok(format!(
-            "({e})",
-            e = elts
-                .iter()
+        }) => Ok(tuple(
+            elts.iter()
                 .map(|e| r_e(e, ctx))
-                .intersperse_with(|| Ok(", ".to_string()))
-                .collect::<TResult<String>>()?
+                .collect::<TResult<Vec<_>>>()?,
         )),
         Expr::Slice(ExprSlice {
             lower,
@@ -1268,24 +2482,14 @@ fn r_e(node: &Expr, ctx: &mut Ctx) -> TResult<String> {
             step,
             range: _,
         }) => {
-            let s = &step.as_deref().map(|e| r_e(e, ctx));
-
-            let s = if let Some(Ok(s)) = s {
-                format!(".iter().step_by({s}).collect::<Vec<_>>()")
-            } else {
-                "".to_string()
-            };
+            if step.is_some() {
+                // handled by Expr::Subscript
+                return Err(todo_link!());
+            }
 
-            Ok(format!(
-                "{l}..{u}{s}",
-                l = lower
-                    .as_deref()
-                    .map(|e| r_e(e, ctx))
-                    .unwrap_or(Ok("".to_string()))?,
-                u = upper
-                    .as_deref()
-                    .map(|e| r_e(e, ctx))
-                    .unwrap_or(Ok("".to_string()))?
+            Ok(range(
+                lower.as_deref().map(|e| r_e(e, ctx)).transpose()?,
+                upper.as_deref().map(|e| r_e(e, ctx)).transpose()?,
             ))
         }
         Expr::GeneratorExp(ExprGeneratorExp {
@@ -1296,104 +2500,459 @@ fn r_e(node: &Expr, ctx: &mut Ctx) -> TResult<String> {
             // https://peps.python.org/pep-0289/
             gen_generator(generators, elt, None, ctx)
         }
-        Expr::Starred(ExprStarred {
-            range: _,
-            value,
-            ctx: _ctx_inner,
-        }) => {
-            // https://docs.python.org/3/tutorial/controlflow.html#tut-unpacking-arguments
-            let _value = r_e(value, ctx)?;
-            Err(todo_link!())
-        }
+        // https://docs.python.org/3/tutorial/controlflow.html#tut-unpacking-arguments
+        Expr::Starred(_) => Err(todo_link!(
+            "`*value` can only be unpacked in assignments, calls and list literals"
+        )),
         // https://github.com/rust-lang/rfcs/pull/3513
-        Expr::Await(_) => Err(todo_link!()),
-        Expr::Yield(_) => Err(todo_link!()),
-        Expr::YieldFrom(_) => Err(todo_link!()),
+        Expr::Await(ExprAwait { value, .. }) => asyncio::r_await(value, ctx),
+        Expr::Yield(_) | Expr::YieldFrom(_) => generator::r_yield(node, ctx),
+    }
+}
+
+const KWARGS_WARNING: &str =
+    "keyword arguments are passed as a `Params` struct which is not generated";
+
+/// convert function call
+fn r_call(node: &ExprCall, ctx: &mut Ctx) -> TResult<syn::Expr> {
+    let ExprCall {
+        func,
+        args: _,
+        keywords,
+        range: call_range,
+    } = node;
+
+    // `cls(...)` in a `@classmethod` creates an instance of the class
+    if ctx.class_method
+        && func.as_name_expr().is_some_and(|n| n.id.as_str() == "cls")
+        && let Some(class) = ctx.types.class.clone()
+    {
+        let func = ExprName {
+            range: func.range(),
+            id: Identifier::new(class),
+            ctx: ExprContext::Load,
+        };
+        return r_call(
+            &ExprCall {
+                func: Box::new(func.into()),
+                ..node.clone()
+            },
+            ctx,
+        );
+    }
+
+    // calls of the functions of the module are resolved against their signature
+    if let Expr::Name(ExprName { id, .. }) = func.as_ref()
+        && let Some(sig) = ctx.types.functions.get(id.as_str()).cloned()
+    {
+        return params::r_call(node, id, &sig, ctx);
+    }
+
+    let args = unpack::r_args(node, ctx)?;
+
+    let function_name = match func.as_ref() {
+        Expr::Name(ExprName { id, .. }) => id.to_string(),
+        Expr::Attribute(ExprAttribute { attr, .. }) => attr.to_string(),
+        _ => String::new(),
+    };
+
+    let mut call_args = args.clone();
+    if !keywords.is_empty() {
+        // the params struct of functions which are not known, e.g. methods
+        let params = params::struct_name(&function_name);
+        let names = keywords
+            .iter()
+            .map(|kw| Ok(ident(kw.arg.as_deref().ok_or(todo_link!())?)))
+            .collect::<TResult<Vec<_>>>()?;
+        let values = keywords
+            .iter()
+            .map(|kw| r_e(&kw.value, ctx))
+            .collect::<TResult<Vec<_>>>()?;
+        call_args.push(parse_quote!(#params { #(#names: #values),* }));
+    }
+
+    if let Expr::Attribute(ExprAttribute { value, attr, .. }) = func.as_ref() {
+        if inherit::is_super(value) {
+            // methods of traits can't be called from the implementation
+            let class = ctx.types.class.as_ref().ok_or(todo_link!())?;
+            if !ctx.bases.contains_key(class) {
+                return Err(todo_link!());
+            }
+            let base = field(path("self"), "base");
+            return Ok(method_call(base, attr, call_args));
+        }
+        // static methods, class methods and methods called on the class
+        if let Expr::Name(ExprName { id, .. }) = value.as_ref() {
+            let associated = |class: &str| {
+                ctx.types
+                    .associated
+                    .get(class)
+                    .is_some_and(|a| a.contains(attr.as_str()))
+            };
+            let class: Option<syn::Path> = match id.as_str() {
+                "cls" if ctx.class_method => Some(parse_quote!(Self)),
+                "self" if ctx.types.class.as_deref().is_some_and(associated) => {
+                    Some(parse_quote!(Self))
+                }
+                id if ctx.get_class_members(id).is_some() || ctx.has_constructor(id) => {
+                    Some(ident(id).into())
+                }
+                _ => None,
+            };
+            if let Some(class) = class {
+                if !keywords.is_empty() {
+                    ctx.warn("Call", *call_range, KWARGS_WARNING);
+                }
+                let method = ident(attr);
+                return Ok(call(parse_quote!(#class::#method), call_args));
+            }
+        }
+        if let Expr::Name(ExprName { id: module, .. }) = value.as_ref() {
+            if ctx.imports.math_import_name.as_deref() == Some(module.as_str()) {
+                // TOOD automate this for all modules not just math
+                // ctx.imports.called_math_fns.push(PyMath::from(math_method));
+                ctx.imports.math.insert(attr.to_string(), None);
+                let math_method = ident(attr);
+                return Ok(call(parse_quote!(prelude::#math_method), call_args));
+            } else if module.as_str() == "asyncio" {
+                return asyncio::r_asyncio_call(attr, call_args, ctx);
+            } else if module.as_str() == "threading" && matches!(attr.as_str(), "Lock" | "RLock") {
+                return Ok(parse_quote!(std::sync::Mutex::new(())));
+            } else if let Some(file) = context::r_file_method(module, attr, call_args.clone(), ctx)?
+            {
+                return Ok(file);
+            } else if module.as_str() == "json" {
+                if attr.as_str() == "loads" {
+                    let c = call(parse_quote!(serde_json::from_string), call_args);
+                    return Ok(method_call(c, "unwrap", []));
+                } else if attr.as_str() == "dumps" {
+                    let c = call(parse_quote!(serde_json::to_string), call_args);
+                    return Ok(method_call(c, "unwrap", []));
+                }
+            } else if module.as_str() == "np" {
+                if attr.as_str() == "where" {
+                    return Ok(parse_quote!(
+                        ndarray::azip!((#(#call_args),*), { todo!("zip body") })
+                    ));
+                } else {
+                    // TODO add numpy polyfill like done for math
+                    return Err(todo_link!());
+                }
+            }
+        }
+
+        if !matches!(value.as_ref(), Expr::Name(ExprName { id, .. }) if ctx.has_enum(id)) {
+            let receiver = r_e(value, ctx)?;
+            // attr is the function name which is being called
+            let method = if attr.as_str() == "append" {
+                "push"
+            } else {
+                attr.as_str()
+            };
+            if !keywords.is_empty() {
+                ctx.warn("Call", *call_range, KWARGS_WARNING);
+            }
+            // `IndexError` of an empty list is a panic
+            if method == "pop"
+                && call_args.is_empty()
+                && matches!(ctx.types.ty(value, &ctx.types.locals), infer::Ty::List(_))
+            {
+                return Ok(method_call(method_call(receiver, "pop", []), "unwrap", []));
+            }
+            return Ok(method_call(receiver, method, call_args));
+        }
+    }
+
+    if matches!(func.as_ref(), Expr::Name(_)) && ctx.has_constructor(&function_name) {
+        if !keywords.is_empty() {
+            ctx.warn("Call", *call_range, KWARGS_WARNING);
+        }
+        let class = ident(&function_name);
+        return Ok(call(parse_quote!(#class::new), call_args));
+    }
+
+    // support for Dataclass like classes
+    if let Some(members) = ctx.get_class_members(&function_name) {
+        // Class/ Struct Init
+        let class = ident(&function_name);
+        let (members, values): (Vec<_>, Vec<_>) = if keywords.is_empty() {
+            // only args
+            members
+                .iter()
+                .map(|m| ident(m))
+                .zip(args.iter().cloned())
+                .unzip()
+        } else {
+            if !args.is_empty() {
+                // mixing keyword args and non keyword args here is probably an error
+                // double check the python spec on the exact sematics and be sure to translate them
+                return Err(todo_link!());
+            }
+
+            // only kwargs
+            keywords
+                .iter()
+                .map(|k| {
+                    Ok((
+                        ident(k.arg.as_deref().ok_or(todo_link!())?),
+                        r_e(&k.value, ctx)?,
+                    ))
+                })
+                .collect::<TResult<Vec<_>>>()?
+                .into_iter()
+                .unzip()
+        };
+        // string literals passed to `str` fields become owned like their defaults
+        let fields = ctx.types.classes.get(function_name.as_str());
+        let values: Vec<syn::Expr> = members
+            .iter()
+            .zip(values)
+            .map(|(m, v)| {
+                let is_str = fields.is_some_and(|fs| {
+                    fs.iter()
+                        .any(|(f, t)| m == f.as_str() && *t == infer::Ty::Str)
+                });
+                match &v {
+                    syn::Expr::Lit(syn::ExprLit {
+                        lit: syn::Lit::Str(_),
+                        ..
+                    }) if is_str => method_call(v, "to_string", []),
+                    _ => v,
+                }
+            })
+            .collect();
+
+        if members.len() < ctx.get_class_members(&function_name).map_or(0, <[_]>::len) {
+            // the other fields have default values
+            return Ok(parse_quote!(#class { #(#members: #values,)* ..Default::default() }));
+        }
+        return Ok(parse_quote!(#class { #(#members: #values),* }));
+    } else if matches!(func.as_ref(), Expr::Name(_))
+        && matches!(function_name.as_str(), "Lock" | "RLock")
+    {
+        return Ok(parse_quote!(std::sync::Mutex::new(())));
+    } else if function_name == "print" {
+        let fmt = "{:?}";
+        return Ok(parse_quote!(println!(#fmt, #(#call_args),*)));
+    } else if function_name == "enumerate" {
+        let [arg] = call_args.as_slice() else {
+            return Err(todo_link!());
+        };
+        return Ok(method_call(
+            method_call(arg.clone(), "iter", []),
+            "enumerate",
+            [],
+        ));
+    } else if function_name == "zip" {
+        let add_mapping = args.len() > 2;
+
+        let names = args
+            .iter()
+            .enumerate()
+            .map(|(i, arg)| match arg {
+                syn::Expr::Path(p) if let Some(name) = p.path.get_ident() => name.to_string(),
+                _ => format!("v{i}"),
+            })
+            .collect::<Vec<_>>();
+
+        let mut args = args.into_iter();
+        let first = args.next().ok_or(todo_link!())?;
+
+        let mut zips = method_call(first, "iter", []);
+        let mut maps = pat_ident(&names[0], false);
+
+        for (arg, name) in args.zip(names.iter().skip(1)) {
+            zips = method_call(zips, "zip", [method_call(arg, "iter", [])]);
+            maps = pat_tuple(vec![maps, pat_ident(name, false)]);
+        }
+
+        if add_mapping {
+            let flat = tuple(names.iter().map(|n| path(n)).collect());
+            zips = method_call(zips, "map", [closure(vec![maps], flat)]);
+        }
+
+        return Ok(zips);
+    } else if function_name == "str" {
+        let [arg] = call_args.as_slice() else {
+            return Err(todo_link!());
+        };
+        return Ok(method_call(arg.clone(), "to_string", []));
+    } else if function_name == "len" {
+        let [arg] = call_args.as_slice() else {
+            return Err(todo_link!());
+        };
+        return Ok(method_call(arg.clone(), "len", []));
+    } else if function_name == "sum" {
+        let [arg] = call_args.as_slice() else {
+            return Err(todo_link!());
+        };
+        return Ok(method_call(method_call(arg.clone(), "iter", []), "sum", []));
+    } else if function_name == "int" || function_name == "float" {
+        let f = r_annotation(func)?;
+        let [arg] = call_args.as_slice() else {
+            return Err(todo_link!());
+        };
+        if exception::is_parse(node, &ctx.types, &ctx.types.locals) {
+            let mut parsed = method_call(arg.clone(), "parse", []);
+            if let syn::Expr::MethodCall(m) = &mut parsed {
+                m.turbofish = Some(parse_quote!(::<#f>));
+            }
+            // the caller propagates the error with `?`
+            if ctx.result_errors {
+                let error = parse_quote! {
+                    |e| Error::ValueError { message: e.to_string(), source: None }
+                };
+                return Ok(method_call(parsed, "map_err", [error]));
+            }
+            return Ok(method_call(parsed, "unwrap", []));
+        }
+        return Ok(cast(arg.clone(), f));
+    } else if function_name == "range" {
+        // TODO handle start and step interval here in a more robust way
+        // keyword args start stop step need to be handled
+        match call_args.as_slice() {
+            [end] => return Ok(range(Some(parse_quote!(0)), Some(end.clone()))),
+            [start, end] => return Ok(range(Some(start.clone()), Some(end.clone()))),
+            [_start, _step, _stop] => {
+                // start stop step
+                return Err(todo_link!());
+            }
+            _ => return Err(todo_link!()),
+        }
+    }
+
+    if !keywords.is_empty() {
+        ctx.warn("Call", *call_range, KWARGS_WARNING);
+    }
+    Ok(call(r_e(func, ctx)?, call_args))
+}
+
+/// convert assignment target (or any other place which binds names) into a pattern
+fn r_target(node: &Expr, ctx: &mut Ctx) -> TResult<syn::Pat> {
+    match node {
+        Expr::Name(ExprName { id, .. }) => Ok(pat_ident(id, ctx.scopes.is_mutated(id))),
+        Expr::Tuple(ExprTuple { elts, .. }) | Expr::List(ExprList { elts, .. }) => Ok(pat_tuple(
+            elts.iter()
+                .map(|e| r_target(e, ctx))
+                .collect::<TResult<Vec<_>>>()?,
+        )),
+        _ => Err(todo_link!()),
     }
 }
 
 fn gen_generator(
     generators: &[rustpython_parser::ast::Comprehension],
     elt: &Expr,
-    collection_type: Option<&str>,
+    collection_type: Option<syn::Type>,
     ctx: &mut Ctx,
-) -> TResult<String> {
+) -> TResult<syn::Expr> {
     if generators.len() != 1 {
         // only one level nested for loops are supported
         return Err(todo_link!());
     }
     let g = &generators[0];
     let body = r_e(elt, ctx)?;
+    let iter = method_call(r_e(&g.iter, ctx)?, "into_iter", []);
+    let target = r_target(&g.target, ctx)?;
+
+    let res = if let Some(ifs) = g.ifs.first() {
+        let body = if_expr(
+            r_e(ifs, ctx)?,
+            vec![syn::Stmt::Expr(parse_quote!(Some(#body)), None)],
+            Some(tail_block(parse_quote!(None))),
+        );
 
-    let collect = if let Some(collection_type) = collection_type {
-        format!(".collect::<{collection_type}>()")
+        method_call(
+            iter,
+            "filter_map",
+            [closure(vec![target], tail_block(body))],
+        )
     } else {
-        String::new()
+        method_call(iter, "map", [closure(vec![target], tail_block(body))])
     };
 
-    if let Some(ifs) = g.ifs.first() {
-        let body = format!(
-            "if {cond} {{ Some({body}) }} else {{ None }} ",
-            cond = r_e(ifs, ctx)?
-        );
-
-        Ok(format!(
-            "{gen}.into_iter().filter_map(|{target}| {{ {body} }}){collect}",
-            gen = r_e(&g.iter, ctx)?,
-            target = r_e(&g.target, ctx)?,
-        ))
-    } else {
-        Ok(format!(
-            "{gen}.into_iter().map(|{target}| {{ {body} }}){collect}",
-            gen = r_e(&g.iter, ctx)?,
-            target = r_e(&g.target, ctx)?,
-        ))
-    }
+    Ok(match collection_type {
+        Some(t) => collect(res, t),
+        None => res,
+    })
 }
 
 /// convert args
-fn r_a(node: &ArgWithDefault, ctx: &mut Ctx) -> TResult<String> {
-    let node = node.to_arg().0;
+fn r_a(node: &ArgWithDefault, ctx: &mut Ctx) -> TResult<syn::FnArg> {
+    r_a_inner(node, ctx).map_err(|e| e.at("arg", node.range))
+}
+
+fn r_a_inner(node: &ArgWithDefault, ctx: &mut Ctx) -> TResult<syn::FnArg> {
+    let node = &node.def;
     if node.arg.as_str() == "self" {
-        Ok("&self".to_string())
+        Ok(ctx.receiver.to_syn())
     } else {
-        let t = &node
-            .annotation
-            .clone()
-            .map(|e| r_annotation(&e))
-            .unwrap_or(Ok("()".to_string()))?;
-
-        if t.starts_with("numpy::") {
+        let t = match node.annotation.as_deref() {
+            // exception classes are variants of `Error`
+            Some(Expr::Name(n)) if ctx.result_errors && ctx.exceptions.is_exception(&n.id) => {
+                parse_quote!(Error)
+            }
+            Some(e) => r_annotation(e)?,
+            None => ctx
+                .types
+                .locals
+                .get(node.arg.as_str())
+                .and_then(infer::Ty::to_syn)
+                .unwrap_or_else(|| parse_quote!(())),
+        };
+
+        // numpy arrays are copied into a new binding at the start of the function
+        let numpy = type_starts_with(&t, "numpy");
+        if numpy {
             ctx.numpy_array_args
-                .push((node.arg.to_string(), t.to_string()));
+                .push((node.arg.to_string(), quote::quote!(#t).to_string()));
         }
 
-        Ok(format!("{n}: {t}", n = node.arg))
+        let n = pat_ident(&node.arg, !numpy && ctx.scopes.is_mutated(&node.arg));
+        Ok(parse_quote!(#n: #t))
     }
 }
 
-fn r_annotation(e: &Expr) -> TResult<String> {
+fn r_annotation(e: &Expr) -> TResult<syn::Type> {
     match e {
         Expr::Constant(ExprConstant {
             range: _,
             value,
             kind: _,
-        }) => Ok(value.clone().str().unwrap().to_owned()),
+        }) => match value {
+            Constant::None => Ok(parse_quote!(())),
+            Constant::Str(s) => syn::parse_str(s).map_err(|_| todo_link!()),
+            _ => Err(todo_link!()),
+        },
         Expr::Subscript(ExprSubscript {
             value,
             slice,
             ctx: _,
             range: _,
         }) => {
-            let v = r_annotation(value)?;
-            let s = r_annotation(slice)?;
+            let s = match slice.as_ref() {
+                Expr::Tuple(ExprTuple { elts, .. }) => {
+                    elts.iter().map(r_annotation).collect::<TResult<Vec<_>>>()?
+                }
+                s => vec![r_annotation(s)?],
+            };
 
-            if v == "tuple" {
-                Ok(format!("({s})"))
-            } else if let Some(v) = v.strip_prefix("Np") {
-                Ok(format!("numpy::Py{v}<{s}>"))
+            let name = value.as_name_expr().map(|n| n.id.as_str());
+            if name == Some("tuple") {
+                Ok(parse_quote!((#(#s,)*)))
+            } else if matches!(name, Some("Iterator" | "Generator")) {
+                // the send and return types of `Generator` are not used
+                let item = &s[0];
+                Ok(parse_quote!(impl Iterator<Item = #item>))
+            } else if name == Some("Iterable") {
+                let item = &s[0];
+                Ok(parse_quote!(impl IntoIterator<Item = #item>))
+            } else if let Some(v) = name.and_then(|n| n.strip_prefix("Np")) {
+                let v = ident(&format!("Py{v}"));
+                Ok(parse_quote!(numpy::#v<#(#s),*>))
             } else {
-                Ok(format!("{v}<{s}>"))
+                let v = r_annotation(value)?;
+                Ok(parse_quote!(#v<#(#s),*>))
             }
         }
         Expr::Name(ExprName {
@@ -1408,24 +2967,27 @@ fn r_annotation(e: &Expr) -> TResult<String> {
             // this would make tuple vs generic more precise and would
             // allow better extensions
             Ok(match id.as_str() {
-                "Dict" => "std::collections::HashMap".to_string(),
-                "float" => "f64".to_string(),
-                "int" => "isize".to_string(),
-                "List" => "Vec".to_string(),
-                "Optional" => "Option".to_string(),
-                "str" => "String".to_string(),
-                _ => id.to_string(),
+                "Dict" => parse_quote!(std::collections::HashMap),
+                "float" => parse_quote!(f64),
+                "int" => parse_quote!(isize),
+                "List" => parse_quote!(Vec),
+                "Optional" => parse_quote!(Option),
+                "str" => parse_quote!(String),
+                "Lock" | "RLock" => parse_quote!(std::sync::Mutex<()>),
+                _ => {
+                    let id = ident(id);
+                    parse_quote!(#id)
+                }
             })
         }
         Expr::Tuple(ExprTuple {
             elts,
             ctx: _,
             range: _,
-        }) => elts
-            .iter()
-            .map(r_annotation)
-            .intersperse_with(|| Ok(", ".to_string()))
-            .collect::<TResult<String>>(),
+        }) => {
+            let elts = elts.iter().map(r_annotation).collect::<TResult<Vec<_>>>()?;
+            Ok(parse_quote!((#(#elts,)*)))
+        }
         Expr::Attribute(_) => Err(todo_link!()),
         Expr::Await(_) => Err(todo_link!()),
         Expr::BinOp(_) => Err(todo_link!()),
@@ -1453,57 +3015,150 @@ fn r_annotation(e: &Expr) -> TResult<String> {
 }
 
 /// convert binary operator
-fn r_o(node: &Operator) -> TResult<&'static str> {
+fn r_o(node: &Operator) -> TResult<syn::BinOp> {
     match node {
-        Operator::Add => Ok("+"),
-        Operator::BitAnd => Ok("&"),
-        Operator::BitOr => Ok("|"),
-        Operator::BitXor => Ok("^"),
-        Operator::Div => Ok("/"),
-        Operator::LShift => Ok("<<"),
-        Operator::Mod => Ok("%"),
-        Operator::Mult => Ok("*"),
-        Operator::RShift => Ok(">>"),
-        Operator::Sub => Ok("-"),
+        Operator::Add => Ok(parse_quote!(+)),
+        Operator::BitAnd => Ok(parse_quote!(&)),
+        Operator::BitOr => Ok(parse_quote!(|)),
+        Operator::BitXor => Ok(parse_quote!(^)),
+        Operator::Div => Ok(parse_quote!(/)),
+        Operator::LShift => Ok(parse_quote!(<<)),
+        Operator::Mod => Ok(parse_quote!(%)),
+        Operator::Mult => Ok(parse_quote!(*)),
+        Operator::RShift => Ok(parse_quote!(>>)),
+        Operator::Sub => Ok(parse_quote!(-)),
         Operator::FloorDiv => Err(todo_link!()),
         Operator::MatMult => Err(todo_link!()),
         Operator::Pow => unreachable!(),
     }
 }
 
+/// convert binary operator of augmented assignment (`+=`, ...)
+fn r_o_assign(node: &Operator) -> TResult<syn::BinOp> {
+    match node {
+        Operator::Add => Ok(parse_quote!(+=)),
+        Operator::BitAnd => Ok(parse_quote!(&=)),
+        Operator::BitOr => Ok(parse_quote!(|=)),
+        Operator::BitXor => Ok(parse_quote!(^=)),
+        Operator::Div => Ok(parse_quote!(/=)),
+        Operator::LShift => Ok(parse_quote!(<<=)),
+        Operator::Mod => Ok(parse_quote!(%=)),
+        Operator::Mult => Ok(parse_quote!(*=)),
+        Operator::RShift => Ok(parse_quote!(>>=)),
+        Operator::Sub => Ok(parse_quote!(-=)),
+        Operator::FloorDiv => Err(todo_link!()),
+        Operator::MatMult => Err(todo_link!()),
+        Operator::Pow => Err(todo_link!()),
+    }
+}
+
 /// convert boolean operation
-fn r_bool(node: &BoolOp) -> &str {
+fn r_bool(node: &BoolOp) -> syn::BinOp {
     match node {
-        BoolOp::And => "&&",
-        BoolOp::Or => "||",
+        BoolOp::And => parse_quote!(&&),
+        BoolOp::Or => parse_quote!(||),
     }
 }
 
 /// convert comparison
-fn r_c(node: &CmpOp) -> TResult<&str> {
+fn r_c(node: &CmpOp) -> TResult<syn::BinOp> {
     match node {
-        CmpOp::Eq => Ok("=="),
-        CmpOp::NotEq => Ok("!="),
-        CmpOp::Lt => Ok("<"),
-        CmpOp::LtE => Ok("<="),
-        CmpOp::Gt => Ok(">"),
-        CmpOp::GtE => Ok(">="),
-        CmpOp::Is => Ok("=="),    // a bit of a hack
-        CmpOp::IsNot => Ok("!="), // a bit of a hack
+        CmpOp::Eq => Ok(parse_quote!(==)),
+        CmpOp::NotEq => Ok(parse_quote!(!=)),
+        CmpOp::Lt => Ok(parse_quote!(<)),
+        CmpOp::LtE => Ok(parse_quote!(<=)),
+        CmpOp::Gt => Ok(parse_quote!(>)),
+        CmpOp::GtE => Ok(parse_quote!(>=)),
+        CmpOp::Is => Ok(parse_quote!(==)),    // a bit of a hack
+        CmpOp::IsNot => Ok(parse_quote!(!=)), // a bit of a hack
         CmpOp::In => unreachable!(),
-        CmpOp::NotIn => Ok("!contains(TODO)"),
+        CmpOp::NotIn => unreachable!(),
+    }
+}
+
+/// `# foo` -> `foo`
+fn comment_text(comment: &str) -> &str {
+    let c = comment.strip_prefix('#').unwrap_or(comment);
+    c.strip_prefix(' ').unwrap_or(c).trim_end()
+}
+
+/// name of the statement like in the python `ast` module
+fn stmt_kind(node: &Stmt) -> &'static str {
+    match node {
+        Stmt::FunctionDef(_) => "FunctionDef",
+        Stmt::AsyncFunctionDef(_) => "AsyncFunctionDef",
+        Stmt::ClassDef(_) => "ClassDef",
+        Stmt::Return(_) => "Return",
+        Stmt::Delete(_) => "Delete",
+        Stmt::Assign(_) => "Assign",
+        Stmt::TypeAlias(_) => "TypeAlias",
+        Stmt::AugAssign(_) => "AugAssign",
+        Stmt::AnnAssign(_) => "AnnAssign",
+        Stmt::For(_) => "For",
+        Stmt::AsyncFor(_) => "AsyncFor",
+        Stmt::While(_) => "While",
+        Stmt::If(_) => "If",
+        Stmt::With(_) => "With",
+        Stmt::AsyncWith(_) => "AsyncWith",
+        Stmt::Match(_) => "Match",
+        Stmt::Raise(_) => "Raise",
+        Stmt::Try(_) => "Try",
+        Stmt::TryStar(_) => "TryStar",
+        Stmt::Assert(_) => "Assert",
+        Stmt::Import(_) => "Import",
+        Stmt::ImportFrom(_) => "ImportFrom",
+        Stmt::Global(_) => "Global",
+        Stmt::Nonlocal(_) => "Nonlocal",
+        Stmt::Expr(_) => "Expr",
+        Stmt::Pass(_) => "Pass",
+        Stmt::Break(_) => "Break",
+        Stmt::Continue(_) => "Continue",
+    }
+}
+
+/// name of the expression like in the python `ast` module
+fn expr_kind(node: &Expr) -> &'static str {
+    match node {
+        Expr::BoolOp(_) => "BoolOp",
+        Expr::NamedExpr(_) => "NamedExpr",
+        Expr::BinOp(_) => "BinOp",
+        Expr::UnaryOp(_) => "UnaryOp",
+        Expr::Lambda(_) => "Lambda",
+        Expr::IfExp(_) => "IfExp",
+        Expr::Dict(_) => "Dict",
+        Expr::Set(_) => "Set",
+        Expr::ListComp(_) => "ListComp",
+        Expr::SetComp(_) => "SetComp",
+        Expr::DictComp(_) => "DictComp",
+        Expr::GeneratorExp(_) => "GeneratorExp",
+        Expr::Await(_) => "Await",
+        Expr::Yield(_) => "Yield",
+        Expr::YieldFrom(_) => "YieldFrom",
+        Expr::Compare(_) => "Compare",
+        Expr::Call(_) => "Call",
+        Expr::FormattedValue(_) => "FormattedValue",
+        Expr::JoinedStr(_) => "JoinedStr",
+        Expr::Constant(_) => "Constant",
+        Expr::Attribute(_) => "Attribute",
+        Expr::Subscript(_) => "Subscript",
+        Expr::Starred(_) => "Starred",
+        Expr::Name(_) => "Name",
+        Expr::List(_) => "List",
+        Expr::Tuple(_) => "Tuple",
+        Expr::Slice(_) => "Slice",
     }
 }
 
-fn r_p(node: &Pattern, ctx: &mut Ctx) -> TResult<String> {
+/// name of the pattern like in the python `ast` module
+fn pattern_kind(node: &Pattern) -> &'static str {
     match node {
-        Pattern::MatchValue(PatternMatchValue { range: _, value }) => r_e(value, ctx),
-        Pattern::MatchAs(_) => Err(todo_link!()),
-        Pattern::MatchClass(_) => Err(todo_link!()),
-        Pattern::MatchMapping(_) => Err(todo_link!()),
-        Pattern::MatchOr(_) => Err(todo_link!()),
-        Pattern::MatchSequence(_) => Err(todo_link!()),
-        Pattern::MatchSingleton(_) => Err(todo_link!()),
-        Pattern::MatchStar(_) => Err(todo_link!()),
+        Pattern::MatchValue(_) => "MatchValue",
+        Pattern::MatchSingleton(_) => "MatchSingleton",
+        Pattern::MatchSequence(_) => "MatchSequence",
+        Pattern::MatchMapping(_) => "MatchMapping",
+        Pattern::MatchClass(_) => "MatchClass",
+        Pattern::MatchStar(_) => "MatchStar",
+        Pattern::MatchAs(_) => "MatchAs",
+        Pattern::MatchOr(_) => "MatchOr",
     }
 }
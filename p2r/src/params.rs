@@ -0,0 +1,247 @@
+//! default values, keyword arguments, `*args` and `**kwargs`
+//!
+//! rust has neither default values nor keyword arguments: the parameters of a function which
+//! have a default value become the fields of a `FooParams` struct which implements `Default`,
+//! `*args` becomes a slice and `**kwargs` a map. calls of the functions of the module are
+//! resolved against their signature, keyword arguments are moved to their position and the
+//! missing fields of the params struct come from `..Default::default()`
+
+use crate::infer::{Kind, Signature, Ty};
+use crate::{
+    call, ident, pat_ident, r_a, r_annotation, r_default, r_e, todo_link, unpack, Ctx, TResult,
+    TranspileError,
+};
+use rustpython_parser::ast::{Arg, ArgWithDefault, Arguments, ExprCall, StmtFunctionDef};
+use std::collections::HashMap;
+use syn::parse_quote;
+
+/// names of all parameters
+pub fn names(args: &Arguments) -> impl Iterator<Item = &str> {
+    let Arguments {
+        posonlyargs,
+        args,
+        vararg,
+        kwonlyargs,
+        kwarg,
+        ..
+    } = args;
+    posonlyargs
+        .iter()
+        .chain(args)
+        .map(|a| &a.def)
+        .chain(vararg.as_deref())
+        .chain(kwonlyargs.iter().map(|a| &a.def))
+        .chain(kwarg.as_deref())
+        .map(|a| a.arg.as_str())
+}
+
+/// parameters with a default value
+fn defaults(args: &Arguments) -> impl Iterator<Item = &ArgWithDefault> {
+    args.posonlyargs
+        .iter()
+        .chain(&args.args)
+        .chain(&args.kwonlyargs)
+        .filter(|a| a.default.is_some())
+}
+
+/// the defaults of functions are passed in a params struct, methods can't declare it next to
+/// them and take all parameters
+fn has_struct(def: &StmtFunctionDef, ctx: &Ctx) -> bool {
+    ctx.types.class.is_none() && defaults(&def.args).next().is_some()
+}
+
+/// `FooBarParams` for the parameters with default values of `foo_bar`
+pub fn struct_name(function: &str) -> syn::Ident {
+    let name = function
+        .split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|c| c.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect::<String>();
+    ident(&format!("{name}Params"))
+}
+
+/// the params struct of the function `def` and its `Default` impl
+pub fn r_struct(def: &StmtFunctionDef, ctx: &mut Ctx) -> TResult<Vec<syn::Stmt>> {
+    if !has_struct(def, ctx) {
+        return Ok(vec![]);
+    }
+    let types = ctx.types.params(def);
+    let name = struct_name(&def.name);
+    let mut fields = vec![];
+    let mut field_types = vec![];
+    let mut values = vec![];
+    for a in defaults(&def.args) {
+        fields.push(ident(&a.def.arg));
+        field_types.push(r_type(&a.def, &types)?);
+        values.push(r_default(a.default.as_deref().unwrap(), ctx)?);
+    }
+    Ok(vec![
+        parse_quote! {
+            struct #name {
+                #(#fields: #field_types),*
+            }
+        },
+        parse_quote! {
+            impl Default for #name {
+                fn default() -> Self {
+                    Self {
+                        #(#fields: #values),*
+                    }
+                }
+            }
+        },
+    ])
+}
+
+fn r_type(arg: &Arg, types: &HashMap<String, Ty>) -> TResult<syn::Type> {
+    match arg.annotation.as_deref() {
+        Some(e) => r_annotation(e),
+        None => Ok(types
+            .get(arg.arg.as_str())
+            .and_then(Ty::to_syn)
+            .unwrap_or_else(|| parse_quote!(()))),
+    }
+}
+
+/// parameters of the rust function: the positional ones, `*args`, the keyword-only ones,
+/// `**kwargs` and the params struct, whose fields are bound at the start of the body
+pub fn r_params(
+    def: &StmtFunctionDef,
+    ctx: &mut Ctx,
+) -> TResult<(Vec<syn::FnArg>, Option<syn::Stmt>)> {
+    let Arguments {
+        posonlyargs,
+        args,
+        vararg,
+        kwonlyargs,
+        kwarg,
+        ..
+    } = def.args.as_ref();
+    let with_struct = has_struct(def, ctx);
+    if !with_struct && defaults(&def.args).next().is_some() {
+        ctx.warn(
+            "FunctionDef",
+            def.range,
+            "default values of methods are ignored, the parameters are required",
+        );
+    }
+    let required = |a: &&ArgWithDefault| !with_struct || a.default.is_none();
+
+    let mut params = posonlyargs
+        .iter()
+        .chain(args)
+        .filter(required)
+        .map(|a| r_a(a, ctx))
+        .collect::<TResult<Vec<_>>>()?;
+    if let Some(a) = vararg {
+        params.push(r_variadic(a, Kind::Args, ctx)?);
+    }
+    for a in kwonlyargs.iter().filter(required) {
+        params.push(r_a(a, ctx)?);
+    }
+    if let Some(a) = kwarg {
+        params.push(r_variadic(a, Kind::Kwargs, ctx)?);
+    }
+    if !with_struct {
+        return Ok((params, None));
+    }
+
+    let name = struct_name(&def.name);
+    params.push(parse_quote!(params: #name));
+    let fields =
+        defaults(&def.args).map(|a| pat_ident(&a.def.arg, ctx.scopes.is_mutated(&a.def.arg)));
+    Ok((
+        params,
+        Some(parse_quote!(let #name { #(#fields),* } = params;)),
+    ))
+}
+
+/// `*args: int` -> `args: &[isize]`, `**kwargs: int` -> `kwargs: &HashMap<&str, isize>`
+fn r_variadic(a: &Arg, kind: Kind, ctx: &mut Ctx) -> TResult<syn::FnArg> {
+    let t = match a.annotation.as_deref() {
+        Some(e) => r_annotation(e)?,
+        None => match ctx.types.locals.get(a.arg.as_str()) {
+            Some(Ty::List(t) | Ty::Dict(_, t)) => t.to_syn(),
+            _ => None,
+        }
+        .unwrap_or_else(|| parse_quote!(())),
+    };
+    let name = pat_ident(&a.arg, false);
+    Ok(match kind {
+        Kind::Kwargs => parse_quote!(#name: &std::collections::HashMap<&str, #t>),
+        _ => parse_quote!(#name: &[#t]),
+    })
+}
+
+/// `foo(1, c=3)` of a function of the module -> `foo(1, FooParams { c: 3, ..Default::default() })`
+pub fn r_call(node: &ExprCall, name: &str, sig: &Signature, ctx: &mut Ctx) -> TResult<syn::Expr> {
+    let has = |kind| sig.params.iter().any(|p| p.kind == kind);
+    let mut slots = vec![None; sig.params.len()];
+    let mut rest = vec![];
+    let mut kwargs = vec![];
+
+    for (i, arg) in unpack::r_args(node, ctx)?.into_iter().enumerate() {
+        if i < sig.positional {
+            slots[i] = Some(arg);
+        } else if has(Kind::Args) {
+            rest.push(arg);
+        } else {
+            return Err(todo_link!("the function takes less positional arguments"));
+        }
+    }
+    for keyword in &node.keywords {
+        let Some(arg) = keyword.arg.as_deref() else {
+            return Err(todo_link!("`**kwargs` can't be spread into a call"));
+        };
+        let value = r_e(&keyword.value, ctx)?;
+        let param = sig
+            .params
+            .iter()
+            .position(|p| p.name == arg && !matches!(p.kind, Kind::Args | Kind::Kwargs));
+        match param {
+            Some(i) if slots[i].is_none() => slots[i] = Some(value),
+            Some(_) => return Err(todo_link!("the argument is passed twice")),
+            None if has(Kind::Kwargs) => kwargs.push((arg.to_string(), value)),
+            None => return Err(todo_link!("the function has no parameter of this name")),
+        }
+    }
+
+    let mut args = vec![];
+    let mut fields = vec![];
+    let mut values = vec![];
+    for (p, slot) in sig.params.iter().zip(slots) {
+        match p.kind {
+            Kind::Positional | Kind::Keyword => {
+                args.push(slot.ok_or(todo_link!("an argument without default value is missing"))?)
+            }
+            Kind::Default => {
+                if let Some(v) = slot {
+                    fields.push(ident(&p.name));
+                    values.push(v);
+                }
+            }
+            Kind::Args => args.push(parse_quote!(&[#(#rest),*])),
+            Kind::Kwargs => {
+                let (keys, values): (Vec<_>, Vec<_>) =
+                    std::mem::take(&mut kwargs).into_iter().unzip();
+                args.push(parse_quote!(
+                    &std::collections::HashMap::from([#((#keys, #values)),*])
+                ));
+            }
+        }
+    }
+    if has(Kind::Default) {
+        let params = struct_name(name);
+        args.push(if fields.is_empty() {
+            parse_quote!(#params::default())
+        } else {
+            parse_quote!(#params { #(#fields: #values,)* ..Default::default() })
+        });
+    }
+    Ok(call(r_e(&node.func, ctx)?, args))
+}
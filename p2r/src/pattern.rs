@@ -0,0 +1,468 @@
+//! `match` statements, the patterns of `case` become rust patterns
+//!
+//! maps can't be destructured in rust, mapping patterns become guards which look up the keys,
+//! the captured values are looked up at the start of the arm
+
+use crate::infer::{visit_expr, Ty};
+use crate::scope::pattern_names;
+use crate::util::pat_tuple;
+use crate::{
+    arm_body, binary, ident, method_call, pat_ident, pattern_kind, r_body, r_e, reference, stmt,
+    todo_link, Ctx, TResult, TranspileError,
+};
+use rustpython_parser::ast::{
+    Constant, Expr, ExprAttribute, ExprName, MatchCase, Pattern, PatternMatchAs, PatternMatchClass,
+    PatternMatchMapping, PatternMatchSingleton, PatternMatchStar, PatternMatchValue, Ranged,
+    StmtMatch,
+};
+use syn::parse_quote;
+
+/// how sequence patterns are translated
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Sequence {
+    /// `[a, *rest]` -> `[a, rest @ ..]`, the subject is a slice
+    Slice,
+    /// `[a, *rest]` -> `&[a, ref rest @ ..]`, the elements of the slice are `Copy`
+    Values,
+    /// `(a, b)` -> `(a, b)`, nested sequences are assumed to be tuples
+    Tuple,
+}
+
+/// `match subject: case ...` -> `match subject { ... }`
+pub fn r_match(node: &StmtMatch, ctx: &mut Ctx) -> TResult<Vec<syn::Stmt>> {
+    let StmtMatch { subject, cases, .. } = node;
+    let ty = ctx.types.ty(subject, &ctx.types.locals);
+    let sequence = match &ty {
+        Ty::Tuple(_) => Sequence::Tuple,
+        Ty::List(t) if **t != Ty::Unknown && !t.is_owned() => Sequence::Values,
+        _ => Sequence::Slice,
+    };
+    let mut value = r_e(subject, ctx)?;
+    // slice patterns don't match a `Vec` and string literals don't match a `String`
+    match ty {
+        Ty::List(_) if cases.iter().any(|c| is_sequence(&c.pattern)) => {
+            value = method_call(value, "as_slice", []);
+        }
+        Ty::Str => value = method_call(value, "as_str", []),
+        _ => {}
+    }
+
+    let mut arms = vec![];
+    for case in cases {
+        ctx.scopes.push();
+        ctx.scopes.declare_pattern(&case.pattern);
+        let arm = r_arm(case, subject, sequence, ctx);
+        ctx.scopes.pop();
+        arms.push(arm?);
+    }
+    if !exhaustive(cases, sequence, ctx) {
+        arms.push(parse_quote!(_ => {}));
+    }
+
+    Ok(vec![stmt(syn::Expr::Match(syn::ExprMatch {
+        attrs: vec![],
+        match_token: Default::default(),
+        expr: Box::new(value),
+        brace_token: Default::default(),
+        arms,
+    }))])
+}
+
+fn r_arm(case: &MatchCase, subject: &Expr, sequence: Sequence, ctx: &mut Ctx) -> TResult<syn::Arm> {
+    let MatchCase {
+        pattern,
+        guard,
+        body,
+        ..
+    } = case;
+    if let Pattern::MatchMapping(mapping) = pattern {
+        return r_mapping_arm(mapping, subject, guard.as_deref(), body, ctx);
+    }
+
+    let pat = r_p(pattern, sequence, ctx)?;
+    let guard = guard.as_deref().map(|g| r_e(g, ctx)).transpose()?;
+    Ok(arm(pat, guard, arm_body(r_body(body, ctx)?)))
+}
+
+fn arm(pat: syn::Pat, guard: Option<syn::Expr>, body: syn::Expr) -> syn::Arm {
+    syn::Arm {
+        attrs: vec![],
+        pat,
+        guard: guard.map(|g| (Default::default(), Box::new(g))),
+        fat_arrow_token: Default::default(),
+        body: Box::new(body),
+        comma: Some(Default::default()),
+    }
+}
+
+/// `case {"key": value, "other": name}:` -> `_ if map.get("key").is_some_and(|v| *v == value)
+/// && map.contains_key("other") => { let name = &map["other"]; ... }`
+fn r_mapping_arm(
+    node: &PatternMatchMapping,
+    subject: &Expr,
+    guard: Option<&Expr>,
+    body: &[rustpython_parser::ast::Stmt],
+    ctx: &mut Ctx,
+) -> TResult<syn::Arm> {
+    let PatternMatchMapping {
+        keys,
+        patterns,
+        rest,
+        ..
+    } = node;
+    if rest.is_some() {
+        return Err(todo_link!(
+            "`**rest` of mapping patterns can't be translated"
+        ));
+    }
+    if !matches!(subject, Expr::Name(_)) {
+        return Err(todo_link!(
+            "the subject of mapping patterns has to be a variable"
+        ));
+    }
+    let map = r_e(subject, ctx)?;
+
+    let mut conditions = vec![];
+    let mut lookups: Vec<syn::Stmt> = vec![];
+    for (key, pattern) in keys.iter().zip(patterns) {
+        let key = match r_e(key, ctx)? {
+            // `&str` is the borrowed form of `String` keys
+            key @ syn::Expr::Lit(syn::ExprLit {
+                lit: syn::Lit::Str(_),
+                ..
+            }) => key,
+            key => reference(key),
+        };
+        let value = match pattern {
+            Pattern::MatchValue(PatternMatchValue { value, .. }) => Some(r_e(value, ctx)?),
+            Pattern::MatchSingleton(PatternMatchSingleton { value, .. }) => {
+                Some(r_singleton(value)?)
+            }
+            Pattern::MatchAs(PatternMatchAs {
+                pattern: None,
+                name,
+                ..
+            }) => {
+                if let Some(name) = name {
+                    let pat = pat_ident(name, ctx.scopes.is_mutated(name));
+                    lookups.push(parse_quote!(let #pat = &#map[#key];));
+                }
+                None
+            }
+            _ => {
+                return Err(todo_link!(
+                    "only literals and names can be matched in mapping patterns"
+                ));
+            }
+        };
+        conditions.push(match value {
+            Some(v) => {
+                let lookup = method_call(map.clone(), "get", [key]);
+                parse_quote!(#lookup.is_some_and(|v| *v == #v))
+            }
+            None => method_call(map.clone(), "contains_key", [key]),
+        });
+    }
+
+    if let Some(guard) = guard {
+        let mut captured = vec![];
+        patterns
+            .iter()
+            .for_each(|p| pattern_names(p, &mut captured));
+        let mut uses = false;
+        visit_expr(guard, &mut |e| {
+            uses |= e
+                .as_name_expr()
+                .is_some_and(|n| captured.contains(&n.id.as_str()));
+        });
+        if uses {
+            return Err(todo_link!(
+                "the guard of a mapping pattern can't use the captured values"
+            ));
+        }
+        conditions.push(r_e(guard, ctx)?);
+    }
+
+    let guard = conditions
+        .into_iter()
+        .reduce(|a, b| binary(a, parse_quote!(&&), b));
+    lookups.extend(r_body(body, ctx)?);
+    Ok(arm(parse_quote!(_), guard, arm_body(lookups)))
+}
+
+fn r_p(node: &Pattern, sequence: Sequence, ctx: &mut Ctx) -> TResult<syn::Pat> {
+    r_p_inner(node, sequence, ctx).map_err(|e| e.at(pattern_kind(node), node.range()))
+}
+
+fn r_p_inner(node: &Pattern, sequence: Sequence, ctx: &mut Ctx) -> TResult<syn::Pat> {
+    match node {
+        Pattern::MatchValue(PatternMatchValue { range: _, value }) => match r_e(value, ctx)? {
+            syn::Expr::Lit(l) => Ok(syn::Pat::Lit(l)),
+            syn::Expr::Path(p) => Ok(syn::Pat::Path(p)),
+            // negative numbers
+            e @ syn::Expr::Unary(_) => Ok(parse_quote!(#e)),
+            _ => Err(todo_link!()),
+        },
+        Pattern::MatchSingleton(PatternMatchSingleton { value, .. }) => {
+            let v = r_singleton(value)?;
+            Ok(parse_quote!(#v))
+        }
+        Pattern::MatchAs(PatternMatchAs { pattern, name, .. }) => {
+            let Some(name) = name else {
+                return Ok(parse_quote!(_));
+            };
+            let binding = pat_ident(name, ctx.scopes.is_mutated(name));
+            let Some(pattern) = pattern else {
+                return Ok(binding);
+            };
+            let p = match r_p(pattern, sequence, ctx)? {
+                p @ syn::Pat::Or(_) => parse_quote!((#p)),
+                p => p,
+            };
+            Ok(parse_quote!(#binding @ #p))
+        }
+        Pattern::MatchOr(p) => Ok(syn::Pat::Or(syn::PatOr {
+            attrs: vec![],
+            leading_vert: None,
+            cases: p
+                .patterns
+                .iter()
+                .map(|p| r_p(p, sequence, ctx))
+                .collect::<TResult<_>>()?,
+        })),
+        Pattern::MatchSequence(p) => {
+            let elems = p
+                .patterns
+                .iter()
+                .map(|p| match p {
+                    Pattern::MatchStar(PatternMatchStar { name, .. }) => {
+                        r_star(name.as_deref(), sequence, ctx)
+                    }
+                    p => r_p(p, Sequence::Tuple, ctx),
+                })
+                .collect::<TResult<Vec<_>>>()?;
+            Ok(match sequence {
+                Sequence::Slice => parse_quote!([#(#elems),*]),
+                Sequence::Values => parse_quote!(&[#(#elems),*]),
+                Sequence::Tuple => pat_tuple(elems),
+            })
+        }
+        Pattern::MatchClass(p) => r_class(p, ctx),
+        Pattern::MatchMapping(_) => Err(todo_link!(
+            "mapping patterns can only be matched at the top level of a `case`"
+        )),
+        Pattern::MatchStar(_) => Err(todo_link!()),
+    }
+}
+
+/// `*rest` of a sequence pattern
+fn r_star(name: Option<&str>, sequence: Sequence, ctx: &Ctx) -> TResult<syn::Pat> {
+    match (name, sequence) {
+        (None, _) => Ok(parse_quote!(..)),
+        (Some(name), Sequence::Slice) => {
+            let binding = pat_ident(name, ctx.scopes.is_mutated(name));
+            Ok(parse_quote!(#binding @ ..))
+        }
+        (Some(name), Sequence::Values) => {
+            let name = ident(name);
+            Ok(parse_quote!(ref #name @ ..))
+        }
+        (Some(_), Sequence::Tuple) => Err(todo_link!(
+            "the rest of a tuple can't be bound to a name in rust"
+        )),
+    }
+}
+
+/// `None`, `True` or `False`
+fn r_singleton(value: &Constant) -> TResult<syn::Expr> {
+    match value {
+        Constant::None => Ok(parse_quote!(None)),
+        Constant::Bool(true) => Ok(parse_quote!(true)),
+        Constant::Bool(false) => Ok(parse_quote!(false)),
+        _ => Err(todo_link!()),
+    }
+}
+
+/// `Point(0, y=y)` -> `Point { x: 0, y, .. }`, positional patterns match the fields in the
+/// order of their definition
+fn r_class(node: &PatternMatchClass, ctx: &mut Ctx) -> TResult<syn::Pat> {
+    let PatternMatchClass {
+        cls,
+        patterns,
+        kwd_attrs,
+        kwd_patterns,
+        ..
+    } = node;
+    let Expr::Name(ExprName { id: class, .. }) = cls.as_ref() else {
+        return Err(todo_link!());
+    };
+    // `case Color():` matches every variant
+    if ctx.has_enum(class) && patterns.is_empty() && kwd_patterns.is_empty() {
+        return Ok(parse_quote!(_));
+    }
+    let fields = match ctx.get_class_members(class) {
+        Some(members) => members.to_vec(),
+        None if ctx.has_constructor(class) => ctx
+            .types
+            .classes
+            .get(class.as_str())
+            .map(|f| f.iter().map(|(name, _)| name.clone()).collect())
+            .unwrap_or_default(),
+        None => {
+            return Err(todo_link!(
+                "only the classes of the module can be matched, builtin types are not supported"
+            ));
+        }
+    };
+    if patterns.len() > fields.len() {
+        return Err(todo_link!());
+    }
+
+    let mut field_pats = vec![];
+    let named = fields
+        .iter()
+        .map(String::as_str)
+        .zip(patterns)
+        .chain(kwd_attrs.iter().map(|a| a.as_str()).zip(kwd_patterns));
+    for (field, pattern) in named {
+        let p = r_p(pattern, Sequence::Tuple, ctx)?;
+        let field = ident(field);
+        field_pats.push(match &p {
+            syn::Pat::Ident(i) if i.ident == field && i.subpat.is_none() => quote::quote!(#p),
+            _ => quote::quote!(#field: #p),
+        });
+    }
+    if field_pats.len() < fields.len() {
+        field_pats.push(quote::quote!(..));
+    }
+    let class = ident(class);
+    Ok(parse_quote!(#class { #(#field_pats),* }))
+}
+
+/// the top level pattern is a sequence
+fn is_sequence(pattern: &Pattern) -> bool {
+    match pattern {
+        Pattern::MatchSequence(_) => true,
+        Pattern::MatchAs(PatternMatchAs {
+            pattern: Some(p), ..
+        }) => is_sequence(p),
+        Pattern::MatchOr(p) => p.patterns.iter().any(is_sequence),
+        _ => false,
+    }
+}
+
+/// rust would reject the match without a `_` arm, or warn about an unreachable one
+fn exhaustive(cases: &[MatchCase], sequence: Sequence, ctx: &Ctx) -> bool {
+    let patterns = cases
+        .iter()
+        .filter(|c| c.guard.is_none())
+        .map(|c| &c.pattern)
+        .collect::<Vec<_>>();
+    if patterns.iter().any(|p| irrefutable(p, sequence, ctx)) {
+        return true;
+    }
+
+    let mut alternatives = vec![];
+    for p in &patterns {
+        flatten(p, &mut alternatives);
+    }
+
+    // every variant of an enum
+    let variants = alternatives
+        .iter()
+        .filter_map(|p| match p {
+            Pattern::MatchValue(PatternMatchValue { value, .. }) => match value.as_ref() {
+                Expr::Attribute(ExprAttribute { value, attr, .. }) => {
+                    Some((value.as_name_expr()?.id.as_str(), attr.as_str()))
+                }
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if ctx.variants.iter().any(|(e, vs)| {
+        vs.iter()
+            .all(|v| variants.contains(&(e.as_str(), v.as_str())))
+    }) {
+        return true;
+    }
+
+    // `True` and `False`
+    let bools = [true, false].map(|b| {
+        alternatives.iter().any(|p| {
+            matches!(p, Pattern::MatchSingleton(PatternMatchSingleton { value: Constant::Bool(v), .. }) if *v == b)
+        })
+    });
+    if bools == [true, true] {
+        return true;
+    }
+
+    // slices of every length, e.g. `[]`, `[x]` and `[x, y, *rest]`
+    if sequence != Sequence::Tuple {
+        let mut lengths = vec![];
+        let mut min_rest = None;
+        for p in &alternatives {
+            let Pattern::MatchSequence(s) = p else {
+                continue;
+            };
+            let elems = s
+                .patterns
+                .iter()
+                .filter(|p| !matches!(p, Pattern::MatchStar(_)))
+                .collect::<Vec<_>>();
+            if !elems.iter().all(|p| irrefutable(p, Sequence::Tuple, ctx)) {
+                continue;
+            }
+            if elems.len() == s.patterns.len() {
+                lengths.push(elems.len());
+            } else {
+                min_rest = Some(min_rest.map_or(elems.len(), |m: usize| m.min(elems.len())));
+            }
+        }
+        if let Some(min) = min_rest
+            && (0..min).all(|l| lengths.contains(&l))
+        {
+            return true;
+        }
+    }
+    false
+}
+
+/// the alternatives of or-patterns
+fn flatten<'a>(pattern: &'a Pattern, alternatives: &mut Vec<&'a Pattern>) {
+    match pattern {
+        Pattern::MatchOr(p) => p.patterns.iter().for_each(|p| flatten(p, alternatives)),
+        Pattern::MatchAs(PatternMatchAs {
+            pattern: Some(p), ..
+        }) => flatten(p, alternatives),
+        p => alternatives.push(p),
+    }
+}
+
+/// the pattern matches every value of the type of the subject
+fn irrefutable(pattern: &Pattern, sequence: Sequence, ctx: &Ctx) -> bool {
+    match pattern {
+        Pattern::MatchAs(PatternMatchAs { pattern: None, .. }) => true,
+        Pattern::MatchAs(PatternMatchAs {
+            pattern: Some(p), ..
+        }) => irrefutable(p, sequence, ctx),
+        Pattern::MatchOr(p) => p.patterns.iter().any(|p| irrefutable(p, sequence, ctx)),
+        Pattern::MatchSequence(p) if sequence == Sequence::Tuple => p
+            .patterns
+            .iter()
+            .all(|p| matches!(p, Pattern::MatchStar(_)) || irrefutable(p, sequence, ctx)),
+        Pattern::MatchClass(p) => {
+            let Some(class) = p.cls.as_name_expr() else {
+                return false;
+            };
+            if ctx.has_enum(&class.id) {
+                return p.patterns.is_empty() && p.kwd_patterns.is_empty();
+            }
+            p.patterns
+                .iter()
+                .chain(&p.kwd_patterns)
+                .all(|p| irrefutable(p, Sequence::Tuple, ctx))
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,562 @@
+//! which names are bound in the current rust block and which of them need `mut`
+
+use crate::params::names;
+use rustpython_parser::ast::{
+    Expr, ExprAttribute, ExprCall, ExprList, ExprName, ExprStarred, ExprSubscript, ExprTuple, Stmt,
+    StmtAnnAssign, StmtAssign, StmtAsyncFor, StmtAsyncWith, StmtAugAssign, StmtDelete, StmtFor,
+    StmtGlobal, StmtIf, StmtNonlocal, StmtTry, StmtWhile, StmtWith,
+};
+use rustpython_parser::ast::{Pattern, StmtFunctionDef, StmtReturn};
+use std::collections::{HashMap, HashSet};
+use syn::parse_quote;
+
+/// methods of builtin types which change the object they are called on
+const MUTATING_METHODS: &[&str] = &[
+    "append",
+    "extend",
+    "insert",
+    "pop",
+    "popitem",
+    "remove",
+    "clear",
+    "sort",
+    "reverse",
+    "update",
+    "add",
+    "discard",
+    "write",
+    "writelines",
+    "setdefault",
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct Scopes {
+    /// names bound in the nested rust blocks, innermost last
+    blocks: Vec<HashSet<String>>,
+    /// names of the current function which are reassigned or changed in place
+    mutated: HashSet<String>,
+    /// fields of `self` which are local variables, while `__init__` is transpiled into `new`
+    fields: HashSet<String>,
+    /// methods of classes which mutate `self`
+    methods: HashSet<String>,
+    /// names which are bound anywhere in the function, they hide the globals of the module
+    locals: HashSet<String>,
+    /// names of the `global` statements of the function
+    globals: HashSet<String>,
+    /// fields of `self` which are declared at the top of `new` and not yet assigned
+    uninit: HashSet<String>,
+}
+
+impl Scopes {
+    /// scopes of a function (or module) body in which `params` are bound
+    ///
+    /// calls of `methods` (or builtin methods like `append`) mutate the object
+    pub fn function<'a>(
+        params: impl IntoIterator<Item = &'a str>,
+        methods: &HashSet<String>,
+        body: &[Stmt],
+    ) -> Scopes {
+        Scopes::constructor(params, &[], methods, body)
+    }
+
+    /// scopes of `__init__` in which the `fields` of `self` are local variables
+    pub fn constructor<'a>(
+        params: impl IntoIterator<Item = &'a str>,
+        fields: &[String],
+        methods: &HashSet<String>,
+        body: &[Stmt],
+    ) -> Scopes {
+        let mut params = params
+            .into_iter()
+            .map(str::to_string)
+            .collect::<HashSet<_>>();
+        // `nonlocal` names are bound by the enclosing function
+        params.extend(declared_names(body, false));
+        let nested = nested_fields(fields, body);
+        params.extend(nested.iter().cloned());
+
+        let mut analysis = Scopes {
+            blocks: vec![params.clone()],
+            mutated: HashSet::new(),
+            fields: fields.iter().cloned().collect(),
+            methods: methods.clone(),
+            locals: params.clone(),
+            globals: declared_names(body, true).collect(),
+            uninit: nested.into_iter().collect(),
+        };
+        analysis.stmts(body);
+
+        Scopes {
+            blocks: vec![params],
+            mutated: analysis.mutated,
+            fields: HashSet::new(),
+            methods: HashSet::new(),
+            locals: analysis.locals,
+            globals: analysis.globals,
+            uninit: HashSet::new(),
+        }
+    }
+
+    /// `names` refer to the globals of the module, e.g. in the module itself
+    pub fn declare_globals<'a>(&mut self, names: impl IntoIterator<Item = &'a String>) {
+        self.globals.extend(names.into_iter().cloned());
+    }
+
+    /// `name` is not a local variable of the function, it refers to a global of the module
+    pub fn is_global(&self, name: &str) -> bool {
+        self.globals.contains(name) || !self.locals.contains(name)
+    }
+
+    pub fn push(&mut self) {
+        self.blocks.push(HashSet::new());
+    }
+
+    pub fn pop(&mut self) {
+        self.blocks.pop();
+    }
+
+    pub fn is_declared(&self, name: &str) -> bool {
+        self.blocks.iter().any(|b| b.contains(name))
+    }
+
+    pub fn is_mutated(&self, name: &str) -> bool {
+        self.mutated.contains(name)
+    }
+
+    /// binds all names of the assignment target `target` in the innermost block
+    pub fn declare(&mut self, target: &Expr) {
+        let mut names = vec![];
+        target_names(target, &mut names);
+        self.locals.extend(names.iter().map(|n| n.to_string()));
+        if let Some(block) = self.blocks.last_mut() {
+            block.extend(names.into_iter().map(str::to_string));
+        }
+    }
+
+    /// binds the names which are captured by the pattern of a `case` in the innermost block
+    pub fn declare_pattern(&mut self, pattern: &Pattern) {
+        let mut names = vec![];
+        pattern_names(pattern, &mut names);
+        self.locals.extend(names.iter().map(|n| n.to_string()));
+        if let Some(block) = self.blocks.last_mut() {
+            block.extend(names.into_iter().map(str::to_string));
+        }
+    }
+
+    /// true if all names of `target` are already bound, so it can be assigned to
+    pub fn is_assignable(&self, target: &Expr) -> bool {
+        let mut names = vec![];
+        target_names(target, &mut names);
+        !names.is_empty() && names.iter().all(|n| self.is_declared(n))
+    }
+
+    /// simulates the bindings of the transpiled code to find the reassigned names
+    fn stmts(&mut self, body: &[Stmt]) {
+        for s in body {
+            self.stmt(s);
+        }
+    }
+
+    fn block(&mut self, target: Option<&Expr>, body: &[Stmt]) {
+        self.push();
+        if let Some(target) = target {
+            self.declare(target);
+        }
+        self.stmts(body);
+        self.pop();
+    }
+
+    fn stmt(&mut self, s: &Stmt) {
+        match s {
+            Stmt::Assign(StmtAssign { targets, value, .. }) => {
+                self.expr(value);
+                for t in targets {
+                    // `self.x = x` in `new` is just `Self { x }`
+                    if let Some(field) = self.field(t)
+                        && value.as_name_expr().is_some_and(|n| n.id.as_str() == field)
+                    {
+                        continue;
+                    }
+                    self.bind(t);
+                }
+            }
+            Stmt::AnnAssign(StmtAnnAssign { target, value, .. }) => {
+                if let Some(v) = value {
+                    self.expr(v);
+                }
+                self.bind(target);
+            }
+            Stmt::AugAssign(StmtAugAssign { target, value, .. }) => {
+                self.expr(value);
+                self.mutate(target);
+            }
+            Stmt::Delete(StmtDelete { targets, .. }) => {
+                for t in targets {
+                    if !matches!(t, Expr::Name(_)) {
+                        self.mutate(t);
+                    }
+                }
+            }
+            Stmt::Expr(e) => self.expr(&e.value),
+            Stmt::Return(r) => {
+                if let Some(v) = &r.value {
+                    self.expr(v);
+                }
+            }
+            Stmt::For(StmtFor {
+                target,
+                iter,
+                body,
+                orelse,
+                ..
+            })
+            | Stmt::AsyncFor(StmtAsyncFor {
+                target,
+                iter,
+                body,
+                orelse,
+                ..
+            }) => {
+                self.expr(iter);
+                self.block(Some(target), body);
+                self.block(None, orelse);
+            }
+            Stmt::While(StmtWhile {
+                test, body, orelse, ..
+            }) => {
+                self.expr(test);
+                self.block(None, body);
+                self.block(None, orelse);
+            }
+            Stmt::If(StmtIf {
+                test, body, orelse, ..
+            }) => {
+                self.expr(test);
+                // each branch assigns the declared fields for the first time
+                let uninit = self.uninit.clone();
+                self.block(None, body);
+                let after_body = std::mem::replace(&mut self.uninit, uninit);
+                self.block(None, orelse);
+                self.uninit.extend(after_body);
+            }
+            Stmt::With(StmtWith { items, body, .. })
+            | Stmt::AsyncWith(StmtAsyncWith { items, body, .. }) => {
+                self.push();
+                for item in items {
+                    self.expr(&item.context_expr);
+                    if let Some(vars) = &item.optional_vars {
+                        self.declare(vars);
+                    }
+                }
+                self.block(None, body);
+                self.pop();
+            }
+            Stmt::Try(StmtTry {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+                ..
+            }) => {
+                self.block(None, body);
+                for h in handlers {
+                    self.block(None, &h.as_except_handler().unwrap().body);
+                }
+                self.block(None, orelse);
+                self.block(None, finalbody);
+            }
+            Stmt::Match(m) => {
+                self.expr(&m.subject);
+                for case in &m.cases {
+                    self.push();
+                    self.declare_pattern(&case.pattern);
+                    if let Some(guard) = &case.guard {
+                        self.expr(guard);
+                    }
+                    self.block(None, &case.body);
+                    self.pop();
+                }
+            }
+            // a closure which assigns the variables of the function
+            Stmt::FunctionDef(def) => {
+                let params = names(&def.args);
+                let inner = Scopes::function(params, &self.methods, &def.body);
+                self.mutated
+                    .extend(declared_names(&def.body, false).filter(|n| inner.is_mutated(n)));
+            }
+            // nested classes have their own scopes
+            _ => {}
+        }
+    }
+
+    /// `self.field` while `__init__` is transpiled
+    fn field<'a>(&self, e: &'a Expr) -> Option<&'a str> {
+        match e {
+            Expr::Attribute(ExprAttribute { value, attr, .. })
+                if value
+                    .as_name_expr()
+                    .is_some_and(|n| n.id.as_str() == "self")
+                    && self.fields.contains(attr.as_str()) =>
+            {
+                Some(attr.as_str())
+            }
+            _ => None,
+        }
+    }
+
+    /// the variable of `a.b[c].d`
+    fn root<'a>(&self, e: &'a Expr) -> Option<&'a str> {
+        if let Some(field) = self.field(e) {
+            return Some(field);
+        }
+        match e {
+            Expr::Name(ExprName { id, .. }) => Some(id.as_str()),
+            Expr::Attribute(ExprAttribute { value, .. })
+            | Expr::Subscript(ExprSubscript { value, .. }) => self.root(value),
+            _ => None,
+        }
+    }
+
+    /// assignment to `target`
+    fn bind(&mut self, target: &Expr) {
+        if let Some(field) = self.field(target) {
+            if self.is_declared(field) && !self.uninit.remove(field) {
+                self.mutated.insert(field.to_string());
+            }
+            if let Some(block) = self.blocks.last_mut() {
+                block.insert(field.to_string());
+            }
+            return;
+        }
+        match target {
+            // assigns the static of the module
+            Expr::Name(ExprName { id, .. }) if self.globals.contains(id.as_str()) => {}
+            Expr::Name(ExprName { id, .. }) => {
+                if self.is_declared(id) {
+                    self.mutated.insert(id.to_string());
+                }
+                self.declare(target);
+            }
+            // e.g. `a, b = b, a` or `xs[i], xs[j] = xs[j], xs[i]`
+            Expr::Tuple(ExprTuple { elts, .. }) | Expr::List(ExprList { elts, .. }) => {
+                elts.iter().for_each(|e| self.bind(e));
+            }
+            Expr::Starred(ExprStarred { value, .. }) => self.bind(value),
+            // e.g. `a[0] = 1` or `self.a = 1`
+            _ => self.mutate(target),
+        }
+    }
+
+    /// `e` (or the variable it belongs to) is changed in place
+    fn mutate(&mut self, e: &Expr) {
+        if let Some(name) = self.root(e) {
+            self.mutated.insert(name.to_string());
+        }
+    }
+
+    /// finds calls of mutating methods in `e`
+    fn expr(&mut self, e: &Expr) {
+        let mut mutated = vec![];
+        crate::infer::visit_expr(e, &mut |e| {
+            if let Expr::Call(ExprCall { func, .. }) = e
+                && let Expr::Attribute(ExprAttribute { value, attr, .. }) = func.as_ref()
+                && (MUTATING_METHODS.contains(&attr.as_str())
+                    || self.methods.contains(attr.as_str()))
+                && let Some(name) = self.root(value)
+            {
+                mutated.push(name.to_string());
+            }
+        });
+        self.mutated.extend(mutated);
+    }
+}
+
+/// fields of `self` which `__init__` assigns, but not at the top level of its body, they are
+/// declared at the top of `new` so that `Self { .. }` can use them
+pub fn nested_fields(fields: &[String], body: &[Stmt]) -> Vec<String> {
+    fields
+        .iter()
+        .filter(|f| {
+            let mut assigned = false;
+            crate::infer::visit_stmts(body, &mut |s| assigned |= assigns(s, f));
+            assigned && !body.iter().any(|s| assigns(s, f))
+        })
+        .cloned()
+        .collect()
+}
+
+/// `self.field` is assigned on every path through `body` which doesn't raise
+pub fn always_assigns(body: &[Stmt], field: &str) -> bool {
+    body.iter().any(|s| match s {
+        Stmt::If(StmtIf { body, orelse, .. }) => {
+            always_assigns(body, field) && always_assigns(orelse, field)
+        }
+        Stmt::With(StmtWith { body, .. }) => always_assigns(body, field),
+        Stmt::Raise(_) => true,
+        s => assigns(s, field),
+    })
+}
+
+/// `s` assigns `self.field`
+fn assigns(s: &Stmt, field: &str) -> bool {
+    let is_field = |t: &Expr| {
+        matches!(t, Expr::Attribute(ExprAttribute { value, attr, .. })
+            if attr.as_str() == field
+                && value.as_name_expr().is_some_and(|n| n.id.as_str() == "self"))
+    };
+    match s {
+        Stmt::Assign(StmtAssign { targets, .. }) => targets.iter().any(is_field),
+        Stmt::AnnAssign(StmtAnnAssign {
+            target,
+            value: Some(_),
+            ..
+        }) => is_field(target),
+        _ => false,
+    }
+}
+
+/// names of the `global` (or `nonlocal`) statements of a function body
+pub(crate) fn declared_names(body: &[Stmt], global: bool) -> impl Iterator<Item = String> {
+    let mut names = vec![];
+    crate::infer::visit_stmts(body, &mut |s| match s {
+        Stmt::Global(StmtGlobal { names: n, .. }) if global => {
+            names.extend(n.iter().map(|n| n.to_string()));
+        }
+        Stmt::Nonlocal(StmtNonlocal { names: n, .. }) if !global => {
+            names.extend(n.iter().map(|n| n.to_string()));
+        }
+        _ => {}
+    });
+    names.into_iter()
+}
+
+/// how a method takes `self`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Receiver {
+    /// fields are assigned or changed in place
+    pub mutates: bool,
+    /// `self` or one of its owned fields is returned
+    pub consumes: bool,
+}
+
+impl Receiver {
+    pub fn to_syn(self) -> syn::FnArg {
+        match self {
+            Receiver {
+                consumes: true,
+                mutates: true,
+            } => parse_quote!(mut self),
+            Receiver { consumes: true, .. } => parse_quote!(self),
+            Receiver { mutates: true, .. } => parse_quote!(&mut self),
+            _ => parse_quote!(&self),
+        }
+    }
+}
+
+/// receivers of the methods of a class
+///
+/// `owned` tells if a field is not `Copy`, calls of other methods on `self` are followed
+pub fn receivers(
+    defs: &[&StmtFunctionDef],
+    owned: impl Fn(&str) -> bool,
+) -> HashMap<String, Receiver> {
+    let mut receivers = HashMap::new();
+    let mut calls = HashMap::new();
+
+    for def in defs {
+        let params = names(&def.args);
+        let mutates = Scopes::function(params, &HashSet::new(), &def.body).is_mutated("self");
+
+        let mut consumes = false;
+        crate::infer::visit_stmts(&def.body, &mut |s| {
+            if let Stmt::Return(StmtReturn { value: Some(v), .. }) = s {
+                consumes |= match v.as_ref() {
+                    Expr::Name(n) => n.id.as_str() == "self",
+                    Expr::Attribute(ExprAttribute { value, attr, .. }) => {
+                        value
+                            .as_name_expr()
+                            .is_some_and(|n| n.id.as_str() == "self")
+                            && owned(attr.as_str())
+                    }
+                    _ => false,
+                };
+            }
+        });
+
+        let mut called = vec![];
+        crate::infer::visit_exprs(&def.body, &mut |e| {
+            if let Expr::Call(ExprCall { func, .. }) = e
+                && let Expr::Attribute(ExprAttribute { value, attr, .. }) = func.as_ref()
+                && value
+                    .as_name_expr()
+                    .is_some_and(|n| n.id.as_str() == "self")
+            {
+                called.push(attr.to_string());
+            }
+        });
+
+        receivers.insert(def.name.to_string(), Receiver { mutates, consumes });
+        calls.insert(def.name.to_string(), called);
+    }
+
+    // a method which calls a mutating method on `self` mutates as well
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (name, called) in &calls {
+            let mut r = receivers[name];
+            for c in called {
+                if let Some(other) = receivers.get(c) {
+                    r.mutates |= other.mutates;
+                    r.consumes |= other.consumes;
+                }
+            }
+            if r != receivers[name] {
+                receivers.insert(name.clone(), r);
+                changed = true;
+            }
+        }
+    }
+
+    receivers
+}
+
+/// names which are bound by the assignment target `target`
+pub(crate) fn target_names<'a>(target: &'a Expr, names: &mut Vec<&'a str>) {
+    match target {
+        Expr::Name(ExprName { id, .. }) => names.push(id.as_str()),
+        Expr::Tuple(t) => t.elts.iter().for_each(|e| target_names(e, names)),
+        Expr::List(l) => l.elts.iter().for_each(|e| target_names(e, names)),
+        Expr::Starred(ExprStarred { value, .. }) => target_names(value, names),
+        _ => {}
+    }
+}
+
+/// names which are captured by a pattern of `match`
+pub(crate) fn pattern_names<'a>(pattern: &'a Pattern, names: &mut Vec<&'a str>) {
+    match pattern {
+        Pattern::MatchAs(p) => {
+            if let Some(p) = &p.pattern {
+                pattern_names(p, names);
+            }
+            names.extend(p.name.as_ref().map(|n| n.as_str()));
+        }
+        Pattern::MatchStar(p) => names.extend(p.name.as_ref().map(|n| n.as_str())),
+        Pattern::MatchSequence(p) => p.patterns.iter().for_each(|p| pattern_names(p, names)),
+        Pattern::MatchOr(p) => {
+            // every alternative binds the same names
+            if let Some(p) = p.patterns.first() {
+                pattern_names(p, names);
+            }
+        }
+        Pattern::MatchMapping(p) => {
+            p.patterns.iter().for_each(|p| pattern_names(p, names));
+            names.extend(p.rest.as_ref().map(|n| n.as_str()));
+        }
+        Pattern::MatchClass(p) => {
+            p.patterns.iter().for_each(|p| pattern_names(p, names));
+            p.kwd_patterns.iter().for_each(|p| pattern_names(p, names));
+        }
+        Pattern::MatchValue(_) | Pattern::MatchSingleton(_) => {}
+    }
+}
@@ -10,7 +10,7 @@ fn simple() {
     let actual = test_p2r(code);
     let expected = expect![[r#"
         fn main() {
-            let mut a = 0;
+            let a: isize = 0;
         }
     "#]];
     expected.assert_eq(&actual.to_string())
@@ -27,12 +27,12 @@ fn comprehensions() {
     let actual = test_p2r(code);
     let expected = expect![[r#"
         fn main() {
-            let mut arr = (5..10).into_iter().map(|x| { x }).collect::<Vec<_>>();
-            let mut set_res = res
+            let arr = (5..10).into_iter().map(|x| { x }).collect::<Vec<_>>();
+            let set_res = res
                 .into_iter()
                 .filter_map(|x| { if arr.into_iter().any(|v| v == x) { Some(x) } else { None } })
                 .collect::<HashSet<_, _>>();
-            let mut dict_res = arr
+            let dict_res = arr
                 .iter()
                 .zip(arr.iter())
                 .into_iter()
@@ -76,16 +76,16 @@ fn basic_for_and_while() {
     let actual = test_p2r(code);
     let expected = expect![[r#"
         fn main() {
-            let mut a = 4;
-            let mut x = 0;
-            for i in (0..a) {
+            let mut a: isize = 4;
+            let mut x: isize = 0;
+            for i in 0..a {
                 x += 1;
             }
-            let mut res = vec![];
+            let mut res: Vec<isize> = vec![];
             while x > 0 {
                 x -= 1;
-                let mut a = x.powf(2);
-                res.push(a)
+                a = x.powf(2);
+                res.push(a);
             }
         }
     "#]];
@@ -105,12 +105,73 @@ fn while_else() {
     let actual = test_p2r(code);
     let expected = expect![[r#"
         fn main() {
-            let mut res = vec![];
+            let res = vec![];
             while x > 0 {
                 x -= 1;
             }
-            if !(x > 0) {
-                println!("{:?}", "done");
+            println!("{:?}", "done");
+        }
+    "#]];
+    expected.assert_eq(&actual.to_string())
+}
+
+#[test]
+fn for_else() {
+    let code = indoc! {"
+        def find(xs: List[int], target: int):
+            for x in xs:
+                if x == target:
+                    print(\"found\")
+                    break
+            else:
+                print(\"missing\")
+
+        def wait(n: int):
+            while n > 0:
+                for x in range(n):
+                    if x > 3:
+                        break
+                else:
+                    n -= 1
+                    continue
+                break
+            else:
+                print(\"done\")
+        "};
+
+    let actual = test_p2r(code);
+    let expected = expect![[r#"
+        fn main() {
+            fn find(xs: Vec<isize>, target: isize) -> () {
+                'search: {
+                    for x in xs.iter().copied() {
+                        if x == target {
+                            println!("{:?}", "found");
+                            break 'search;
+                        }
+                    }
+                    println!("{:?}", "missing");
+                }
+            }
+            fn wait(mut n: isize) -> () {
+                'search: {
+                    while n > 0 {
+                        let broke = 'search2: {
+                            for x in 0..n {
+                                if x > 3 {
+                                    break 'search2 true;
+                                }
+                            }
+                            false
+                        };
+                        if !broke {
+                            n -= 1;
+                            continue;
+                        }
+                        break 'search;
+                    }
+                    println!("{:?}", "done");
+                }
             }
         }
     "#]];
@@ -136,7 +197,7 @@ fn basic_class() {
     // TOOD fix this should be in impl
     let expected = expect![[r#"
         fn main() {
-            let mut foo_i = Foo();
+            let foo_i = Foo();
             #[derive(Debug, Clone)]
             struct Foo {
                 a: isize,
@@ -147,8 +208,8 @@ fn basic_class() {
                     return 2 * self.a.powf(other);
                 }
             }
-            let mut foo_arg = Foo { a: 1, b: 2 * 2 };
-            let mut foo_kw = Foo { b: 4 - 1, a: 1 };
+            let foo_arg = Foo { a: 1, b: 2 * 2 };
+            let foo_kw = Foo { b: 4 - 1, a: 1 };
         }
     "#]];
     expected.assert_eq(&actual.to_string())
@@ -179,15 +240,17 @@ fn basic_enum() {
     let expected = expect![[r#"
         fn main() {
             fn EnumOrFun() -> () {
-                todo!()
+                todo!();
             }
-            let mut foo_i = EnumOrFun();
+            // EnumOrFun is a function
+            let foo_i = EnumOrFun();
             #[derive(Debug, Clone)]
             enum EnumOrFun {
                 A = 1,
                 B = 2,
             }
-            let mut a_inst = EnumOrFun::A;
+            // EnumOrFun is a class
+            let a_inst = EnumOrFun::A;
             match a_inst {
                 EnumOrFun::A => println!("{:?}", "got an A"),
                 EnumOrFun::B => println!("{:?}", "got a B"),
@@ -214,12 +277,84 @@ fn tuple_fn() {
         fn main() {
             fn t(x: isize) -> Option<(isize, isize)> {
                 if x < 3 {
-                    return ((3 * x, 42)).into();
+                    return (3 * x, 42).into();
                 } else {
-                    return (None).into();
+                    return None.into();
                 }
             }
-            let (mut a, mut b) = t(1);
+            let (a, b) = t(1);
+        }
+    "#]];
+    expected.assert_eq(&actual.to_string())
+}
+
+#[test]
+fn unpacking() {
+    let code = indoc! {"
+        def add(a: int, b: int, c: int) -> int:
+            return a + b + c
+
+        def unpack(items: List[int], names: List[str]):
+            x = y = 1
+            x, y = y + 1, x
+            first, *rest = items
+            *init, last = names
+            a, *middle, b = 1, 2, 3, 4
+            n, x = first, x + a
+            items[0], items[1] = items[1], items[0]
+            both = [*items, 0, *rest]
+            print(add(first, *rest))
+            print(add(*middle, b))
+            print(both)
+            print(init)
+            print(last)
+            print(n + x + y)
+
+        def merge(a: Dict[int, int], b: Dict[int, int]) -> Dict[int, int]:
+            return {**a, **b, 0: 3}
+        "};
+
+    let actual = test_p2r(code);
+    let expected = expect![[r#"
+        fn main() {
+            fn add(a: isize, b: isize, c: isize) -> isize {
+                return a + b + c;
+            }
+            fn unpack(mut items: Vec<isize>, names: Vec<String>) -> () {
+                let mut x: isize = 1;
+                let mut y = x;
+                (x, y) = (y + 1, x);
+                let (first, rest) = match items.as_slice() {
+                    &[first, ref rest @ ..] => (first, rest.to_vec()),
+                    _ => panic!("not enough values to unpack"),
+                };
+                let (init, last) = match names.as_slice() {
+                    [init @ .., last] => (init.to_vec(), last.clone()),
+                    _ => panic!("not enough values to unpack"),
+                };
+                let (a, middle, b) = (1, vec![2, 3], 4);
+                let n;
+                (n, x) = (first, x + a);
+                items.swap(0, 1);
+                let both = [&items[..], &[0][..], &rest[..]].concat();
+                println!("{:?}", add(first, rest[0], rest[1]));
+                println!("{:?}", add(middle[0], middle[1], b));
+                println!("{:?}", both);
+                println!("{:?}", init);
+                println!("{:?}", last);
+                println!("{:?}", n + x + y);
+            }
+            fn merge(
+                a: std::collections::HashMap<isize, isize>,
+                b: std::collections::HashMap<isize, isize>,
+            ) -> std::collections::HashMap<isize, isize> {
+                return a
+                    .clone()
+                    .into_iter()
+                    .chain(b.clone())
+                    .chain([(0, 3)])
+                    .collect::<HashMap<_, _>>();
+            }
         }
     "#]];
     expected.assert_eq(&actual.to_string())
@@ -239,12 +374,12 @@ fn lists_sets() {
     let actual = test_p2r(code);
     let expected = expect![[r#"
         fn main() {
-            let mut a = vec![];
-            let mut b = vec![1, 2, 3];
-            let mut is_in = b.into_iter().any(|v| v == 42);
-            let mut c = HashMap::new();
-            let mut d = [1, 2, 3].into_iter().collect::<HashSet<_>>();
-            let mut e = ["a", "b", "c"]
+            let a = vec![];
+            let b = vec![1, 2, 3];
+            let is_in = b.into_iter().any(|v| v == 42);
+            let c = HashMap::new();
+            let d = [1, 2, 3].into_iter().collect::<HashSet<_>>();
+            let e = ["a", "b", "c"]
                 .into_iter()
                 .zip([1, 2, 3].into_iter())
                 .collect::<HashMap<_, _>>();
@@ -265,9 +400,9 @@ fn lambda() {
     let actual = test_p2r(code);
     let expected = expect![[r#"
         fn main() {
-            let mut times_two = |x| { x * 2 };
-            let mut twice = times_two(3);
-            let mut add = |x, y| { x + y };
+            let times_two = |x| { x * 2 };
+            let twice = times_two(3);
+            let add = |x, y| { x + y };
             add(3, 4);
         }
     "#]];
@@ -295,10 +430,10 @@ fn else_if() {
     // the elif branches could be collpsed, but clippy can fix this automatically
     let expected = expect![[r#"
         fn main() {
-            let mut DEBUG = true;
-            let mut res = vec![];
+            let DEBUG = true;
+            let res = vec![];
             if DEBUG && res.len() > 2 {
-                let mut a = 42;
+                let a: isize = 42;
                 println!("{:?}", 1);
             } else {
                 if DEBUG {
@@ -309,8 +444,8 @@ fn else_if() {
                     } else {
                         println!("{:?}", "inside else...");
                         println!("{:?}", 4);
-                    };
-                };
+                    }
+                }
             }
         }
     "#]];
@@ -354,8 +489,8 @@ fn math_polyfill() {
     // compiles
     let expected = expect![[r#"
         fn main() {
-            let mut arr = vec![1.1, 2.1, 3.1];
-            let mut total = prelude::pow(
+            let arr = vec![1.1, 2.1, 3.1];
+            let total = prelude::pow(
                 prelude::sqrt(prelude::cos(prelude::sin(arr.iter().sum()))),
                 32.1,
             );
@@ -414,8 +549,10 @@ fn slicing() {
     // TOOD fix range/ tuple logic here
     let expected = expect![[r#"
         fn main() {
-            let mut res2 = vec![1, 2, 3, 4];
-            println!("{:?}", res2[..3], res2[1..2.iter().step_by(30).collect::< Vec < _ >> ()]);
+            let res2 = vec![1, 2, 3, 4];
+            println!(
+                "{:?}", res2[..3], res2[1..2].iter().step_by(30).collect:: < Vec < _ > > ()
+            );
         }
     "#]];
     expected.assert_eq(&actual.to_string())
@@ -431,8 +568,8 @@ fn json() {
     let actual = test_p2r(code);
     let expected = expect![[r#"
         fn main() {
-            let mut foo_s = serde_json::to_string(Foo()).unwrap();
-            let mut foo_instance = serde_json::from_string(foo_s).unwrap();
+            let foo_s = serde_json::to_string(Foo()).unwrap();
+            let foo_instance = serde_json::from_string(foo_s).unwrap();
         }
     "#]];
     expected.assert_eq(&actual.to_string())
@@ -451,9 +588,9 @@ fn zip() {
     let actual = test_p2r(code);
     let expected = expect![[r#"
         fn main() {
-            let mut a_arr = vec![1, 2, 3];
-            let mut b_arr = vec![4, 5, 6];
-            let mut c_arr = vec![7, 8, 9];
+            let a_arr = vec![1, 2, 3];
+            let b_arr = vec![4, 5, 6];
+            let c_arr = vec![7, 8, 9];
             for (a, b, c) in a_arr
                 .iter()
                 .zip(b_arr.iter())
@@ -481,8 +618,8 @@ fn print_fmt() {
     let actual = test_p2r(code);
     let expected = expect![[r#"
         fn main() {
-            let mut a = 3;
-            let mut b = format!("{:?}{:?}", "b is ", 2.powf(2));
+            let a: isize = 3;
+            let b = format!("{:?}{:?}", "b is ", 2.powf(2));
             println!("{:?}", format!("{:?}{:?}", "a is ", a));
         }
     "#]];
@@ -522,9 +659,9 @@ fn casts() {
     let actual = test_p2r(code);
     let expected = expect![[r#"
         fn main() {
-            let mut i = ((3.2) as isize);
-            let mut f = ((3 + 1) as f64);
-            let mut s = 1.to_string();
+            let i = 3.2 as isize;
+            let f = (3 + 1) as f64;
+            let s = 1.to_string();
         }
     "#]];
     expected.assert_eq(&actual.to_string())
@@ -532,15 +669,37 @@ fn casts() {
 
 #[test]
 fn init() {
-    // TODO improve this, using new instead on the rust side
     let code = indoc! {"
         class A:
             i : int
             f : float
 
-        def __init__(self, i : int, f : float) -> A:
-            self.i = i
-            self.f = f
+            def __init__(self, i : int, f : float) -> A:
+                self.i = i
+                self.f = f
+
+        class Counter:
+            def __init__(self, name: str, start: int):
+                self.name = name
+                self.count = start * 2
+                self.seen: List[str] = []
+
+            def incr(self):
+                self.count += 1
+
+        class Span:
+            def __init__(self, a: int, b: int):
+                if a < b:
+                    self.lo = a
+                    self.hi = b
+                else:
+                    self.lo = b
+                    self.hi = a
+                self.hi += 1
+
+        a = A(1, 2.0)
+        c = Counter(\"c\", 0)
+        c.incr()
         "};
 
     let actual = test_p2r(code);
@@ -551,13 +710,65 @@ fn init() {
                 i: isize,
                 f: f64,
             }
-            fn __init__(&self, i: isize, f: f64) -> A {
-                self.i = i;
-                self.f = f;
+            impl A {
+                pub fn new(i: isize, f: f64) -> Self {
+                    Self { i, f }
+                }
+            }
+            #[derive(Debug, Clone)]
+            struct Counter {
+                name: String,
+                count: isize,
+                seen: Vec<String>,
+            }
+            impl Counter {
+                pub fn new(name: String, start: isize) -> Self {
+                    let count = start * 2;
+                    let seen: Vec<String> = vec![];
+                    Self { name, count, seen }
+                }
+                fn incr(&mut self) -> () {
+                    self.count += 1;
+                }
+            }
+            #[derive(Debug, Clone)]
+            struct Span {
+                lo: isize,
+                hi: isize,
+            }
+            impl Span {
+                pub fn new(a: isize, b: isize) -> Self {
+                    let lo;
+                    let mut hi;
+                    if a < b {
+                        lo = a;
+                        hi = b;
+                    } else {
+                        lo = b;
+                        hi = a;
+                    }
+                    hi += 1;
+                    Self { lo, hi }
+                }
             }
+            let a = A::new(1, 2.0);
+            let mut c = Counter::new("c", 0);
+            c.incr();
         }
     "#]];
-    expected.assert_eq(&actual.to_string())
+    expected.assert_eq(&actual.to_string());
+
+    // the struct can't be built if a field is only assigned on some paths
+    let code = indoc! {"
+        class Maybe:
+            def __init__(self, x: int):
+                if x > 0:
+                    self.x = x
+        "};
+
+    let err = test_p2r_err(code);
+    let expected = expect!["FunctionDef at 2:5-4:23: a field which `__init__` doesn't assign on every path can't be initialized"];
+    expected.assert_eq(&err);
 }
 
 #[test]
@@ -570,9 +781,9 @@ fn delete() {
     let actual = test_p2r(code);
     let expected = expect![[r#"
         fn main() {
-            let (mut a, mut b) = (1, 2);
+            let (a, b) = (1, 2);
             drop(a);
-            drop(b)
+            drop(b);
         }
     "#]];
     expected.assert_eq(&actual.to_string())
@@ -585,7 +796,7 @@ fn bytes() {
     let actual = test_p2r(code);
     let expected = expect![[r#"
         fn main() {
-            let mut hello_world = b"\x7f\x45\x4c\x46\x01\x01\x01\x00";
+            let hello_world = b"\x7f\x45\x4c\x46\x01\x01\x01\x00";
         }
     "#]];
     expected.assert_eq(&actual.to_string())
@@ -598,7 +809,7 @@ fn raise() {
     let actual = test_p2r(code);
     let expected = expect![[r#"
         fn main() {
-            panic!("Exception("hello ")")
+            panic!("Exception({:?})", "hello");
         }
     "#]];
     expected.assert_eq(&actual.to_string())
@@ -606,13 +817,7 @@ fn raise() {
 
 #[test]
 fn try_except() {
-    // there really is no great way to convert this
-    // prettyplease does not perserve comments so commenting out the else case
-    // https://github.com/dtolnay/prettyplease/issues/50
-    // is also not an option so we put it in a new scope to let the user deal with the result
-    // manually for now, ideas are appreciated
-
-    // catch_unwind would be a possibility
+    // without `result_errors` exceptions are panics, they are caught with catch_unwind
     // https://doc.rust-lang.org/std/panic/fn.catch_unwind.html
     let code = indoc! {"
         try:
@@ -628,13 +833,21 @@ fn try_except() {
     let actual = test_p2r(code);
     let expected = expect![[r#"
         fn main() {
-            try_it(|| {
-                return 3 / 0;
-            });
-            catch_it(|e1| {
-                let mut val = 100;
-                return val;
-            });
+            match std::panic::catch_unwind(
+                std::panic::AssertUnwindSafe(|| -> Option<_> {
+                    return Some(3 / 0);
+                }),
+            ) {
+                Ok(Some(value)) => return value,
+                Ok(None) => unreachable!(),
+                Err(e1) => {
+                    // TODO handle ; here better
+                    // in .rs code
+                    // print(e)
+                    let val: isize = 100;
+                    return val;
+                }
+            }
         }
     "#]];
     expected.assert_eq(&actual.to_string())
@@ -652,10 +865,96 @@ fn kwargs() {
     let actual = test_p2r(code);
     let expected = expect![[r#"
         fn main() {
-            fn foo(x: (), a: (), b: (), c: ()) -> () {
-                todo!()
+            fn foo(x: isize, a: isize, b: isize, c: isize) -> () {
+                todo!();
+            }
+            foo(0, 1, 2, 3);
+        }
+    "#]];
+    expected.assert_eq(&actual.to_string())
+}
+
+#[test]
+fn default_params() {
+    let code = indoc! {"
+        def power(base: int, exp: int = 2, offset: int = 0) -> int:
+            result = 1
+            for _ in range(exp):
+                result *= base
+            return result + offset
+
+        def total(first: int, *rest: int) -> int:
+            s = first
+            for r in rest:
+                s += r
+            return s
+
+        def describe(name: int, **extra: int) -> int:
+            return name + extra[\"a\"]
+
+        a = power(3)
+        b = power(2, 10)
+        c = power(2, offset=1, exp=3)
+        d = power(exp=3, base=4)
+        e = total(1, 2, 3)
+        f = describe(1, a=2)
+        "};
+
+    let actual = test_p2r(code);
+    let expected = expect![[r#"
+        fn main() {
+            struct PowerParams {
+                exp: isize,
+                offset: isize,
+            }
+            impl Default for PowerParams {
+                fn default() -> Self {
+                    Self { exp: 2, offset: 0 }
+                }
+            }
+            fn power(base: isize, params: PowerParams) -> isize {
+                let PowerParams { exp, offset } = params;
+                let mut result: isize = 1;
+                for _ in 0..exp {
+                    result *= base;
+                }
+                return result + offset;
+            }
+            fn total(first: isize, rest: &[isize]) -> isize {
+                let mut s = first;
+                for r in rest.iter().copied() {
+                    s += r;
+                }
+                return s;
+            }
+            fn describe(name: isize, extra: &std::collections::HashMap<&str, isize>) -> isize {
+                return name + extra["a"];
             }
-            foo(0, fooParams { a: 1, b: 2, c: 3 });
+            let a = power(3, PowerParams::default());
+            let b = power(
+                2,
+                PowerParams {
+                    exp: 10,
+                    ..Default::default()
+                },
+            );
+            let c = power(
+                2,
+                PowerParams {
+                    exp: 3,
+                    offset: 1,
+                    ..Default::default()
+                },
+            );
+            let d = power(
+                4,
+                PowerParams {
+                    exp: 3,
+                    ..Default::default()
+                },
+            );
+            let e = total(1, &[2, 3]);
+            let f = describe(1, &std::collections::HashMap::from([("a", 2)]));
         }
     "#]];
     expected.assert_eq(&actual.to_string())
@@ -671,8 +970,45 @@ fn compare() {
     let actual = test_p2r(code);
     let expected = expect![[r#"
         fn main() {
-            let mut a = 3 < 4;
-            let mut b = 3 < 4 && 4 < 5;
+            let a = 3 < 4;
+            let b = 3 < 4 && 4 < 5;
+        }
+    "#]];
+    expected.assert_eq(&actual.to_string())
+}
+
+#[test]
+fn precedence() {
+    let code = indoc! {"
+        a = (1 + 2) * 3
+        b = 1 + 2 * 3
+        c = 1 - (2 - 3)
+        d = (1 - 2) - 3
+        e = -(a ** 2)
+        f = (-a) ** 2
+        g = (a + b) ** 2
+        h = (a or b) and c
+        i = not (a and b)
+        j = (a < b) == (c < d)
+        k = int(a + b) < c
+        l = (a + b).real
+        "};
+
+    let actual = test_p2r(code);
+    let expected = expect![[r#"
+        fn main() {
+            let a = (1 + 2) * 3;
+            let b = 1 + 2 * 3;
+            let c = 1 - (2 - 3);
+            let d = 1 - 2 - 3;
+            let e = -a.powf(2);
+            let f = (-a).powf(2);
+            let g = (a + b).powf(2);
+            let h = (a || b) && c;
+            let i = !(a && b);
+            let j = (a < b) == (c < d);
+            let k = ((a + b) as isize) < c;
+            let l = (a + b).real;
         }
     "#]];
     expected.assert_eq(&actual.to_string())
@@ -693,11 +1029,10 @@ fn doc_comments() {
     let actual = test_p2r(code);
     let expected = expect![[r#"
         fn main() {
-            /*! Adds two numbers
-            Arguments:
-                a : Number
-                b : Number
-             */
+            /// Adds two numbers
+            /// Arguments:
+            ///     a : Number
+            ///     b : Number
             fn add(a: (), b: ()) -> () {
                 return a + b;
             }
@@ -746,28 +1081,30 @@ while count2 < 20:
     // compiles
     let expected = expect![[r#"
         fn main() {
+            // https://rosettacode.org/wiki/Duffinian_numbers#Python
+            // with minor modifications (added typehints)
             fn factors(n: isize) -> Vec<isize> {
-                let mut factors = vec![];
-                for i in (1..n + 1) {
+                let mut factors: Vec<isize> = vec![];
+                for i in 1..n + 1 {
                     if n % i == 0 {
                         factors.push(i);
                     }
                 }
                 return factors;
             }
-            fn gcd(a: isize, b: isize) -> isize {
+            fn gcd(mut a: isize, mut b: isize) -> isize {
                 while b != 0 {
-                    let (mut a, mut b) = (b, a % b);
+                    (a, b) = (b, a % b);
                 }
                 return a;
             }
-            let mut is_relively_prime = |a, b| { gcd(a, b) == 1 };
-            let mut sigma_sum = |x| { factors(x).iter().sum() };
-            let mut is_duffinian = |x| {
+            let is_relively_prime = |a, b| { gcd(a, b) == 1 };
+            let sigma_sum = |x| { factors(x).iter().sum() };
+            let is_duffinian = |x| {
                 is_relively_prime(x, sigma_sum(x)) && factors(x).len() > 2
             };
-            let mut count = 0;
-            let mut i = 0;
+            let mut count: isize = 0;
+            let mut i: isize = 0;
             while count < 50 {
                 if is_duffinian(i) {
                     println!("{:?}", i);
@@ -775,10 +1112,10 @@ while count2 < 20:
                 }
                 i += 1;
             }
-            let mut count2 = 0;
-            let mut j = 0;
+            let mut count2: isize = 0;
+            let mut j: isize = 0;
             while count2 < 20 {
-                if is_duffinian(j) && is_duffinian(j + 1) {
+                if is_duffinian(j) && is_duffinian(j + 1) && is_duffinian(j + 2) {
                     println!(
                         "{:?}", format!("{:?}{:?}{:?}{:?}{:?}{:?}{:?}", "(", j, ",", j + 1, ",",
                         j + 2, ")")
@@ -811,6 +1148,11 @@ fn fizzbufzz() {
     // compiles
     let expected = expect![[r#"
         fn main() {
+            // from https://rosettacode.org/wiki/FizzBuzz#Python
+            // TODO
+            // print (', '.join([(x%3<1)*'Fizz'+(x%5<1)*'Buzz' or str(x) for x in range(1,101)]))
+            // TODO
+            // print(*map(lambda n: 'Fizzbuzz '[(i):i+13] if (i := n**4%-15) > -14 else n, range(1,100)))
             (1..101)
                 .into_iter()
                 .map(|i| {
@@ -891,7 +1233,7 @@ fn imports() {
     let actual = test_p2r(code);
     let expected = expect![[r#"
         fn main() {
-            let mut sin_of_pi = prelude::abs(cos(foo(mypi)));
+            let sin_of_pi = prelude::abs(cos(foo(mypi)));
             println!("{:?}", sin_of_pi);
             use prelude::{abs, cos, pi as mypi, sin as foo};
             mod prelude {
@@ -916,5 +1258,2064 @@ fn imports() {
 
 fn test_p2r(code: &str) -> String {
     let code = crate::p2r(code, &mut crate::Ctx::default()).unwrap();
-    crate::fmt(&format!("fn main(){{{code}}}"))
+    crate::fmt(&crate::main_file(code))
+}
+
+#[test]
+fn error_span() {
+    let code = indoc! {"
+        for x in y:
+            z = x // 2
+        f = lambda **kw: kw
+        "};
+
+    let err = test_p2r_err(code);
+    let expected = expect!["BinOp at 2:9-2:15"];
+    expected.assert_eq(&err);
+
+    let err = test_p2r_err(code.lines().last().unwrap());
+    let expected = expect!["Lambda at 1:5-1:20"];
+    expected.assert_eq(&err);
+}
+
+#[test]
+fn comments() {
+    let code = indoc! {"
+        # leading
+        x = 1  # trailing
+        for i in range(3):  # header
+            # inside
+            x += i
+
+        class Foo:
+            a: int
+
+            # method
+            def get(self) -> int:
+                return self.a
+
+        import math  # dropped import
+        # end of file
+        "};
+
+    let actual = test_p2r(code);
+    let expected = expect![[r#"
+        fn main() {
+            // leading
+            // trailing
+            let mut x: isize = 1;
+            // header
+            for i in 0..3 {
+                // inside
+                x += i;
+            }
+            #[derive(Debug, Clone)]
+            struct Foo {
+                a: isize,
+            }
+            impl Foo {
+                // method
+                fn get(&self) -> isize {
+                    return self.a;
+                }
+            }
+            // dropped import
+            // end of file
+        }
+    "#]];
+    expected.assert_eq(&actual.to_string())
+}
+
+#[test]
+fn module() {
+    let code = indoc! {"
+        \"\"\"Geometry helpers\"\"\"
+        import math
+
+        # scale of the output
+        SCALE = 2.5
+        NAME: str = \"geo\"
+
+        # a point in the plane
+        class Point:
+            x: float
+            y: float
+
+        # euclidean norm
+        def norm(p: Point) -> float:
+            return math.sqrt(p.x * p.x + p.y * p.y)
+
+        def label() -> str:
+            return NAME
+
+        if __name__ == \"__main__\":
+            # entry point
+            p = Point(3.0, 4.0)
+            print(norm(p) * SCALE)
+        # end of file
+        "};
+
+    let actual = crate::fmt(&crate::p2r_module(code, &mut crate::Ctx::default()).unwrap());
+    let expected = expect![[r#"
+        //! Geometry helpers
+        use prelude::sqrt;
+        // scale of the output
+        const SCALE: f64 = 2.5;
+        const NAME: &str = "geo";
+        // a point in the plane
+        #[derive(Debug, Clone)]
+        struct Point {
+            x: f64,
+            y: f64,
+        }
+        // euclidean norm
+        fn norm(p: Point) -> f64 {
+            return prelude::sqrt(p.x * p.x + p.y * p.y);
+        }
+        fn label() -> String {
+            return NAME.to_string();
+        }
+        mod prelude {
+            #[inline(always)]
+            pub fn sqrt(a: f64) -> f64 {
+                a.sqrt()
+            }
+        }
+        fn main() {
+            // entry point
+            let p = Point { x: 3.0, y: 4.0 };
+            println!("{:?}", norm(p) * SCALE);
+        }
+        // end of file
+    "#]];
+    expected.assert_eq(&actual.to_string());
+
+    let code = indoc! {"
+        def double(x: int) -> int:
+            return 2 * x
+
+        y = double(21)
+        print(y)
+        "};
+
+    let actual = crate::fmt(&crate::p2r_module(code, &mut crate::Ctx::default()).unwrap());
+    let expected = expect![[r#"
+        fn double(x: isize) -> isize {
+            return 2 * x;
+        }
+        fn main() {
+            let y = double(21);
+            println!("{:?}", y);
+        }
+    "#]];
+    expected.assert_eq(&actual.to_string());
+
+    // an unsupported global doesn't stop the rest of the module
+    let code = indoc! {"
+        ITEMS = make_items()
+
+        def count() -> int:
+            global ITEMS
+            return len(ITEMS)
+        "};
+
+    let res = crate::transpile_module(code, &mut crate::Ctx::default()).unwrap();
+    let actual = crate::fmt(&res.output);
+    let expected = expect![[r#"
+        fn count() -> isize {
+            return ITEMS.lock().map(|v| v.clone()).unwrap().len() as isize;
+        }
+        fn main() {
+            // ITEMS = make_items()
+            todo!();
+        }
+    "#]];
+    expected.assert_eq(&actual.to_string());
+    let actual = res
+        .diagnostics
+        .iter()
+        .map(|d| {
+            let span = d.span.as_ref().unwrap();
+            format!(
+                "{} {} at {}:{}: {}\n",
+                d.severity, span.kind, span.start.line, span.start.column, d.message
+            )
+        })
+        .collect::<String>();
+    let expected = expect![[r#"
+        error Assign at 1:1: python Assign: the type of the global variable is unknown, annotate it
+    "#]];
+    expected.assert_eq(&actual);
+}
+
+#[test]
+fn pyfunctions() {
+    let code = indoc! {"
+        import math
+
+        def norm(x: float, y: float) -> float:
+            return math.sqrt(x * x + y * y)
+        "};
+
+    let mut file = crate::p2r_module(code, &mut crate::Ctx::default()).unwrap();
+    crate::pyfunctions(&mut file);
+    let expected = expect![[r#"
+        use prelude::sqrt;
+        #[pyo3::pyfunction]
+        fn norm(x: f64, y: f64) -> f64 {
+            return prelude::sqrt(x * x + y * y);
+        }
+        mod prelude {
+            #[inline(always)]
+            pub fn sqrt(a: f64) -> f64 {
+                a.sqrt()
+            }
+        }
+    "#]];
+    expected.assert_eq(&crate::fmt(&file));
+}
+
+#[test]
+fn best_effort() {
+    let code = indoc! {"
+        def foo(x: int) -> int:
+            global counter
+            with open(\"f\") as f:
+                y = x // 2
+                print(f\"{y}\")
+            return x
+        a = foo(1)
+        "};
+
+    let mut ctx = crate::Ctx {
+        best_effort: true,
+        ..Default::default()
+    };
+    let actual = crate::fmt(&crate::main_file(crate::p2r(code, &mut ctx).unwrap()));
+    let expected = expect![[r#"
+        fn main() {
+            fn foo(x: isize) -> isize {
+                // global counter
+                todo!();
+                {
+                    let f = std::fs::File::open("f").unwrap();
+                    // y = x // 2
+                    todo!();
+                    println!("{:?}", format!("{:?}", y));
+                }
+                return x;
+            }
+            let a = foo(1);
+        }
+    "#]];
+    expected.assert_eq(&actual.to_string())
+}
+
+#[test]
+fn diagnostics() {
+    let code = indoc! {"
+        global counter
+        if a is None:
+            raise ValueError(a)
+        x = [1, 2] @ [3, 4]
+        foo(1, b=2)
+        "};
+
+    let transpiled = crate::transpile(code, &mut crate::Ctx::default()).unwrap();
+    let actual = transpiled
+        .diagnostics
+        .iter()
+        .map(|d| {
+            let span = d.span.as_ref().unwrap();
+            format!(
+                "{} {} at {}:{}: {}\n",
+                d.severity, span.kind, span.start.line, span.start.column, d.message
+            )
+        })
+        .collect::<String>();
+    let expected = expect![[r#"
+        error Global at 1:1: python Global: `global` variables have to be assigned at the top level of the module
+        warning Compare at 2:4: `is` is translated as `==`
+        warning Raise at 3:5: exceptions are translated to panics
+        error BinOp at 4:5: python BinOp is not supported yet
+        warning Call at 5:1: keyword arguments are passed as a `Params` struct which is not generated
+    "#]];
+    expected.assert_eq(&actual);
+
+    let actual = crate::fmt(&crate::main_file(transpiled.output));
+    let expected = expect![[r#"
+        fn main() {
+            // global counter
+            todo!();
+            if a == None {
+                panic!("ValueError({:?})", a);
+            }
+            // x = [1, 2] @ [3, 4]
+            todo!();
+            foo(1, FooParams { b: 2 });
+        }
+    "#]];
+    expected.assert_eq(&actual);
+}
+
+#[test]
+fn type_inference() {
+    let code = indoc! {"
+def mean(values):
+    total = 0.0
+    for v in values:
+        total += v
+    return total / len(values)
+
+def scale(x, factor=2):
+    return x * factor
+
+def names():
+    res = []
+    for i in range(3):
+        res.append(str(i))
+    return res
+
+def size(xs: List[int]) -> int:
+    return len(xs)
+
+def double(x):
+    return x * 2
+
+m = mean([1.0, 2.0])
+s = scale(3)
+d = [double(v) for v in [1, 2]]
+        "};
+
+    let actual = test_p2r(code);
+    let expected = expect![[r#"
+        fn main() {
+            fn mean(values: Vec<f64>) -> f64 {
+                let mut total: f64 = 0.0;
+                for v in values.iter().copied() {
+                    total += v;
+                }
+                return total / values.len() as f64;
+            }
+            struct ScaleParams {
+                factor: isize,
+            }
+            impl Default for ScaleParams {
+                fn default() -> Self {
+                    Self { factor: 2 }
+                }
+            }
+            fn scale(x: isize, params: ScaleParams) -> isize {
+                let ScaleParams { factor } = params;
+                return x * factor;
+            }
+            fn names() -> Vec<String> {
+                let mut res: Vec<String> = vec![];
+                for i in 0..3 {
+                    res.push(i.to_string());
+                }
+                return res;
+            }
+            fn size(xs: Vec<isize>) -> isize {
+                return xs.len() as isize;
+            }
+            fn double(x: isize) -> isize {
+                return x * 2;
+            }
+            let m = mean(vec![1.0, 2.0]);
+            let s = scale(3, ScaleParams::default());
+            let d = vec![1, 2].into_iter().map(|v| { double(v) }).collect::<Vec<_>>();
+        }
+    "#]];
+    expected.assert_eq(&actual);
+}
+
+#[test]
+fn mutability() {
+    let code = indoc! {"
+def clamp(x: int, hi: int) -> int:
+    if x > hi:
+        x = hi
+    return x
+
+name = \"a\"
+total = 0
+items = []
+for i in range(3):
+    total = total + i
+    items.append(i)
+    last = i
+counts = {}
+counts[name] = total
+        "};
+
+    let actual = test_p2r(code);
+    let expected = expect![[r#"
+        fn main() {
+            fn clamp(mut x: isize, hi: isize) -> isize {
+                if x > hi {
+                    x = hi;
+                }
+                return x;
+            }
+            let name = "a";
+            let mut total: isize = 0;
+            let mut items: Vec<isize> = vec![];
+            for i in 0..3 {
+                total = total + i;
+                items.push(i);
+                let last = i;
+            }
+            let mut counts = HashMap::new();
+            counts[name] = total;
+        }
+    "#]];
+    expected.assert_eq(&actual);
+}
+
+#[test]
+fn receivers() {
+    let code = indoc! {"
+class Stack:
+    items: List[int]
+    size: int
+
+    def push(self, item: int):
+        self.items.append(item)
+        self.size += 1
+
+    def push_twice(self, item: int):
+        self.push(item)
+        self.push(item)
+
+    def peek(self) -> int:
+        return self.items[-1]
+
+    def into_items(self) -> List[int]:
+        return self.items
+        "};
+
+    let actual = test_p2r(code);
+    let expected = expect![[r#"
+        fn main() {
+            #[derive(Debug, Clone)]
+            struct Stack {
+                items: Vec<isize>,
+                size: isize,
+            }
+            impl Stack {
+                fn push(&mut self, item: isize) -> () {
+                    self.items.push(item);
+                    self.size += 1;
+                }
+                fn push_twice(&mut self, item: isize) -> () {
+                    self.push(item);
+                    self.push(item);
+                }
+                fn peek(&self) -> isize {
+                    return *self.items.last().unwrap();
+                }
+                fn into_items(self) -> Vec<isize> {
+                    return self.items;
+                }
+            }
+        }
+    "#]];
+    expected.assert_eq(&actual);
+}
+
+#[test]
+fn dunder() {
+    let code = indoc! {"
+class Vec2:
+    x: float
+    y: float
+
+    def __add__(self, other):
+        return Vec2(self.x + other.x, self.y + other.y)
+
+    def __mul__(self, k: float):
+        return Vec2(self.x * k, self.y * k)
+
+    def __eq__(self, other):
+        return self.x == other.x and self.y == other.y
+
+    def __lt__(self, other):
+        return self.x < other.x
+
+    def __str__(self):
+        return f\"({self.x}, {self.y})\"
+
+    def __hash__(self):
+        return hash((self.x, self.y))
+
+class Countdown:
+    items: List[int]
+    n: int
+
+    def __len__(self) -> int:
+        if self.n > 0:
+            return self.n
+        return len(self.items)
+
+    def __getitem__(self, i: int):
+        return self.items[i]
+
+    def __iter__(self):
+        return self
+
+    def __next__(self) -> int:
+        if self.n == 0:
+            raise StopIteration
+        self.n -= 1
+        return self.n
+        "};
+
+    let actual = test_p2r(code);
+    let expected = expect![[r#"
+        fn main() {
+            #[derive(Debug, Clone)]
+            struct Vec2 {
+                x: f64,
+                y: f64,
+            }
+            impl std::ops::Add for Vec2 {
+                type Output = Vec2;
+                fn add(self, other: Self) -> Self::Output {
+                    return Vec2 {
+                        x: self.x + other.x,
+                        y: self.y + other.y,
+                    };
+                }
+            }
+            impl std::ops::Mul<f64> for Vec2 {
+                type Output = Vec2;
+                fn mul(self, k: f64) -> Self::Output {
+                    return Vec2 {
+                        x: self.x * k,
+                        y: self.y * k,
+                    };
+                }
+            }
+            impl PartialEq for Vec2 {
+                fn eq(&self, other: &Self) -> bool {
+                    return self.x == other.x && self.y == other.y;
+                }
+            }
+            impl PartialOrd for Vec2 {
+                fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+                    use std::cmp::Ordering::*;
+                    if self < other {
+                        Some(Less)
+                    } else if other < self {
+                        Some(Greater)
+                    } else {
+                        Some(Equal)
+                    }
+                }
+                fn lt(&self, other: &Self) -> bool {
+                    return self.x < other.x;
+                }
+            }
+            impl std::fmt::Display for Vec2 {
+                fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    return write!(
+                        formatter, "{}", format!("{:?}{:?}{:?}{:?}{:?}", "(", self.x, ", ", self
+                        .y, ")")
+                    );
+                }
+            }
+            impl std::hash::Hash for Vec2 {
+                fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+                    return (self.x.to_bits(), self.y.to_bits()).hash(state);
+                }
+            }
+            impl Eq for Vec2 {}
+            #[derive(Debug, Clone)]
+            struct Countdown {
+                items: Vec<isize>,
+                n: isize,
+            }
+            impl Countdown {
+                fn len(&self) -> usize {
+                    if self.n > 0 {
+                        return self.n as usize;
+                    }
+                    return self.items.len();
+                }
+            }
+            impl std::ops::Index<usize> for Countdown {
+                type Output = isize;
+                fn index(&self, i: usize) -> &Self::Output {
+                    return &self.items[i];
+                }
+            }
+            impl Iterator for Countdown {
+                type Item = isize;
+                fn next(&mut self) -> Option<Self::Item> {
+                    if self.n == 0 {
+                        return None;
+                    }
+                    self.n -= 1;
+                    return self.n.into();
+                }
+            }
+        }
+    "#]];
+    expected.assert_eq(&actual);
+}
+
+#[test]
+fn inheritance() {
+    let code = indoc! {"
+class Shape(ABC):
+    @abstractmethod
+    def area(self) -> float:
+        pass
+
+    def describe(self) -> str:
+        return \"shape\"
+
+class Square(Shape):
+    side: float
+
+    def area(self) -> float:
+        return self.side * self.side
+
+class Animal:
+    def __init__(self, name: str):
+        self.name = name
+
+    def speak(self) -> str:
+        return self.name
+
+class Dog(Animal):
+    def __init__(self, name: str, age: int):
+        super().__init__(name)
+        self.age = age
+
+    def speak(self) -> str:
+        return super().speak() + \"!\"
+        "};
+
+    let actual = test_p2r(code);
+    let expected = expect![[r#"
+        fn main() {
+            trait Shape {
+                fn area(&self) -> f64;
+                fn describe(&self) -> String {
+                    return "shape".to_string();
+                }
+            }
+            #[derive(Debug, Clone)]
+            struct Square {
+                side: f64,
+            }
+            impl Shape for Square {
+                fn area(&self) -> f64 {
+                    return self.side * self.side;
+                }
+            }
+            #[derive(Debug, Clone)]
+            struct Animal {
+                name: String,
+            }
+            impl Animal {
+                pub fn new(name: String) -> Self {
+                    Self { name }
+                }
+                fn speak(&self) -> String {
+                    return self.name.clone();
+                }
+            }
+            #[derive(Debug, Clone)]
+            struct Dog {
+                base: Animal,
+                age: isize,
+            }
+            impl Dog {
+                pub fn new(name: String, age: isize) -> Self {
+                    let base = Animal::new(name);
+                    Self { base, age }
+                }
+                fn speak(&self) -> String {
+                    return self.base.speak() + "!";
+                }
+            }
+            impl std::ops::Deref for Dog {
+                type Target = Animal;
+                fn deref(&self) -> &Self::Target {
+                    &self.base
+                }
+            }
+            impl std::ops::DerefMut for Dog {
+                fn deref_mut(&mut self) -> &mut Self::Target {
+                    &mut self.base
+                }
+            }
+        }
+    "#]];
+    expected.assert_eq(&actual);
+}
+
+#[test]
+fn decorators() {
+    let code = indoc! {"
+@dataclass(frozen=True, order=True)
+class Point:
+    x: int
+    y: int
+
+    @staticmethod
+    def origin_distance(x: int, y: int) -> int:
+        return x * x + y * y
+
+    @classmethod
+    def diagonal(cls, v: int) -> Point:
+        return cls(v, v)
+
+    @property
+    def norm(self) -> int:
+        return Point.origin_distance(self.x, self.y)
+
+class Temperature:
+    def __init__(self, celsius: float):
+        self._celsius = celsius
+
+    @property
+    def celsius(self) -> float:
+        return self._celsius
+
+    @celsius.setter
+    def celsius(self, value: float):
+        self._celsius = value
+
+def f(p: Point, t: Temperature) -> int:
+    t.celsius = 20.0
+    return p.norm
+        "};
+
+    let actual = test_p2r(code);
+    let expected = expect![[r#"
+        fn main() {
+            #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd)]
+            struct Point {
+                x: isize,
+                y: isize,
+            }
+            impl Point {
+                fn origin_distance(x: isize, y: isize) -> isize {
+                    return x * x + y * y;
+                }
+                fn diagonal(v: isize) -> Point {
+                    return Point { x: v, y: v };
+                }
+                fn norm(&self) -> isize {
+                    return Point::origin_distance(self.x, self.y);
+                }
+            }
+            #[derive(Debug, Clone)]
+            struct Temperature {
+                _celsius: f64,
+            }
+            impl Temperature {
+                pub fn new(celsius: f64) -> Self {
+                    let _celsius = celsius;
+                    Self { _celsius }
+                }
+                fn celsius(&self) -> f64 {
+                    return self._celsius;
+                }
+                fn set_celsius(&mut self, value: f64) -> () {
+                    self._celsius = value;
+                }
+            }
+            fn f(p: Point, mut t: Temperature) -> isize {
+                t.set_celsius(20.0);
+                return p.norm();
+            }
+        }
+    "#]];
+    expected.assert_eq(&actual);
+
+    // floats are neither `Eq` nor `Hash`
+    let code = indoc! {"
+@dataclass(frozen=True)
+class Vec2:
+    x: float
+    y: float
+        "};
+    let transpiled = crate::transpile(code, &mut crate::Ctx::default()).unwrap();
+    let actual = crate::fmt(&crate::main_file(transpiled.output));
+    let expected = expect![[r#"
+        fn main() {
+            #[derive(Debug, Clone, PartialEq)]
+            struct Vec2 {
+                x: f64,
+                y: f64,
+            }
+        }
+    "#]];
+    expected.assert_eq(&actual);
+    let actual = transpiled
+        .diagnostics
+        .iter()
+        .map(|d| {
+            let span = d.span.as_ref().unwrap();
+            format!(
+                "{} {} at {}:{}: {}\n",
+                d.severity, span.kind, span.start.line, span.start.column, d.message
+            )
+        })
+        .collect::<String>();
+    let expected = expect![[r#"
+        warning Call at 1:2: floats can't be hashed, `Eq` and `Hash` aren't derived
+    "#]];
+    expected.assert_eq(&actual);
+
+    // only values of a known class have properties
+    let code = indoc! {"
+class Square:
+    side: float
+
+    @property
+    def area(self) -> float:
+        return self.side * self.side
+
+def show(shape):
+    print(shape.area)
+        "};
+
+    let transpiled = crate::transpile(code, &mut crate::Ctx::default()).unwrap();
+    let actual = crate::fmt(&crate::main_file(transpiled.output));
+    let expected = expect![[r#"
+        fn main() {
+            #[derive(Debug, Clone)]
+            struct Square {
+                side: f64,
+            }
+            impl Square {
+                fn area(&self) -> f64 {
+                    return self.side * self.side;
+                }
+            }
+            fn show(shape: ()) -> () {
+                println!("{:?}", shape.area);
+            }
+        }
+    "#]];
+    expected.assert_eq(&actual);
+    let actual = transpiled
+        .diagnostics
+        .iter()
+        .map(|d| {
+            let span = d.span.as_ref().unwrap();
+            format!(
+                "{} {} at {}:{}: {}\n",
+                d.severity, span.kind, span.start.line, span.start.column, d.message
+            )
+        })
+        .collect::<String>();
+    let expected = expect![[r#"
+        warning Attribute at 9:11: `area` is a property of a class, but the type of the value is unknown, it is accessed as a field
+    "#]];
+    expected.assert_eq(&actual);
+}
+
+#[test]
+fn defaults() {
+    let code = indoc! {"
+@dataclass
+class Config:
+    name: str
+    retries: int = 3
+    verbose: bool = field(default=False)
+    tags: List[str] = field(default_factory=list)
+    label: str = \"config\"
+
+def f() -> Config:
+    a = Config(\"a\")
+    return Config(name=\"b\", retries=5)
+        "};
+
+    let actual = test_p2r(code);
+    let expected = expect![[r#"
+        fn main() {
+            #[derive(Debug, Clone, PartialEq)]
+            struct Config {
+                name: String,
+                retries: isize,
+                verbose: bool,
+                tags: Vec<String>,
+                label: String,
+            }
+            impl Default for Config {
+                fn default() -> Self {
+                    Self {
+                        name: Default::default(),
+                        retries: 3,
+                        verbose: false,
+                        tags: Vec::new(),
+                        label: "config".to_string(),
+                    }
+                }
+            }
+            fn f() -> Config {
+                let a = Config {
+                    name: "a".to_string(),
+                    ..Default::default()
+                };
+                return Config {
+                    name: "b".to_string(),
+                    retries: 5,
+                    ..Default::default()
+                };
+            }
+        }
+    "#]];
+    expected.assert_eq(&actual);
+}
+
+#[test]
+fn exceptions() {
+    let code = indoc! {"
+class ParseError(Exception):
+    \"\"\"input which is not a number\"\"\"
+
+def parse(s: str) -> int:
+    if not s.isdigit():
+        raise ParseError(s)
+    return int(s)
+
+def check(x: int):
+    if x < 0:
+        raise ValueError(\"negative\")
+
+def total(items: List[str]) -> int:
+    t = 0
+    for i in items:
+        n = parse(i)
+        check(n)
+        t += n
+    return t
+
+def invalid(e: ParseError):
+    raise ValueError(\"invalid\") from e
+
+print(total([\"1\", \"2\"]))
+        "};
+
+    let mut ctx = crate::Ctx {
+        result_errors: true,
+        ..Default::default()
+    };
+    let actual = crate::fmt(&crate::main_file(crate::p2r(code, &mut ctx).unwrap()));
+    let expected = expect![[r#"
+        fn main() {
+            fn parse(s: String) -> Result<isize, Error> {
+                if !s.isdigit() {
+                    return Err(Error::ParseError {
+                        message: s.to_string(),
+                        source: None,
+                    });
+                }
+                return Ok(
+                    s
+                        .parse::<isize>()
+                        .map_err(|e| Error::ValueError {
+                            message: e.to_string(),
+                            source: None,
+                        })?,
+                );
+            }
+            fn check(x: isize) -> Result<(), Error> {
+                if x < 0 {
+                    return Err(Error::ValueError {
+                        message: "negative".to_string(),
+                        source: None,
+                    });
+                }
+                Ok(())
+            }
+            fn total(items: Vec<String>) -> Result<isize, Error> {
+                let mut t: isize = 0;
+                for i in items {
+                    let n = parse(i)?;
+                    check(n)?;
+                    t += n;
+                }
+                return Ok(t);
+            }
+            fn invalid(e: Error) -> Result<(), Error> {
+                return Err(Error::ValueError {
+                    message: "invalid".to_string(),
+                    source: Some(Box::new(e)),
+                });
+            }
+            println!("{:?}", total(vec!["1", "2"]).unwrap());
+            #[derive(Debug)]
+            enum Error {
+                /// input which is not a number
+                ParseError { message: String, source: Option<Box<Error>> },
+                ValueError { message: String, source: Option<Box<Error>> },
+            }
+            impl std::fmt::Display for Error {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    match self {
+                        Error::ParseError { message, .. } => write!(f, "ParseError: {message}"),
+                        Error::ValueError { message, .. } => write!(f, "ValueError: {message}"),
+                    }
+                }
+            }
+            impl std::error::Error for Error {
+                fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                    match self {
+                        Error::ParseError { source, .. } | Error::ValueError { source, .. } => {
+                            source.as_deref().map(|e| e as &(dyn std::error::Error + 'static))
+                        }
+                    }
+                }
+            }
+        }
+    "#]];
+    expected.assert_eq(&actual);
+}
+
+#[test]
+fn raising_methods() {
+    let code = indoc! {"
+class Stack:
+    def __init__(self):
+        self.items: List[int] = []
+
+    def pop(self) -> int:
+        if len(self.items) == 0:
+            raise IndexError(\"empty\")
+        return self.items.pop()
+
+def top(s: Stack, xs: List[int]) -> int:
+    a = xs.pop()
+    return s.pop() + a
+        "};
+
+    let mut ctx = crate::Ctx {
+        result_errors: true,
+        ..Default::default()
+    };
+    let actual = crate::fmt(&crate::main_file(crate::p2r(code, &mut ctx).unwrap()));
+    let expected = expect![[r#"
+        fn main() {
+            #[derive(Debug, Clone)]
+            struct Stack {
+                items: Vec<isize>,
+            }
+            impl Stack {
+                pub fn new() -> Self {
+                    let items: Vec<isize> = vec![];
+                    Self { items }
+                }
+                fn pop(&mut self) -> Result<isize, Error> {
+                    if self.items.len() == 0 {
+                        return Err(Error::IndexError {
+                            message: "empty".to_string(),
+                            source: None,
+                        });
+                    }
+                    return Ok(self.items.pop().unwrap());
+                }
+            }
+            fn top(mut s: Stack, mut xs: Vec<isize>) -> Result<isize, Error> {
+                let a = xs.pop().unwrap();
+                return Ok(s.pop()? + a);
+            }
+            #[derive(Debug)]
+            enum Error {
+                IndexError { message: String, source: Option<Box<Error>> },
+            }
+            impl std::fmt::Display for Error {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    match self {
+                        Error::IndexError { message, .. } => write!(f, "IndexError: {message}"),
+                    }
+                }
+            }
+            impl std::error::Error for Error {
+                fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                    match self {
+                        Error::IndexError { source, .. } => {
+                            source.as_deref().map(|e| e as &(dyn std::error::Error + 'static))
+                        }
+                    }
+                }
+            }
+        }
+    "#]];
+    expected.assert_eq(&actual);
+}
+
+#[test]
+fn try_except_finally() {
+    let code = indoc! {"
+class ParseError(ValueError):
+    pass
+
+def parse(s: str) -> int:
+    if not s.isdigit():
+        raise ParseError(s)
+    return int(s)
+
+def convert(s: str) -> int:
+    try:
+        return parse(s)
+    except ParseError as e:
+        raise RuntimeError(\"invalid\") from e
+
+def count(items: List[str]) -> int:
+    n = 0
+    for i in items:
+        try:
+            parse(i)
+        except (KeyError, ValueError):
+            print(\"skip\")
+        except RuntimeError:
+            raise
+        else:
+            n += 1
+        finally:
+            print(i)
+    return n
+
+try:
+    convert(\"x\")
+except* ValueError:
+    pass
+        "};
+
+    let mut ctx = crate::Ctx {
+        result_errors: true,
+        ..Default::default()
+    };
+    let transpiled = crate::transpile(code, &mut ctx).unwrap();
+    let actual = transpiled
+        .diagnostics
+        .iter()
+        .map(|d| format!("{}: {}\n", d.severity, d.message))
+        .collect::<String>();
+    let expected = expect![[r#"
+        error: python TryStar: exception groups can't be translated, `except*` has no equivalent in rust
+    "#]];
+    expected.assert_eq(&actual);
+
+    let actual = crate::fmt(&crate::main_file(transpiled.output));
+    let expected = expect![[r#"
+        fn main() {
+            fn parse(s: String) -> Result<isize, Error> {
+                if !s.isdigit() {
+                    return Err(Error::ParseError {
+                        message: s.to_string(),
+                        source: None,
+                    });
+                }
+                return Ok(
+                    s
+                        .parse::<isize>()
+                        .map_err(|e| Error::ValueError {
+                            message: e.to_string(),
+                            source: None,
+                        })?,
+                );
+            }
+            fn convert(s: String) -> Result<isize, Error> {
+                match (|| -> Result<Option<_>, Error> {
+                    return Ok(Some(Ok(parse(s)?)));
+                })() {
+                    Ok(Some(value)) => return value,
+                    Ok(None) => unreachable!(),
+                    Err(e @ Error::ParseError { .. }) => {
+                        return Err(Error::RuntimeError {
+                            message: "invalid".to_string(),
+                            source: Some(Box::new(e)),
+                        });
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            fn count(items: Vec<String>) -> Result<isize, Error> {
+                let mut n: isize = 0;
+                for i in items {
+                    let outcome = (|| -> Result<(), Error> {
+                        match (|| -> Result<(), Error> {
+                            parse(i)?;
+                            Ok(())
+                        })() {
+                            Ok(()) => {
+                                n += 1;
+                            }
+                            Err(
+                                Error::KeyError { .. }
+                                | Error::ParseError { .. }
+                                | Error::ValueError { .. },
+                            ) => {
+                                println!("{:?}", "skip");
+                            }
+                            Err(error @ Error::RuntimeError { .. }) => {
+                                return Err(error);
+                            }
+                        }
+                        Ok(())
+                    })();
+                    println!("{:?}", i);
+                    outcome?;
+                }
+                return Ok(n);
+            }
+            // try:
+            //     convert("x")
+            // except* ValueError:
+            //     pass
+            todo!();
+            #[derive(Debug)]
+            enum Error {
+                ParseError { message: String, source: Option<Box<Error>> },
+                RuntimeError { message: String, source: Option<Box<Error>> },
+                KeyError { message: String, source: Option<Box<Error>> },
+                ValueError { message: String, source: Option<Box<Error>> },
+            }
+            impl std::fmt::Display for Error {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    match self {
+                        Error::ParseError { message, .. } => write!(f, "ParseError: {message}"),
+                        Error::RuntimeError { message, .. } => {
+                            write!(f, "RuntimeError: {message}")
+                        }
+                        Error::KeyError { message, .. } => write!(f, "KeyError: {message}"),
+                        Error::ValueError { message, .. } => write!(f, "ValueError: {message}"),
+                    }
+                }
+            }
+            impl std::error::Error for Error {
+                fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                    match self {
+                        Error::ParseError { source, .. }
+                        | Error::RuntimeError { source, .. }
+                        | Error::KeyError { source, .. }
+                        | Error::ValueError { source, .. } => {
+                            source.as_deref().map(|e| e as &(dyn std::error::Error + 'static))
+                        }
+                    }
+                }
+            }
+        }
+    "#]];
+    expected.assert_eq(&actual);
+}
+
+#[test]
+fn try_loop_jumps() {
+    let code = indoc! {"
+def count(items: List[str]) -> int:
+    n = 0
+    for i in items:
+        try:
+            if i == \"stop\":
+                break
+            if i == \"\":
+                continue
+            n += int(i)
+        except ValueError:
+            continue
+    return n
+
+def total(items: List[str]) -> int:
+    n = 0
+    for i in items:
+        try:
+            if i == \"stop\":
+                break
+            n += int(i)
+        finally:
+            n += 1
+    return n
+        "};
+
+    let mut ctx = crate::Ctx {
+        result_errors: true,
+        ..Default::default()
+    };
+    let actual = crate::fmt(&crate::main_file(crate::p2r(code, &mut ctx).unwrap()));
+    let expected = expect![[r#"
+        fn main() {
+            fn count(items: Vec<String>) -> isize {
+                let mut n: isize = 0;
+                for i in items {
+                    match (|| -> Result<Option<_>, Error> {
+                        if i == "stop" {
+                            return Ok(Some(std::ops::ControlFlow::Break(())));
+                        }
+                        if i == "" {
+                            return Ok(Some(std::ops::ControlFlow::Continue(())));
+                        }
+                        n
+                            += i
+                                .parse::<isize>()
+                                .map_err(|e| Error::ValueError {
+                                    message: e.to_string(),
+                                    source: None,
+                                })?;
+                        Ok(None)
+                    })() {
+                        Ok(Some(std::ops::ControlFlow::Break(()))) => break,
+                        Ok(Some(std::ops::ControlFlow::Continue(()))) => continue,
+                        Ok(None) => {}
+                        Err(Error::ValueError { .. }) => {
+                            continue;
+                        }
+                    }
+                }
+                return n;
+            }
+            fn total(items: Vec<String>) -> Result<isize, Error> {
+                let mut n: isize = 0;
+                for i in items {
+                    let outcome = (|| -> Result<Option<_>, Error> {
+                        match (|| -> Result<Option<_>, Error> {
+                            if i == "stop" {
+                                return Ok(Some(std::ops::ControlFlow::Break(())));
+                            }
+                            n
+                                += i
+                                    .parse::<isize>()
+                                    .map_err(|e| Error::ValueError {
+                                        message: e.to_string(),
+                                        source: None,
+                                    })?;
+                            Ok(None)
+                        })() {
+                            Ok(Some(std::ops::ControlFlow::Break(()))) => {
+                                return Ok(Some(std::ops::ControlFlow::Break(())));
+                            }
+                            Ok(Some(std::ops::ControlFlow::Continue(()))) => {
+                                return Ok(Some(std::ops::ControlFlow::Continue(())));
+                            }
+                            Ok(None) => {}
+                            Err(e) => return Err(e),
+                        }
+                        Ok(None)
+                    })();
+                    n += 1;
+                    match outcome? {
+                        Some(std::ops::ControlFlow::Break(())) => break,
+                        Some(std::ops::ControlFlow::Continue(())) => continue,
+                        None => {}
+                    }
+                }
+                return Ok(n);
+            }
+            #[derive(Debug)]
+            enum Error {
+                ValueError { message: String, source: Option<Box<Error>> },
+            }
+            impl std::fmt::Display for Error {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    match self {
+                        Error::ValueError { message, .. } => write!(f, "ValueError: {message}"),
+                    }
+                }
+            }
+            impl std::error::Error for Error {
+                fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                    match self {
+                        Error::ValueError { source, .. } => {
+                            source.as_deref().map(|e| e as &(dyn std::error::Error + 'static))
+                        }
+                    }
+                }
+            }
+        }
+    "#]];
+    expected.assert_eq(&actual);
+
+    let code = indoc! {"
+def find(items: List[str]) -> int:
+    for i in items:
+        try:
+            if i == \"\":
+                continue
+            return int(i)
+        except ValueError:
+            pass
+    return 0
+        "};
+    let err = test_p2r_err(code);
+    let expected = expect![
+        "Try at 3:9-8:17: a `try` with `return` and `break` or `continue` can't be transpiled"
+    ];
+    expected.assert_eq(&err);
+}
+
+#[test]
+fn with_statement() {
+    let code = indoc! {"
+class Timer:
+    def __init__(self, name: str):
+        self.name = name
+        self.running = False
+
+    def __enter__(self):
+        self.running = True
+        return self
+
+    def __exit__(self, exc_type, exc, tb):
+        print(self.name)
+
+class Depth:
+    def __init__(self):
+        self.depth = 0
+
+    def __enter__(self) -> int:
+        return self.depth + 1
+
+    def __exit__(self, exc_type, exc, tb):
+        self.depth = 0
+
+def nested():
+    with Depth() as d:
+        print(d)
+
+def copy(src: str, dst: str, lock: Lock):
+    with lock:
+        with open(src) as f, open(dst, \"w\") as out:
+            for line in f:
+                out.write(line)
+
+def measure(path: str) -> str:
+    with tempfile.TemporaryDirectory() as d:
+        print(d)
+    with Timer(\"read\") as t, open(path) as f:
+        return f.read()
+        "};
+
+    let actual = test_p2r(code);
+    let expected = expect![[r#"
+        fn main() {
+            #[derive(Debug, Clone)]
+            struct Timer {
+                name: String,
+                running: bool,
+            }
+            impl Timer {
+                pub fn new(name: String) -> Self {
+                    let running = false;
+                    Self { name, running }
+                }
+                fn enter(&mut self) -> TimerGuard<'_> {
+                    self.running = true;
+                    TimerGuard(self)
+                }
+                fn exit(&mut self) {
+                    println!("{:?}", self.name);
+                }
+            }
+            struct TimerGuard<'a>(&'a mut Timer);
+            impl std::ops::Deref for TimerGuard<'_> {
+                type Target = Timer;
+                fn deref(&self) -> &Timer {
+                    self.0
+                }
+            }
+            impl std::ops::DerefMut for TimerGuard<'_> {
+                fn deref_mut(&mut self) -> &mut Timer {
+                    self.0
+                }
+            }
+            impl Drop for TimerGuard<'_> {
+                fn drop(&mut self) {
+                    self.0.exit();
+                }
+            }
+            #[derive(Debug, Clone)]
+            struct Depth {
+                depth: isize,
+            }
+            impl Depth {
+                pub fn new() -> Self {
+                    let depth = 0;
+                    Self { depth }
+                }
+                fn enter(&self) -> isize {
+                    return self.depth + 1;
+                }
+                fn exit(&mut self) {
+                    self.depth = 0;
+                }
+            }
+            struct DepthGuard<'a>(&'a mut Depth);
+            impl std::ops::Deref for DepthGuard<'_> {
+                type Target = Depth;
+                fn deref(&self) -> &Depth {
+                    self.0
+                }
+            }
+            impl std::ops::DerefMut for DepthGuard<'_> {
+                fn deref_mut(&mut self) -> &mut Depth {
+                    self.0
+                }
+            }
+            impl Drop for DepthGuard<'_> {
+                fn drop(&mut self) {
+                    self.0.exit();
+                }
+            }
+            fn nested() -> () {
+                {
+                    let mut manager = Depth::new();
+                    let guard = DepthGuard(&mut manager);
+                    let d = guard.enter();
+                    println!("{:?}", d);
+                }
+            }
+            fn copy(src: String, dst: String, lock: std::sync::Mutex<()>) -> () {
+                {
+                    let _guard = lock.lock().unwrap();
+                    {
+                        let f = std::fs::File::open(&src).unwrap();
+                        let mut out = std::fs::File::create(&dst).unwrap();
+                        for line in std::io::BufRead::lines(std::io::BufReader::new(&f))
+                            .map(Result::unwrap)
+                        {
+                            std::io::Write::write_all(&mut out, line.as_bytes()).unwrap();
+                        }
+                    }
+                }
+            }
+            fn measure(path: String) -> String {
+                {
+                    let d_dir = tempfile::tempdir().unwrap();
+                    let d = d_dir.path();
+                    println!("{:?}", d);
+                }
+                {
+                    let mut t = Timer::new("read");
+                    let t = t.enter();
+                    let f = std::fs::File::open(&path).unwrap();
+                    return std::io::read_to_string(&f).unwrap();
+                }
+            }
+        }
+    "#]];
+    expected.assert_eq(&actual);
+}
+
+#[test]
+fn generators() {
+    let code = indoc! {"
+def count(n: int):
+    i = 0
+    while i < n:
+        yield i
+        i += 1
+
+def evens(xs: List[int]) -> Iterator[int]:
+    \"\"\"even numbers of xs\"\"\"
+    for x in xs:
+        if x % 2 == 0:
+            yield x
+
+def squares(xs: List[int]):
+    for x in xs:
+        yield x * x
+
+def flatten(rows: List[List[int]]) -> Iterator[int]:
+    for row in rows:
+        yield from row
+
+def header(rows: List[int]) -> Iterator[int]:
+    yield 0
+    yield from rows
+
+def total(n: int) -> int:
+    s = 0
+    for i in count(n):
+        s += i
+    return s
+        "};
+
+    let actual = test_p2r(code);
+    let expected = expect![[r#"
+        fn main() {
+            fn count(n: isize) -> impl Iterator<Item = isize> {
+                let mut i: isize = 0;
+                let mut resumed = false;
+                std::iter::from_fn(move || {
+                    if resumed {
+                        resumed = false;
+                        i += 1;
+                    }
+                    if i < n {
+                        resumed = true;
+                        return Some(i);
+                    }
+                    None
+                })
+            }
+            /// even numbers of xs
+            fn evens(xs: Vec<isize>) -> impl Iterator<Item = isize> {
+                let mut iter = xs.into_iter();
+                std::iter::from_fn(move || {
+                    for x in iter.by_ref() {
+                        if x % 2 == 0 {
+                            return Some(x);
+                        }
+                    }
+                    None
+                })
+            }
+            fn squares(xs: Vec<isize>) -> impl Iterator<Item = isize> {
+                xs.into_iter().map(move |x| x * x)
+            }
+            fn flatten(rows: Vec<Vec<isize>>) -> impl Iterator<Item = isize> {
+                rows.into_iter().flat_map(move |row| row)
+            }
+            fn header(rows: Vec<isize>) -> impl Iterator<Item = isize> {
+                std::iter::once(0).chain(rows)
+            }
+            fn total(n: isize) -> isize {
+                let mut s: isize = 0;
+                for i in count(n) {
+                    s += i;
+                }
+                return s;
+            }
+        }
+    "#]];
+    expected.assert_eq(&actual);
+}
+
+#[test]
+fn async_functions() {
+    let code = indoc! {"
+class Connection:
+    def __init__(self, url: str):
+        self.url = url
+        self.open = False
+
+    async def __aenter__(self):
+        self.open = True
+        return self
+
+    async def __aexit__(self, exc_type, exc, tb):
+        self.open = False
+
+    async def fetch(self, n: int) -> int:
+        await asyncio.sleep(0.1)
+        return n
+
+async def total(url: str) -> int:
+    result = 0
+    async with Connection(url) as c:
+        a, b = await asyncio.gather(c.fetch(1), c.fetch(2))
+        result = a + b
+    return result
+
+async def consume(events: Events):
+    async for e in events:
+        print(e)
+
+print(asyncio.run(total(\"db\")))
+        "};
+
+    let actual = test_p2r(code);
+    let expected = expect![[r#"
+        fn main() {
+            #[derive(Debug, Clone)]
+            struct Connection {
+                url: String,
+                open: bool,
+            }
+            impl Connection {
+                pub fn new(url: String) -> Self {
+                    let open = false;
+                    Self { url, open }
+                }
+                async fn fetch(&self, n: isize) -> isize {
+                    tokio::time::sleep(std::time::Duration::from_secs_f64(0.1 as f64)).await;
+                    return n;
+                }
+                async fn enter(&mut self) {
+                    self.open = true;
+                }
+                async fn exit(&mut self) {
+                    self.open = false;
+                }
+            }
+            async fn total(url: String) -> isize {
+                let mut result: isize = 0;
+                {
+                    let mut c = Connection::new(url);
+                    c.enter().await;
+                    let (a, b) = tokio::join!(c.fetch(1), c.fetch(2));
+                    result = a + b;
+                    c.exit().await;
+                }
+                return result;
+            }
+            async fn consume(events: Events) -> () {
+                {
+                    let mut stream = std::pin::pin!(events);
+                    while let Some(e) = tokio_stream::StreamExt::next(&mut stream).await {
+                        println!("{:?}", e);
+                    }
+                }
+            }
+            println!("{:?}", tokio::runtime::Runtime::new().unwrap().block_on(total("db")));
+        }
+    "#]];
+    expected.assert_eq(&actual);
+
+    let mut ctx = crate::Ctx {
+        runtime: crate::Runtime::AsyncStd,
+        ..Default::default()
+    };
+    let code = indoc! {"
+async def main():
+    await asyncio.sleep(1)
+    await asyncio.gather(*[asyncio.sleep(2)])
+
+asyncio.run(main())
+        "};
+    let actual = crate::fmt(&crate::main_file(crate::p2r(code, &mut ctx).unwrap()));
+    let expected = expect![[r#"
+        fn main() {
+            async fn main() -> () {
+                async_std::task::sleep(std::time::Duration::from_secs_f64(1 as f64)).await;
+                futures::future::join_all(
+                        vec![
+                            async_std::task::sleep(std::time::Duration::from_secs_f64(2 as f64))
+                        ],
+                    )
+                    .await;
+            }
+            async_std::task::block_on(main());
+        }
+    "#]];
+    expected.assert_eq(&actual);
+}
+
+#[test]
+fn global_variables() {
+    let code = indoc! {"
+counter = 0
+names: List[str] = []
+greeting = \"hi\"
+
+def increment(step: int):
+    global counter
+    counter += step
+
+def reset():
+    global counter
+    counter = counter - counter
+
+def remember(name: str):
+    global names
+    names.append(name)
+
+def forget():
+    global names
+    names = []
+
+def greet():
+    global greeting
+    greeting = \"hello\"
+
+def number():
+    names.append(str(len(names)))
+
+def last() -> str:
+    return names[-1]
+
+def repeat():
+    names.append(last())
+    for n in names:
+        remember(n)
+
+def fill():
+    names.extend([last() for _ in range(2)])
+
+def total() -> int:
+    return counter * 2
+
+increment(3)
+reset()
+remember(\"a\")
+print(counter, total())
+        "};
+
+    let res = crate::transpile_module(code, &mut crate::Ctx::default()).unwrap();
+    let actual = crate::fmt(&res.output);
+    let expected = expect![[r#"
+        static COUNTER: std::sync::Mutex<isize> = std::sync::Mutex::new(0);
+        static NAMES: std::sync::LazyLock<std::sync::Mutex<Vec<String>>> = std::sync::LazyLock::new(||
+        std::sync::Mutex::new(vec![]));
+        static GREETING: std::sync::LazyLock<std::sync::Mutex<String>> = std::sync::LazyLock::new(||
+        std::sync::Mutex::new("hi".to_string()));
+        fn increment(step: isize) -> () {
+            (*COUNTER.lock().unwrap()) += step;
+        }
+        fn reset() -> () {
+            (*COUNTER
+                .lock()
+                .unwrap()) = COUNTER.lock().map(|v| *v).unwrap()
+                - COUNTER.lock().map(|v| *v).unwrap();
+        }
+        fn remember(name: String) -> () {
+            (*NAMES.lock().unwrap()).push(name);
+        }
+        fn forget() -> () {
+            (*NAMES.lock().unwrap()) = vec![];
+        }
+        fn greet() -> () {
+            (*GREETING.lock().unwrap()) = "hello".to_string();
+        }
+        fn number() -> () {
+            {
+                let names = &mut *NAMES.lock().unwrap();
+                (*names).push((*names).len().to_string());
+            }
+        }
+        fn last() -> String {
+            return (*NAMES.lock().unwrap()).last().unwrap().clone();
+        }
+        fn repeat() -> () {
+            let last_value = last();
+            (*NAMES.lock().unwrap()).push(last_value);
+            for n in NAMES.lock().map(|v| v.clone()).unwrap() {
+                remember(n);
+            }
+        }
+        fn fill() -> () {
+            (*NAMES.lock().unwrap())
+                .extend((0..2).into_iter().map(|_| { last() }).collect::<Vec<_>>());
+        }
+        fn total() -> isize {
+            return COUNTER.lock().map(|v| *v).unwrap() * 2;
+        }
+        fn main() {
+            increment(3);
+            reset();
+            remember("a");
+            println!("{:?}", COUNTER.lock().map(| v | * v).unwrap(), total());
+        }
+    "#]];
+    expected.assert_eq(&actual);
+    let actual = res
+        .diagnostics
+        .iter()
+        .map(|d| {
+            let span = d.span.as_ref().unwrap();
+            format!(
+                "{} {} at {}:{}: {}\n",
+                d.severity, span.kind, span.start.line, span.start.column, d.message
+            )
+        })
+        .collect::<String>();
+    let expected = expect![[r#"
+        warning Expr at 37:5: `last` uses the global `names`, which is locked by this statement, the call deadlocks
+    "#]];
+    expected.assert_eq(&actual);
+}
+
+#[test]
+fn nonlocal_variables() {
+    let code = indoc! {"
+def count(xs: List[int]) -> int:
+    n = 0
+    def visit(x: int):
+        nonlocal n
+        n += x
+    for x in xs:
+        visit(x)
+    def double() -> int:
+        nonlocal n
+        return n * 2
+    return double()
+        "};
+
+    let actual = test_p2r(code);
+    let expected = expect![[r#"
+        fn main() {
+            fn count(xs: Vec<isize>) -> isize {
+                let mut n: isize = 0;
+                let mut visit = |x: isize| -> () {
+                    n += x;
+                };
+                for x in xs.iter().copied() {
+                    visit(x);
+                }
+                let double = || -> isize {
+                    return n * 2;
+                };
+                return double();
+            }
+        }
+    "#]];
+    expected.assert_eq(&actual);
+}
+
+#[test]
+fn pattern_matching() {
+    let code = indoc! {"
+@dataclass
+class Point:
+    x: int
+    y: int
+
+class Color(Enum):
+    RED = 1
+    GREEN = 2
+    BLUE = 3
+
+def quadrant(p: Point) -> int:
+    match p:
+        case Point(0, 0):
+            return 0
+        case Point(x=0, y=y) if y > 0:
+            return 1
+        case Point(x, y=0):
+            return x
+        case Point():
+            return -1
+
+def warm(c: Color) -> bool:
+    match c:
+        case Color.RED | Color.GREEN:
+            return True
+        case Color.BLUE:
+            return False
+
+def head(xs: List[int]) -> int:
+    match xs:
+        case []:
+            return 0
+        case [x]:
+            return x
+        case [x, *rest] if len(rest) > 2:
+            return x
+        case [first, _, *_]:
+            return first
+
+def pair(t: tuple[int, int]):
+    match t:
+        case (0, -1) as p:
+            print(p)
+        case (a, b):
+            print(a + b)
+
+def command(c: Dict[str, str]):
+    match c:
+        case {\"action\": \"move\", \"to\": target}:
+            print(target)
+        case {\"action\": \"stop\"}:
+            print(\"stop\")
+
+def status(done: bool, code: int):
+    match done:
+        case True:
+            print(1)
+        case False:
+            print(0)
+    match code:
+        case 200 | 201 as ok:
+            print(ok)
+        case -1:
+            print(code)
+        "};
+
+    let actual = test_p2r(code);
+    let expected = expect![[r#"
+        fn main() {
+            #[derive(Debug, Clone, PartialEq)]
+            struct Point {
+                x: isize,
+                y: isize,
+            }
+            #[derive(Debug, Clone)]
+            enum Color {
+                RED = 1,
+                GREEN = 2,
+                BLUE = 3,
+            }
+            fn quadrant(p: Point) -> isize {
+                match p {
+                    Point { x: 0, y: 0 } => return 0,
+                    Point { x: 0, y } if y > 0 => return 1,
+                    Point { x, y: 0 } => return x,
+                    Point { .. } => return -1,
+                }
+            }
+            fn warm(c: Color) -> bool {
+                match c {
+                    Color::RED | Color::GREEN => return true,
+                    Color::BLUE => return false,
+                }
+            }
+            fn head(xs: Vec<isize>) -> isize {
+                match xs.as_slice() {
+                    &[] => return 0,
+                    &[x] => return x,
+                    &[x, ref rest @ ..] if rest.len() > 2 => return x,
+                    &[first, _, ..] => return first,
+                }
+            }
+            fn pair(t: (isize, isize)) -> () {
+                match t {
+                    p @ (0, -1) => println!("{:?}", p),
+                    (a, b) => println!("{:?}", a + b),
+                }
+            }
+            fn command(c: std::collections::HashMap<String, String>) -> () {
+                match c {
+                    _ if c.get("action").is_some_and(|v| *v == "move")
+                        && c.contains_key("to") => {
+                        let target = &c["to"];
+                        println!("{:?}", target);
+                    }
+                    _ if c.get("action").is_some_and(|v| *v == "stop") => {
+                        println!("{:?}", "stop")
+                    }
+                    _ => {}
+                }
+            }
+            fn status(done: bool, code: isize) -> () {
+                match done {
+                    true => println!("{:?}", 1),
+                    false => println!("{:?}", 0),
+                }
+                match code {
+                    ok @ (200 | 201) => println!("{:?}", ok),
+                    -1 => println!("{:?}", code),
+                    _ => {}
+                }
+            }
+        }
+    "#]];
+    expected.assert_eq(&actual);
+}
+
+/// python node kind and location of the error
+fn test_p2r_err(code: &str) -> String {
+    let Err(crate::ParseError::TranspileError(e)) = crate::p2r(code, &mut crate::Ctx::default())
+    else {
+        panic!("expected a TranspileError")
+    };
+    let span = e.span.unwrap();
+    let mut err = format!(
+        "{} at {}:{}-{}:{}",
+        span.kind, span.start.line, span.start.column, span.end.line, span.end.column
+    );
+    if let Some(message) = e.message {
+        err.push_str(": ");
+        err.push_str(message);
+    }
+    err
 }
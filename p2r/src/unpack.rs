@@ -0,0 +1,347 @@
+//! unpacking: `*rest` in assignment targets, swaps, and `*args` and `**kwargs` which are spread
+//! into calls and literals
+//!
+//! the length of a list is only known at runtime, `first, *rest = xs` matches a slice pattern
+//! which copies (or clones) the elements and `f(*xs)` indexes `xs` once per parameter of `f`
+
+use crate::infer::{Kind, Param, Ty};
+use crate::scope::target_names;
+use crate::{
+    assign, call, index, method_call, pat_ident, path, r_e, stmt, todo_link, tuple, Ctx, TResult,
+    TranspileError,
+};
+use quote::ToTokens;
+use rustpython_parser::ast::{
+    Expr, ExprCall, ExprContext, ExprStarred, ExprSubscript, ExprTuple, Ranged,
+};
+use syn::parse_quote;
+
+/// elements of a tuple or list
+fn elements(e: &Expr) -> Option<&[Expr]> {
+    match e {
+        Expr::Tuple(t) => Some(&t.elts),
+        Expr::List(l) => Some(&l.elts),
+        _ => None,
+    }
+}
+
+/// `first, *rest = value` -> the target without the star, `(first, rest)`, and the value as a
+/// tuple of the same shape
+pub fn r_starred(target: &Expr, value: &Expr, ctx: &mut Ctx) -> Option<TResult<(Expr, syn::Expr)>> {
+    let elts = elements(target)?;
+    let star = elts.iter().position(Expr::is_starred_expr)?;
+    let target = Expr::Tuple(ExprTuple {
+        range: target.range(),
+        elts: elts
+            .iter()
+            .map(|e| match e {
+                Expr::Starred(s) => *s.value.clone(),
+                e => e.clone(),
+            })
+            .collect(),
+        ctx: ExprContext::Store,
+    });
+    Some(r_starred_value(elts, star, value, ctx).map(|v| (target, v)))
+}
+
+fn r_starred_value(elts: &[Expr], star: usize, value: &Expr, ctx: &mut Ctx) -> TResult<syn::Expr> {
+    let after = elts.len() - star - 1;
+
+    // the values of a literal are split while transpiling
+    if let Some(values) = elements(value)
+        && !values.iter().any(Expr::is_starred_expr)
+    {
+        if values.len() < elts.len() - 1 {
+            return Err(todo_link!("not enough values to unpack"));
+        }
+        let mut values = values
+            .iter()
+            .map(|v| r_e(v, ctx))
+            .collect::<TResult<Vec<_>>>()?;
+        let after = values.split_off(values.len() - after);
+        let rest = values.split_off(star);
+        values.push(parse_quote!(vec![#(#rest),*]));
+        values.extend(after);
+        return Ok(tuple(values));
+    }
+
+    let copy = match ctx.types.ty(value, &ctx.types.locals) {
+        Ty::List(t) => *t != Ty::Unknown && !t.is_owned(),
+        Ty::Unknown => false,
+        _ => {
+            return Err(todo_link!(
+                "only lists can be unpacked into a starred target"
+            ))
+        }
+    };
+    let mut pats: Vec<syn::Pat> = vec![];
+    let mut values: Vec<syn::Expr> = vec![];
+    for (i, e) in elts.iter().enumerate() {
+        let (name, starred) = match e {
+            Expr::Starred(ExprStarred { value, .. }) => (value.as_ref(), true),
+            e => (e, false),
+        };
+        let name = match name {
+            Expr::Name(n) => n.id.to_string(),
+            _ => format!("v{i}"),
+        };
+        if name == "_" {
+            pats.push(if starred {
+                parse_quote!(..)
+            } else {
+                parse_quote!(_)
+            });
+            values.push(parse_quote!(()));
+            continue;
+        }
+        let p = pat_ident(&name, false);
+        let v = path(&name);
+        match (starred, copy) {
+            (true, true) => pats.push(parse_quote!(ref #p @ ..)),
+            (true, false) => pats.push(parse_quote!(#p @ ..)),
+            (false, _) => pats.push(p),
+        }
+        values.push(match (starred, copy) {
+            (true, _) => method_call(v, "to_vec", []),
+            (false, true) => v,
+            (false, false) => method_call(v, "clone", []),
+        });
+    }
+
+    let value = method_call(r_e(value, ctx)?, "as_slice", []);
+    let pat: syn::Pat = match copy {
+        true => parse_quote!(&[#(#pats),*]),
+        false => parse_quote!([#(#pats),*]),
+    };
+    let values = tuple(values);
+    if elts.len() == 1 {
+        // `*rest, = value` matches every slice
+        return Ok(parse_quote!(match #value { #pat => #values }));
+    }
+    Ok(parse_quote!(match #value {
+        #pat => #values,
+        _ => panic!("not enough values to unpack"),
+    }))
+}
+
+/// `a[i], a[j] = a[j], a[i]` -> `a.swap(i, j)`, `x.a, x.b = x.b, x.a` -> `std::mem::swap`
+///
+/// the places can't be moved out of to build the tuple of values, names are swapped by a
+/// destructuring assignment
+pub fn r_swap(target: &Expr, value: &Expr, ctx: &mut Ctx) -> TResult<Option<syn::Expr>> {
+    let (Some([a, b]), Some([c, d])) = (elements(target), elements(value)) else {
+        return Ok(None);
+    };
+    if a.is_name_expr() && b.is_name_expr() {
+        return Ok(None);
+    }
+    let (pa, pb) = (r_e(a, ctx)?, r_e(b, ctx)?);
+    if !same(&pa, &r_e(d, ctx)?) || !same(&pb, &r_e(c, ctx)?) {
+        return Ok(None);
+    }
+    if let (
+        Expr::Subscript(ExprSubscript {
+            value: list,
+            slice: i,
+            ..
+        }),
+        Expr::Subscript(ExprSubscript {
+            value: other,
+            slice: j,
+            ..
+        }),
+    ) = (a, b)
+        && same(&r_e(list, ctx)?, &r_e(other, ctx)?)
+        && matches!(
+            ctx.types.ty(list, &ctx.types.locals),
+            Ty::List(_) | Ty::Unknown
+        )
+    {
+        let (i, j) = (r_e(i, ctx)?, r_e(j, ctx)?);
+        return Ok(Some(method_call(r_e(list, ctx)?, "swap", [i, j])));
+    }
+    Ok(Some(call(
+        parse_quote!(std::mem::swap),
+        [parse_quote!(&mut #pa), parse_quote!(&mut #pb)],
+    )))
+}
+
+fn same(a: &syn::Expr, b: &syn::Expr) -> bool {
+    a.to_token_stream().to_string() == b.to_token_stream().to_string()
+}
+
+/// a tuple target with places (e.g. `self.x, y = ...`) or names which are already bound, the
+/// new names are declared before the destructuring assignment
+pub fn r_places(target: &Expr, v: syn::Expr, ctx: &mut Ctx) -> Option<TResult<Vec<syn::Stmt>>> {
+    elements(target)?;
+    let mut names = vec![];
+    target_names(target, &mut names);
+    if only_names(target) && !names.iter().any(|n| ctx.scopes.is_declared(n)) {
+        return None;
+    }
+
+    let mut stmts: Vec<syn::Stmt> = names
+        .iter()
+        .filter(|n| **n != "_" && !ctx.scopes.is_declared(n))
+        .map(|n| {
+            let n = pat_ident(n, ctx.scopes.is_mutated(n));
+            parse_quote!(let #n;)
+        })
+        .collect();
+    ctx.scopes.declare(target);
+    Some(r_place(target, ctx).map(|t| {
+        stmts.push(stmt(assign(t, v)));
+        stmts
+    }))
+}
+
+/// the target is a tuple of (new) names, which `let` binds
+fn only_names(target: &Expr) -> bool {
+    match target {
+        Expr::Name(_) => true,
+        e => elements(e).is_some_and(|elts| elts.iter().all(only_names)),
+    }
+}
+
+/// assignee expression of a destructuring assignment
+fn r_place(target: &Expr, ctx: &mut Ctx) -> TResult<syn::Expr> {
+    match elements(target) {
+        Some(elts) => Ok(tuple(
+            elts.iter()
+                .map(|e| r_place(e, ctx))
+                .collect::<TResult<Vec<_>>>()?,
+        )),
+        None if target.as_name_expr().is_some_and(|n| n.id.as_str() == "_") => Ok(parse_quote!(_)),
+        None => r_e(target, ctx),
+    }
+}
+
+/// arguments of a call, `f(a, *xs)` -> `f(a, xs[0], xs[1])` if `f` has three parameters
+pub fn r_args(node: &ExprCall, ctx: &mut Ctx) -> TResult<Vec<syn::Expr>> {
+    let ExprCall {
+        func,
+        args,
+        keywords,
+        ..
+    } = node;
+    let mut values = vec![];
+    for arg in args {
+        let Expr::Starred(ExprStarred { value, .. }) = arg else {
+            values.push(r_e(arg, ctx)?);
+            continue;
+        };
+        // `f(*(a, b))`
+        if let Some(elts) = elements(value) {
+            for e in elts {
+                values.push(r_e(e, ctx)?);
+            }
+            continue;
+        }
+
+        let ty = ctx.types.ty(value, &ctx.types.locals);
+        let v = r_e(value, ctx)?;
+        let clone = |e: syn::Expr, t: &Ty| match t.is_owned() {
+            true => method_call(e, "clone", []),
+            false => e,
+        };
+        match &ty {
+            Ty::Tuple(ts) => {
+                for (i, t) in ts.iter().enumerate() {
+                    let i = syn::Index::from(i);
+                    values.push(clone(parse_quote!(#v.#i), t));
+                }
+            }
+            Ty::List(t) => {
+                let sig = func
+                    .as_name_expr()
+                    .and_then(|f| ctx.types.functions.get(f.id.as_str()))
+                    .ok_or(todo_link!(
+                        "`*args` can only be spread into functions with a known number of parameters"
+                    ))?;
+                // the parameters without default values
+                let required = |p: &&Param| p.kind == Kind::Positional;
+                let params = sig.params[..sig.positional].iter().filter(required).count();
+                let others = args.len() - 1
+                    + keywords
+                        .iter()
+                        .filter(|k| {
+                            sig.params[..sig.positional]
+                                .iter()
+                                .filter(required)
+                                .any(|p| k.arg.as_deref() == Some(p.name.as_str()))
+                        })
+                        .count();
+                if args.iter().filter(|a| a.is_starred_expr()).count() > 1 || params < others {
+                    return Err(todo_link!());
+                }
+                for i in 0..params - others {
+                    let i = proc_macro2::Literal::usize_unsuffixed(i);
+                    values.push(clone(index(v.clone(), parse_quote!(#i)), t));
+                }
+            }
+            _ => {
+                return Err(todo_link!(
+                    "`*args` can only be spread into functions with a known number of parameters"
+                ))
+            }
+        }
+    }
+    Ok(values)
+}
+
+/// `[*a, 1, *b]` -> `[&a[..], &[1][..], &b[..]].concat()`
+pub fn r_list(elts: &[Expr], ctx: &mut Ctx) -> TResult<syn::Expr> {
+    let mut slices: Vec<syn::Expr> = vec![];
+    let mut values = vec![];
+    for e in elts {
+        let Expr::Starred(ExprStarred { value, .. }) = e else {
+            values.push(r_e(e, ctx)?);
+            continue;
+        };
+        if !matches!(
+            ctx.types.ty(value, &ctx.types.locals),
+            Ty::List(_) | Ty::Unknown
+        ) {
+            return Err(todo_link!("only lists can be unpacked into a list"));
+        }
+        if !values.is_empty() {
+            slices.push(parse_quote!(&[#(#values),*][..]));
+            values.clear();
+        }
+        let v = r_e(value, ctx)?;
+        slices.push(parse_quote!(&#v[..]));
+    }
+    if !values.is_empty() {
+        slices.push(parse_quote!(&[#(#values),*][..]));
+    }
+    Ok(method_call(parse_quote!([#(#slices),*]), "concat", []))
+}
+
+/// `{**a, "k": 1}` -> `a.clone().into_iter().chain([("k", 1)]).collect::<HashMap<_, _>>()`,
+/// later keys replace earlier ones like in python
+pub fn r_dict(keys: &[Option<Expr>], values: &[Expr], ctx: &mut Ctx) -> TResult<syn::Expr> {
+    let mut parts: Vec<syn::Expr> = vec![];
+    let mut pairs = vec![];
+    for (k, v) in keys.iter().zip(values) {
+        let v = r_e(v, ctx)?;
+        let Some(k) = k else {
+            if !pairs.is_empty() {
+                parts.push(parse_quote!([#(#pairs),*]));
+                pairs.clear();
+            }
+            parts.push(method_call(v, "clone", []));
+            continue;
+        };
+        pairs.push(tuple(vec![r_e(k, ctx)?, v]));
+    }
+    if !pairs.is_empty() {
+        parts.push(parse_quote!([#(#pairs),*]));
+    }
+
+    let mut parts = parts.into_iter();
+    let first = parts.next().ok_or(todo_link!())?;
+    let iter = parts.fold(method_call(first, "into_iter", []), |iter, p| {
+        method_call(iter, "chain", [p])
+    });
+    Ok(parse_quote!(#iter.collect::<HashMap<_, _>>()))
+}
@@ -1,51 +1,413 @@
-pub struct Padded<I>
-where
-    I: Iterator,
-    I::Item: Clone,
-{
-    iter: std::iter::Peekable<I>,
-    separator: I::Item,
-    yield_seperator: Option<I::Item>,
-}
-
-impl<I> Padded<I>
-where
-    I: Iterator,
-    I::Item: Clone,
-{
-    fn new(iter: I, separator: I::Item) -> Self {
-        Self {
-            iter: iter.peekable(),
-            separator,
-            yield_seperator: None,
+//! helpers to build rust syntax nodes without going through strings
+
+use proc_macro2::Span;
+use syn::{parse_quote, punctuated::Punctuated};
+
+/// python names which are keywords in rust are turned into raw identifiers
+pub fn ident(name: &str) -> syn::Ident {
+    match name {
+        "self" | "Self" | "super" | "crate" => syn::Ident::new(name, Span::call_site()),
+        _ if syn::parse_str::<syn::Ident>(name).is_err() => {
+            syn::Ident::new_raw(name, Span::call_site())
         }
+        _ => syn::Ident::new(name, Span::call_site()),
     }
 }
 
-impl<I> Iterator for Padded<I>
-where
-    I: Iterator,
-    I::Item: Clone,
-{
-    type Item = I::Item;
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.yield_seperator.is_some() {
-            self.yield_seperator.take()
-        } else {
-            self.yield_seperator = Some(self.separator.clone());
-            self.iter.next()
-        }
+pub fn path(name: &str) -> syn::Expr {
+    syn::Expr::Path(syn::ExprPath {
+        attrs: vec![],
+        qself: None,
+        path: ident(name).into(),
+    })
+}
+
+pub fn paren(e: syn::Expr) -> syn::Expr {
+    syn::Expr::Paren(syn::ExprParen {
+        attrs: vec![],
+        paren_token: Default::default(),
+        expr: Box::new(e),
+    })
+}
+
+/// binding power of an expression in rust, higher binds tighter
+///
+/// https://doc.rust-lang.org/reference/expressions.html#expression-precedence
+fn precedence(e: &syn::Expr) -> u8 {
+    match e {
+        syn::Expr::Closure(_)
+        | syn::Expr::Let(_)
+        | syn::Expr::Return(_)
+        | syn::Expr::Break(_)
+        | syn::Expr::Yield(_) => 0,
+        syn::Expr::Assign(_) => 1,
+        syn::Expr::Range(_) => 2,
+        syn::Expr::Binary(b) => bin_precedence(&b.op),
+        syn::Expr::Cast(_) => 12,
+        syn::Expr::Unary(_) | syn::Expr::Reference(_) => 13,
+        syn::Expr::MethodCall(_)
+        | syn::Expr::Field(_)
+        | syn::Expr::Call(_)
+        | syn::Expr::Index(_)
+        | syn::Expr::Try(_)
+        | syn::Expr::Await(_) => 14,
+        _ => 15,
+    }
+}
+
+fn bin_precedence(op: &syn::BinOp) -> u8 {
+    use syn::BinOp::*;
+    match op {
+        Or(_) => 3,
+        And(_) => 4,
+        Eq(_) | Ne(_) | Lt(_) | Le(_) | Gt(_) | Ge(_) => 5,
+        BitOr(_) => 6,
+        BitXor(_) => 7,
+        BitAnd(_) => 8,
+        Shl(_) | Shr(_) => 9,
+        Add(_) | Sub(_) => 10,
+        Mul(_) | Div(_) | Rem(_) => 11,
+        // compound assignment
+        _ => 1,
+    }
+}
+
+/// wraps `e` in parentheses if it binds weaker than `min`
+fn wrap(e: syn::Expr, min: u8) -> syn::Expr {
+    if precedence(&e) < min {
+        paren(e)
+    } else {
+        e
+    }
+}
+
+/// like `wrap` but for the leftmost operand, where block like expressions
+/// would end the statement, e.g. `if a { b } else { c } + 1`
+fn wrap_lhs(e: syn::Expr, min: u8) -> syn::Expr {
+    match e {
+        syn::Expr::If(_)
+        | syn::Expr::Match(_)
+        | syn::Expr::Block(_)
+        | syn::Expr::Loop(_)
+        | syn::Expr::While(_)
+        | syn::Expr::ForLoop(_) => paren(e),
+        e => wrap(e, min),
+    }
+}
+
+pub fn binary(left: syn::Expr, op: syn::BinOp, right: syn::Expr) -> syn::Expr {
+    let p = bin_precedence(&op);
+    let (left, right) = match op {
+        // comparisons are not associative in rust
+        syn::BinOp::Eq(_)
+        | syn::BinOp::Ne(_)
+        | syn::BinOp::Lt(_)
+        | syn::BinOp::Le(_)
+        | syn::BinOp::Gt(_)
+        | syn::BinOp::Ge(_) => (wrap_lhs(left, p + 1), wrap(right, p + 1)),
+        _ if p == 1 => (wrap_lhs(left, 14), wrap(right, p)),
+        _ => (wrap_lhs(left, p), wrap(right, p + 1)),
+    };
+
+    // `x as usize < y` would parse `usize<` as the start of generic arguments
+    let left = match (left, &op) {
+        (left @ syn::Expr::Cast(_), syn::BinOp::Lt(_) | syn::BinOp::Shl(_)) => paren(left),
+        (left, _) => left,
+    };
+
+    syn::Expr::Binary(syn::ExprBinary {
+        attrs: vec![],
+        left: Box::new(left),
+        op,
+        right: Box::new(right),
+    })
+}
+
+/// `left = right`
+pub fn assign(left: syn::Expr, right: syn::Expr) -> syn::Expr {
+    syn::Expr::Assign(syn::ExprAssign {
+        attrs: vec![],
+        left: Box::new(wrap_lhs(left, 14)),
+        eq_token: Default::default(),
+        right: Box::new(right),
+    })
+}
+
+pub fn unary(op: syn::UnOp, e: syn::Expr) -> syn::Expr {
+    syn::Expr::Unary(syn::ExprUnary {
+        attrs: vec![],
+        op,
+        expr: Box::new(wrap(e, 13)),
+    })
+}
+
+/// `&e`
+pub fn reference(e: syn::Expr) -> syn::Expr {
+    syn::Expr::Reference(syn::ExprReference {
+        attrs: vec![],
+        and_token: Default::default(),
+        mutability: None,
+        expr: Box::new(wrap(e, 13)),
+    })
+}
+
+/// `e as t`
+pub fn cast(e: syn::Expr, t: syn::Type) -> syn::Expr {
+    syn::Expr::Cast(syn::ExprCast {
+        attrs: vec![],
+        expr: Box::new(wrap_lhs(e, 12)),
+        as_token: Default::default(),
+        ty: Box::new(t),
+    })
+}
+
+/// `e.member`
+pub fn field(e: syn::Expr, member: &str) -> syn::Expr {
+    syn::Expr::Field(syn::ExprField {
+        attrs: vec![],
+        base: Box::new(wrap_lhs(e, 14)),
+        dot_token: Default::default(),
+        member: syn::Member::Named(ident(member)),
+    })
+}
+
+/// `e[i]`
+pub fn index(e: syn::Expr, i: syn::Expr) -> syn::Expr {
+    syn::Expr::Index(syn::ExprIndex {
+        attrs: vec![],
+        expr: Box::new(wrap_lhs(e, 14)),
+        bracket_token: Default::default(),
+        index: Box::new(i),
+    })
+}
+
+pub fn call(func: syn::Expr, args: impl IntoIterator<Item = syn::Expr>) -> syn::Expr {
+    syn::Expr::Call(syn::ExprCall {
+        attrs: vec![],
+        func: Box::new(wrap_lhs(func, 14)),
+        paren_token: Default::default(),
+        args: args.into_iter().collect(),
+    })
+}
+
+pub fn method_call(
+    receiver: syn::Expr,
+    method: &str,
+    args: impl IntoIterator<Item = syn::Expr>,
+) -> syn::Expr {
+    syn::Expr::MethodCall(syn::ExprMethodCall {
+        attrs: vec![],
+        receiver: Box::new(wrap_lhs(receiver, 14)),
+        dot_token: Default::default(),
+        method: ident(method),
+        turbofish: None,
+        paren_token: Default::default(),
+        args: args.into_iter().collect(),
+    })
+}
+
+/// `e.await`
+pub fn awaited(e: syn::Expr) -> syn::Expr {
+    syn::Expr::Await(syn::ExprAwait {
+        attrs: vec![],
+        base: Box::new(wrap_lhs(e, 14)),
+        dot_token: Default::default(),
+        await_token: Default::default(),
+    })
+}
+
+/// `receiver.collect::<T>()`
+pub fn collect(receiver: syn::Expr, t: syn::Type) -> syn::Expr {
+    let mut c = method_call(receiver, "collect", []);
+    if let syn::Expr::MethodCall(c) = &mut c {
+        c.turbofish = Some(parse_quote!(::<#t>));
     }
+    c
+}
+
+pub fn closure(inputs: Vec<syn::Pat>, body: syn::Expr) -> syn::Expr {
+    syn::Expr::Closure(syn::ExprClosure {
+        attrs: vec![],
+        lifetimes: None,
+        constness: None,
+        movability: None,
+        asyncness: None,
+        capture: None,
+        or1_token: Default::default(),
+        inputs: inputs.into_iter().collect(),
+        or2_token: Default::default(),
+        output: syn::ReturnType::Default,
+        body: Box::new(body),
+    })
 }
 
-pub trait PaddedT: Iterator {
-    fn padded(self, separator: Self::Item) -> Padded<Self>
-    where
-        Self: Sized,
-        Self::Item: Clone,
-    {
-        Padded::new(self, separator)
+pub fn tuple(elems: Vec<syn::Expr>) -> syn::Expr {
+    let mut elems = elems.into_iter().collect::<Punctuated<_, _>>();
+    if elems.len() == 1 {
+        // `(a,)` is a tuple, `(a)` is not
+        elems.push_punct(Default::default());
     }
+    syn::Expr::Tuple(syn::ExprTuple {
+        attrs: vec![],
+        paren_token: Default::default(),
+        elems,
+    })
+}
+
+pub fn range(start: Option<syn::Expr>, end: Option<syn::Expr>) -> syn::Expr {
+    syn::Expr::Range(syn::ExprRange {
+        attrs: vec![],
+        start: start.map(|e| Box::new(wrap_lhs(e, 3))),
+        limits: syn::RangeLimits::HalfOpen(Default::default()),
+        end: end.map(|e| Box::new(wrap(e, 3))),
+    })
+}
+
+pub fn block(stmts: Vec<syn::Stmt>) -> syn::Block {
+    syn::Block {
+        brace_token: Default::default(),
+        stmts,
+    }
+}
+
+pub fn block_expr(stmts: Vec<syn::Stmt>) -> syn::Expr {
+    syn::Expr::Block(syn::ExprBlock {
+        attrs: vec![],
+        label: None,
+        block: block(stmts),
+    })
+}
+
+/// `{ e }`
+pub fn tail_block(e: syn::Expr) -> syn::Expr {
+    block_expr(vec![syn::Stmt::Expr(e, None)])
+}
+
+pub fn if_expr(cond: syn::Expr, then: Vec<syn::Stmt>, orelse: Option<syn::Expr>) -> syn::Expr {
+    syn::Expr::If(syn::ExprIf {
+        attrs: vec![],
+        if_token: Default::default(),
+        cond: Box::new(cond),
+        then_branch: block(then),
+        else_branch: orelse.map(|e| (Default::default(), Box::new(e))),
+    })
+}
+
+/// statement with a trailing semicolon, unless it is block like (`if`, `for`, ...)
+pub fn stmt(e: syn::Expr) -> syn::Stmt {
+    let semi = match e {
+        syn::Expr::If(_)
+        | syn::Expr::While(_)
+        | syn::Expr::ForLoop(_)
+        | syn::Expr::Loop(_)
+        | syn::Expr::Match(_)
+        | syn::Expr::Block(_) => None,
+        _ => Some(Default::default()),
+    };
+    syn::Stmt::Expr(e, semi)
 }
 
-impl<I> PaddedT for I where I: Iterator {}
+pub fn pat_ident(name: &str, mutable: bool) -> syn::Pat {
+    if name == "_" {
+        return parse_quote!(_);
+    }
+    syn::Pat::Ident(syn::PatIdent {
+        attrs: vec![],
+        by_ref: None,
+        mutability: mutable.then(Default::default),
+        ident: ident(name),
+        subpat: None,
+    })
+}
+
+pub fn pat_tuple(elems: Vec<syn::Pat>) -> syn::Pat {
+    let mut elems = elems.into_iter().collect::<Punctuated<_, _>>();
+    if elems.len() == 1 {
+        elems.push_punct(Default::default());
+    }
+    syn::Pat::Tuple(syn::PatTuple {
+        attrs: vec![],
+        paren_token: Default::default(),
+        elems,
+    })
+}
+
+/// `#[doc = "..."]` for each line of a python docstring, dedented like `inspect.cleandoc`
+pub fn doc_attrs(doc: &str) -> Vec<syn::Attribute> {
+    let mut lines = doc.lines();
+    let first = lines.next().unwrap_or_default().trim();
+    let rest = lines.collect::<Vec<_>>();
+    let indent = rest
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.len() - l.trim_start().len())
+        .min()
+        .unwrap_or(0);
+
+    let mut lines = std::iter::once(first)
+        .chain(
+            rest.iter()
+                .map(|l| l.get(indent..).unwrap_or_default().trim_end()),
+        )
+        .collect::<Vec<_>>();
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+    while lines.first().is_some_and(|l| l.is_empty()) {
+        lines.remove(0);
+    }
+
+    lines
+        .into_iter()
+        .map(|l| {
+            let l = if l.is_empty() {
+                String::new()
+            } else {
+                format!(" {l}")
+            };
+            parse_quote!(#[doc = #l])
+        })
+        .collect()
+}
+
+/// one `#[p2r_comment = "..."]` per line, which `crate::fmt` turns into `// ...`
+pub fn comment_attrs<'a>(lines: impl IntoIterator<Item = &'a str>) -> Vec<syn::Attribute> {
+    lines
+        .into_iter()
+        .map(|l| parse_quote!(#[p2r_comment = #l]))
+        .collect()
+}
+
+/// empty item which only carries comments, `None` if there are no comments
+pub fn comment_item<'a>(lines: impl IntoIterator<Item = &'a str>) -> Option<syn::Item> {
+    let attrs = comment_attrs(lines);
+    if attrs.is_empty() {
+        return None;
+    }
+    Some(parse_quote! {
+        #(#attrs)*
+        p2r_comments!();
+    })
+}
+
+/// attributes of an item, e.g. to attach comments to it
+pub fn item_attrs(item: &mut syn::Item) -> Option<&mut Vec<syn::Attribute>> {
+    Some(match item {
+        syn::Item::Const(i) => &mut i.attrs,
+        syn::Item::Enum(i) => &mut i.attrs,
+        syn::Item::ExternCrate(i) => &mut i.attrs,
+        syn::Item::Fn(i) => &mut i.attrs,
+        syn::Item::ForeignMod(i) => &mut i.attrs,
+        syn::Item::Impl(i) => &mut i.attrs,
+        syn::Item::Macro(i) => &mut i.attrs,
+        syn::Item::Mod(i) => &mut i.attrs,
+        syn::Item::Static(i) => &mut i.attrs,
+        syn::Item::Struct(i) => &mut i.attrs,
+        syn::Item::Trait(i) => &mut i.attrs,
+        syn::Item::TraitAlias(i) => &mut i.attrs,
+        syn::Item::Type(i) => &mut i.attrs,
+        syn::Item::Union(i) => &mut i.attrs,
+        syn::Item::Use(i) => &mut i.attrs,
+        _ => return None,
+    })
+}
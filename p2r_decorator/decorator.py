@@ -74,7 +74,6 @@ def rust_decorator(func):
 
 use numpy::IntoPyArray;
 
-#[pyo3::pyfunction]
 {source}
 #[pyo3::pymodule]
 fn {mangled_name}(_py: pyo3::Python<'_>, m: &pyo3::types::PyModule) -> pyo3::PyResult<()> {{
@@ -1,8 +1,19 @@
 #[pyo3::pymodule]
 fn p2r_decorator(_py: pyo3::Python<'_>, m: &pyo3::types::PyModule) -> pyo3::PyResult<()> {
     #[pyfn(m)]
-    fn rust(code: String) -> String {
-        p2r::p2r(&code, &mut p2r::Ctx::default()).unwrap()
+    fn rust(code: String) -> pyo3::PyResult<String> {
+        match p2r::p2r_module(&code, &mut p2r::Ctx::default()) {
+            Ok(mut file) => {
+                p2r::pyfunctions(&mut file);
+                Ok(p2r::fmt(&file))
+            }
+            Err(p2r::ParseError::TranspileError(e)) => Err(
+                pyo3::exceptions::PyNotImplementedError::new_err(e.report(&code)),
+            ),
+            Err(p2r::ParseError::ParseError(e)) => {
+                Err(pyo3::exceptions::PySyntaxError::new_err(e.to_string()))
+            }
+        }
     }
 
     Ok(())
@@ -15,6 +15,13 @@ pub struct Res {
     file: Option<String>,
     line: Option<u32>,
 
+    /// python node which could not be transpiled
+    kind: Option<String>,
+    py_line: Option<u32>,
+    py_column: Option<u32>,
+    py_end_line: Option<u32>,
+    py_end_column: Option<u32>,
+
     parse_error: Option<String>,
 }
 
@@ -43,6 +50,32 @@ impl Res {
         self.line
     }
 
+    #[wasm_bindgen(getter)]
+    #[must_use]
+    pub fn kind(&self) -> Option<String> {
+        self.kind.clone()
+    }
+    #[wasm_bindgen(getter)]
+    #[must_use]
+    pub fn py_line(&self) -> Option<u32> {
+        self.py_line
+    }
+    #[wasm_bindgen(getter)]
+    #[must_use]
+    pub fn py_column(&self) -> Option<u32> {
+        self.py_column
+    }
+    #[wasm_bindgen(getter)]
+    #[must_use]
+    pub fn py_end_line(&self) -> Option<u32> {
+        self.py_end_line
+    }
+    #[wasm_bindgen(getter)]
+    #[must_use]
+    pub fn py_end_column(&self) -> Option<u32> {
+        self.py_end_column
+    }
+
     #[wasm_bindgen(getter)]
     #[must_use]
     pub fn parse_error(&self) -> Option<String> {
@@ -59,14 +92,19 @@ pub fn greet(name: &str) {
 #[must_use]
 pub fn p2r(content: &str) -> Res {
     let mut ctx = p2r::Ctx::default();
-    let res = p2r::p2r(content, &mut ctx);
+    let res = p2r::p2r_module(content, &mut ctx);
 
     match res {
         Ok(code) => Res {
             t: "ok".to_string(),
-            code: Some(p2r::fmt(&format!("fn main(){{{code}}}"))),
+            code: Some(p2r::fmt(&code)),
             file: None,
             line: None,
+            kind: None,
+            py_line: None,
+            py_column: None,
+            py_end_line: None,
+            py_end_column: None,
             parse_error: None,
         },
         Err(p2r::ParseError::TranspileError(e)) => Res {
@@ -74,6 +112,11 @@ pub fn p2r(content: &str) -> Res {
             code: None,
             file: Some(e.file.to_string()),
             line: Some(e.line),
+            kind: e.span.as_ref().map(|s| s.kind.to_string()),
+            py_line: e.span.as_ref().map(|s| s.start.line),
+            py_column: e.span.as_ref().map(|s| s.start.column),
+            py_end_line: e.span.as_ref().map(|s| s.end.line),
+            py_end_column: e.span.as_ref().map(|s| s.end.column),
             parse_error: None,
         },
         Err(p2r::ParseError::ParseError(e)) => Res {
@@ -81,6 +124,11 @@ pub fn p2r(content: &str) -> Res {
             code: None,
             file: None,
             line: None,
+            kind: None,
+            py_line: None,
+            py_column: None,
+            py_end_line: None,
+            py_end_column: None,
             parse_error: Some(e.to_string()),
         },
     }